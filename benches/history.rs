@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use blescan::{discover::DiscoveryEvent, signature::Signature, history::{EventSink, EventSource, sqllite::{SQLLiteEventSink, SQLLiteEventSource}}};
+use chrono::{Utc, TimeZone};
+use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId};
+use futures::TryStreamExt;
+use sqlx::sqlite::SqlitePoolOptions;
+use tokio::runtime::Runtime;
+
+const EVENTS : i64 = 100_000;
+
+async fn populated_source() -> SQLLiteEventSource {
+    let pool = Arc::new(SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap());
+    let mut sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap();
+    let events : Vec<DiscoveryEvent> = (0..EVENTS).map(|i| {
+        DiscoveryEvent::new(
+            Utc.timestamp_opt(i, 0).unwrap(), 
+            Signature::Anonymous(format!("{:x}", md5::compute(i.to_le_bytes()))), 
+            -50)
+    }).collect();
+    for chunk in events.chunks(10_000) {
+        sink.save(chunk).await.unwrap();
+    }
+    SQLLiteEventSource::create_from_pool(pool)
+}
+
+fn stream_events(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let source = runtime.block_on(populated_source());
+    let range = Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(EVENTS, 0).unwrap();
+
+    let mut group = c.benchmark_group("stream_events");
+    group.sample_size(10);
+    for chunk_size in [100, 1_000, 10_000] {
+        group.bench_with_input(BenchmarkId::from_parameter(chunk_size), &chunk_size, |b, &chunk_size| {
+            b.to_async(&runtime).iter(|| async {
+                source.stream_events(range.clone(), chunk_size)
+                    .try_fold(0, |count, chunk| async move { Ok(count + chunk.len()) })
+                    .await
+                    .unwrap()
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, stream_events);
+criterion_main!(benches);
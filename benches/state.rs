@@ -0,0 +1,89 @@
+use blescan::{discover::DiscoveryEvent, signature::Signature, snapshot::Snapshot, state::State};
+use chrono::{Duration, Utc, TimeZone};
+use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId};
+
+const DEVICE_COUNTS : [usize; 3] = [1_000, 5_000, 10_000];
+
+/// One event for each of `devices` devices, spread over a minute and a range
+/// of signal strengths, as seen `seconds` in
+fn events(devices: usize, seconds: i64) -> Vec<DiscoveryEvent> {
+    (0..devices).map(|i| {
+        DiscoveryEvent::new(
+            Utc.timestamp_opt(seconds + (i % 60) as i64, 0).unwrap(),
+            Signature::Anonymous(format!("{:x}", md5::compute(i.to_le_bytes()))),
+            -40 - (i % 60) as i16)
+    }).collect()
+}
+
+fn state(devices: usize, seconds: i64) -> State {
+    let mut state = State::default();
+    state.discover(&events(devices, seconds));
+    state
+}
+
+fn discover(c: &mut Criterion) {
+    let mut group = c.benchmark_group("discover");
+    for devices in DEVICE_COUNTS {
+        let next = events(devices, 60);
+        group.bench_with_input(BenchmarkId::new("new", devices), &devices, |b, _| {
+            b.iter_batched(State::default, |mut state| state.discover(&next), criterion::BatchSize::LargeInput);
+        });
+        group.bench_with_input(BenchmarkId::new("known", devices), &devices, |b, &devices| {
+            b.iter_batched(|| state(devices, 0), |mut state| state.discover(&next), criterion::BatchSize::LargeInput);
+        });
+    }
+    group.finish();
+}
+
+fn snapshot(c: &mut Criterion) {
+    let mut group = c.benchmark_group("snapshot");
+    for devices in DEVICE_COUNTS {
+        let state = state(devices, 0);
+        group.bench_with_input(BenchmarkId::from_parameter(devices), &devices, |b, _| {
+            b.iter(|| state.snapshot());
+        });
+    }
+    group.finish();
+}
+
+fn order_by_age_and_volume(c: &mut Criterion) {
+    let mut group = c.benchmark_group("order_by_age_and_volume");
+    for devices in DEVICE_COUNTS {
+        let snapshot = state(devices, 0).snapshot();
+        group.bench_with_input(BenchmarkId::from_parameter(devices), &devices, |b, _| {
+            b.iter(|| snapshot.order_by_age_and_volume());
+        });
+    }
+    group.finish();
+}
+
+fn compared_to(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compared_to");
+    for devices in DEVICE_COUNTS {
+        // half of the devices seen before, and half new
+        let previous = state(devices / 2, 0).snapshot();
+        let current = state(devices, 60).snapshot();
+        let now = Utc.timestamp_opt(120, 0).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(devices), &devices, |b, _| {
+            b.iter(|| current.compared_to(now, &previous));
+        });
+    }
+    group.finish();
+}
+
+/// What the TUI does for each frame: snapshot, order and compare
+fn frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    for devices in DEVICE_COUNTS {
+        let state = state(devices, 0);
+        let previous : Snapshot = state.snapshot();
+        let now = Utc.timestamp_opt(0, 0).unwrap() + Duration::minutes(2);
+        group.bench_with_input(BenchmarkId::from_parameter(devices), &devices, |b, _| {
+            b.iter(|| state.snapshot().order_by_age_and_volume().compared_to(now, &previous));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, discover, snapshot, order_by_age_and_volume, compared_to, frame);
+criterion_main!(benches);
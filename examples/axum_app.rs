@@ -0,0 +1,90 @@
+//! Embedding the scanner in an axum app: scan in the background, and serve
+//! the latest snapshot as JSON.
+//!
+//!     cargo run --example axum_app
+//!     curl localhost:3000/devices
+
+use std::sync::{Arc, Mutex};
+
+use axum::{extract, routing::get, Json, Router};
+use blescan::{discover_btleplug::Scanner, error::Error, state::State};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+type SharedState = Arc<Mutex<State>>;
+
+#[derive(Serialize, PartialEq, Debug)]
+struct Device {
+    signature: String,
+    rssi: i16,
+    last_seen: DateTime<Utc>
+}
+
+fn app(state: SharedState) -> Router {
+    Router::new()
+        .route("/devices", get(devices))
+        .with_state(state)
+}
+
+async fn devices(extract::State(state): extract::State<SharedState>) -> Json<Vec<Device>> {
+    let snapshot = state.lock().unwrap().snapshot().order_by_age_and_volume();
+    Json(snapshot.0.into_iter().map(|d| Device {
+        signature: d.signature.value().to_string(),
+        rssi: d.rssi,
+        last_seen: d.date_time
+    }).collect())
+}
+
+async fn scan(state: SharedState) -> Result<(), Error> {
+    let mut scanner = Scanner::new().await?;
+    loop {
+        let events = scanner.scan().await?;
+        state.lock().unwrap().discover(&events);
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let state = SharedState::default();
+    let scanning = state.clone();
+    tokio::spawn(async move {
+        match scan(scanning).await {
+            Err(Error::NoAdapters) => eprintln!("no Bluetooth adapter, so no devices will be found"),
+            Err(e) => eprintln!("scanning stopped: {e}"),
+            Ok(()) => {}
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await?;
+    println!("serving on http://{}/devices", listener.local_addr()?);
+    axum::serve(listener, app(state)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{self, Body}, http::{Request, StatusCode}};
+    use blescan::{discover::DiscoveryEvent, signature::Signature};
+    use chrono::{Utc, TimeZone};
+    use tower::ServiceExt;
+
+    use super::{app, SharedState};
+
+    #[tokio::test]
+    async fn serves_discovered_devices() {
+        let state = SharedState::default();
+        state.lock().unwrap().discover(&[
+            DiscoveryEvent::new(Utc.timestamp_opt(0, 0).unwrap(), Signature::Named("Device 1".to_string()), -60),
+            DiscoveryEvent::new(Utc.timestamp_opt(1, 0).unwrap(), Signature::Named("Device 2".to_string()), -50),
+        ]);
+
+        let response = app(state)
+            .oneshot(Request::get("/devices").body(Body::empty()).unwrap())
+            .await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(std::str::from_utf8(&body).unwrap(), concat!(
+            "[{\"signature\":\"Device 2\",\"rssi\":-50,\"last_seen\":\"1970-01-01T00:00:01Z\"},",
+            "{\"signature\":\"Device 1\",\"rssi\":-60,\"last_seen\":\"1970-01-01T00:00:00Z\"}]"
+        ));
+    }
+}
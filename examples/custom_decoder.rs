@@ -0,0 +1,94 @@
+//! Building a custom decoder: recognise iBeacon advertisements, and give
+//! them a signature made from their UUID, major and minor numbers, rather than
+//! a hash of the manufacturer data. Anything else is left to `Signature::find`.
+//!
+//!     cargo run --example custom_decoder
+
+use std::time::Duration;
+
+use blescan::{discover::DiscoveryEvent, signature::Signature, state::State};
+use btleplug::{api::{Central, Manager as _, Peripheral, PeripheralProperties, ScanFilter}, platform::Manager};
+use chrono::{DateTime, Utc};
+
+const APPLE : u16 = 0x004C;
+
+/// The UUID, major and minor numbers from an iBeacon advertisement
+fn ibeacon(properties: &PeripheralProperties) -> Option<(String, u16, u16)> {
+    let data = properties.manufacturer_data.get(&APPLE)?;
+    if data.len() != 23 || data[0..2] != [0x02, 0x15] {
+        return None;
+    }
+    let uuid : String = data[2..18].iter().map(|b| format!("{b:02x}")).collect();
+    let major = u16::from_be_bytes([data[18], data[19]]);
+    let minor = u16::from_be_bytes([data[20], data[21]]);
+    Some((uuid, major, minor))
+}
+
+fn decode(properties: &PeripheralProperties, date_time: DateTime<Utc>) -> Option<DiscoveryEvent> {
+    let signature = match ibeacon(properties) {
+        Some((uuid, major, minor)) => Signature::Named(format!("iBeacon {uuid} {major}/{minor}")),
+        None => Signature::find(properties)?
+    };
+    Some(DiscoveryEvent::new(date_time, signature, properties.rssi?))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let adapter = Manager::new().await?.adapters().await?.pop().ok_or("no Bluetooth adapter")?;
+    adapter.start_scan(ScanFilter::default()).await?;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let now = Utc::now();
+    let mut events = vec![];
+    for peripheral in adapter.peripherals().await? {
+        if let Some(event) = peripheral.properties().await?.and_then(|p| decode(&p, now)) {
+            events.push(event);
+        }
+    }
+    adapter.stop_scan().await?;
+    let mut state = State::default();
+    state.discover(&events);
+    print!("{}", state.snapshot().order_by_age_and_volume());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use blescan::signature::Signature;
+    use btleplug::api::PeripheralProperties;
+    use chrono::{Utc, TimeZone};
+
+    use super::{decode, APPLE};
+
+    fn properties(manufacturer_data: Vec<u8>) -> PeripheralProperties {
+        PeripheralProperties {
+            manufacturer_data: HashMap::from([(APPLE, manufacturer_data)]),
+            rssi: Some(-60),
+            ..PeripheralProperties::default()
+        }
+    }
+
+    #[test]
+    fn ibeacons_are_named() {
+        let mut data = vec![0x02, 0x15];
+        data.extend(0..16u8);
+        data.extend([0x00, 0x01, 0x00, 0x02, 0xC5]);
+        let event = decode(&properties(data), Utc.timestamp_opt(0, 0).unwrap()).unwrap();
+        assert_eq!(event.signature, Signature::Named("iBeacon 000102030405060708090a0b0c0d0e0f 1/2".to_string()));
+        assert_eq!(event.rssi, -60);
+    }
+
+    #[test]
+    fn other_advertisements_are_anonymous() {
+        let properties = properties(vec![0x10, 0x05, 0x01]);
+        let event = decode(&properties, Utc.timestamp_opt(0, 0).unwrap()).unwrap();
+        assert_eq!(Some(event.signature), Signature::find(&properties));
+    }
+
+    #[test]
+    fn no_rssi_no_event() {
+        let properties = PeripheralProperties { rssi: None, ..properties(vec![0x10]) };
+        assert_eq!(decode(&properties, Utc.timestamp_opt(0, 0).unwrap()), None);
+    }
+}
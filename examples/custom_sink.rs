@@ -0,0 +1,62 @@
+//! Writing a custom EventSink: this one just counts what it's given.
+//!
+//!     cargo run --example custom_sink
+
+use async_trait::async_trait;
+use blescan::{discover::DiscoveryEvent, error::Error, gap::ScanGap, history::EventSink, signature::Signature};
+use chrono::Utc;
+
+#[derive(Default)]
+struct CountingEventSink {
+    events: usize,
+    gaps: usize
+}
+
+#[async_trait]
+impl EventSink for CountingEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        self.events += events.len();
+        Ok(())
+    }
+    async fn save_gap(&mut self, _: &ScanGap) -> Result<(), Error> {
+        self.gaps += 1;
+        Ok(())
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        println!("saw {} events and {} gaps", self.events, self.gaps);
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let mut sink: Box<dyn EventSink> = Box::<CountingEventSink>::default();
+    let now = Utc::now();
+    sink.save(&[
+        DiscoveryEvent::new(now, Signature::Named("Device 1".to_string()), -40),
+        DiscoveryEvent::new(now, Signature::Anonymous("503eb25838435ebb288f3b657b9f9031".to_string()), -70),
+    ]).await?;
+    sink.close().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use blescan::{discover::DiscoveryEvent, gap::{GapReason, ScanGap}, history::EventSink, signature::Signature};
+    use chrono::{Utc, TimeZone};
+
+    use super::CountingEventSink;
+
+    #[tokio::test]
+    async fn counts_events_and_gaps() {
+        let start = Utc.timestamp_opt(0, 0).unwrap();
+        let end = Utc.timestamp_opt(60, 0).unwrap();
+        let mut sink = CountingEventSink::default();
+        sink.save(&[
+            DiscoveryEvent::new(start, Signature::Named("Device 1".to_string()), -40),
+            DiscoveryEvent::new(end, Signature::Named("Device 1".to_string()), -50),
+        ]).await.unwrap();
+        sink.save_gap(&ScanGap::new(start, end, GapReason::Stalled)).await.unwrap();
+        assert_eq!((sink.events, sink.gaps), (2, 1));
+    }
+}
@@ -0,0 +1,51 @@
+//! Driving State and Snapshot without any Bluetooth hardware, e.g. from
+//! events recorded elsewhere.
+//!
+//!     cargo run --example offline_state
+
+use blescan::{device_state::DeviceState, discover::DiscoveryEvent, signature::Signature, state::State, snapshot::Comparison};
+use chrono::{Utc, TimeZone};
+
+/// How each device changed between two scans a second apart
+fn compare_scans() -> Vec<(DeviceState, Comparison)> {
+    let mut state = State::default();
+    let start = Utc.timestamp_opt(0, 0).unwrap();
+    state.discover(&[
+        DiscoveryEvent::new(start, Signature::Named("Device 1".to_string()), -60),
+        DiscoveryEvent::new(start, Signature::Named("Device 2".to_string()), -50),
+    ]);
+    let first = state.snapshot();
+
+    let later = Utc.timestamp_opt(1, 0).unwrap();
+    state.discover(&[
+        DiscoveryEvent::new(later, Signature::Named("Device 1".to_string()), -40),
+    ]);
+    let second = state.snapshot();
+
+    second.order_by_age_and_volume().compared_to(later, &first)
+}
+
+fn main() {
+    for (device, comparison) in compare_scans() {
+        println!("{}: {} ({:?}, last seen {}s ago)", 
+            device.signature, device.rssi, comparison.rssi, comparison.relative_age.num_seconds());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use blescan::{signature::Signature, snapshot::RssiComparison};
+
+    use super::compare_scans;
+
+    #[test]
+    fn louder_device_first() {
+        let compared : Vec<(Signature, RssiComparison, i64)> = compare_scans().into_iter()
+            .map(|(device, comparison)| (device.signature, comparison.rssi, comparison.relative_age.num_seconds()))
+            .collect();
+        assert_eq!(compared, vec![
+            (Signature::Named("Device 1".to_string()), RssiComparison::Louder, 0),
+            (Signature::Named("Device 2".to_string()), RssiComparison::Same, 1),
+        ]);
+    }
+}
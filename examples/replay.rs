@@ -0,0 +1,80 @@
+//! Driving the replay API: read a recording back through `EventSource`, and
+//! rebuild what was around at each point in it with `State`.
+//!
+//!     cargo run --example replay -- events.jsonl
+//!
+//! Without a recording, one is made up in a temporary directory first.
+
+use blescan::{discover::DiscoveryEvent, error::Error, history::{EventSinkFormat, SinkOptions}, signature::Signature, snapshot::Snapshot, state::State};
+use chrono::{Duration, Utc, TimeZone};
+use futures::TryStreamExt;
+
+/// The snapshot as of the end of each minute in the recording
+async fn replay(path: &str) -> Result<Vec<Snapshot>, Error> {
+    let source = EventSinkFormat::create_from_file(path)?.to_source().await?;
+    let Some(range) = source.time_range().await? else {
+        return Ok(vec![]);
+    };
+    let mut state = State::default();
+    let mut snapshots = vec![];
+    let mut minute_end = range.start + Duration::minutes(1);
+    let mut events = source.stream_events(range, 1_000);
+    while let Some(chunk) = events.try_next().await? {
+        for event in chunk {
+            while event.date_time >= minute_end {
+                snapshots.push(state.snapshot());
+                minute_end += Duration::minutes(1);
+            }
+            state.discover(&[event]);
+        }
+    }
+    snapshots.push(state.snapshot());
+    Ok(snapshots)
+}
+
+/// Records a device which is seen every 30s, and another which shows up
+/// after a minute
+async fn record(path: &str) -> Result<(), Error> {
+    let mut sink = EventSinkFormat::create_from_file(path)?.to_sink(&SinkOptions::default()).await?;
+    for i in 0..6 {
+        let date_time = Utc.timestamp_opt(i * 30, 0).unwrap();
+        let mut events = vec![DiscoveryEvent::new(date_time, Signature::Named("Device 1".to_string()), -60)];
+        if i >= 2 {
+            events.push(DiscoveryEvent::new(date_time, Signature::Named("Device 2".to_string()), -50));
+        }
+        sink.save(&events).await?;
+    }
+    sink.close().await
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            let path = dir.path().join("events.jsonl").to_string_lossy().to_string();
+            record(&path).await?;
+            path
+        }
+    };
+    for (minute, snapshot) in replay(&path).await?.iter().enumerate() {
+        println!("after minute {}:\n{snapshot}", minute + 1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{record, replay};
+
+    #[tokio::test]
+    async fn snapshot_each_minute() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl").to_string_lossy().to_string();
+        record(&path).await.unwrap();
+
+        let devices : Vec<usize> = replay(&path).await.unwrap().iter().map(|s| s.0.len()).collect();
+        assert_eq!(devices, vec![1, 2, 2]);
+    }
+}
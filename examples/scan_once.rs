@@ -0,0 +1,23 @@
+//! Embedding the scanner: do a single scan and print what was found.
+//!
+//!     cargo run --example scan_once
+
+use std::error::Error;
+
+use blescan::{discover_btleplug::Scanner, state::State};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut scanner = Scanner::new().await?;
+    let mut state = State::default();
+    let events = scanner.scan().await?;
+    state.discover(&events);
+    let snapshot = state.snapshot().order_by_age_and_volume();
+    // a device seen more than once in a scan still only appears once
+    let mut signatures : Vec<_> = events.iter().map(|e| &e.signature).collect();
+    signatures.sort();
+    signatures.dedup();
+    assert_eq!(snapshot.0.len(), signatures.len());
+    print!("{snapshot}");
+    Ok(())
+}
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS scan_gaps
+(
+    start     DATETIME            NOT NULL,
+    end       DATETIME            NOT NULL,
+    reason    TEXT                NOT NULL
+);
@@ -0,0 +1,21 @@
+ALTER TABLE discovery_events ADD COLUMN signature_kind  TEXT NOT NULL DEFAULT '';
+ALTER TABLE discovery_events ADD COLUMN signature_value TEXT NOT NULL DEFAULT '';
+ALTER TABLE discovery_events ADD COLUMN source          TEXT;
+ALTER TABLE discovery_events ADD COLUMN session_id      TEXT;
+
+-- earlier rows only have the formatted signature, where names are padded
+-- and anonymous digests are bare lowercase hex
+UPDATE discovery_events
+SET signature_kind = CASE
+        WHEN signature = '' OR signature GLOB '*[^0-9a-f]*' THEN 'named'
+        ELSE 'anonymous'
+    END,
+    signature_value = CASE
+        WHEN signature = '' OR signature GLOB '*[^0-9a-f]*' THEN ltrim(signature)
+        ELSE signature
+    END;
+
+CREATE INDEX IF NOT EXISTS discovery_events_by_signature 
+    ON discovery_events (signature_kind, signature_value, date_time);
+CREATE INDEX IF NOT EXISTS discovery_events_by_date_time
+    ON discovery_events (date_time);
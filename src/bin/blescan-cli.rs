@@ -0,0 +1,685 @@
+use std::{error::Error, fs::{File, OpenOptions}, io::{self, Write, BufReader, IsTerminal}, path::{Path, PathBuf}, sync::{Arc, Mutex}, collections::HashSet, str::FromStr};
+
+use blescan::{config::{Config, FilterConfig}, discover_btleplug::Scanner, state::State, snapshot::{Snapshot, DeviceFilter, RssiComparison}, signature::{Signature, SignaturePolicy}, chrono_extra::{Truncate, TimestampFormat}, history::{self, query::{distinct_devices, busiest_hours, DeviceSummary}, EventSinkFormat, EventSource, SinkOptions, sqllite::{SQLLiteEventSink, connect_options}, encrypt::{EncryptionKey, DecryptingReader}}, presence::{sessions, PresenceSession, PresenceTracker, PresenceEvent, PresenceChange}, ical::to_ical, discover::DiscoveryEvent};
+use chrono::Utc;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, parser::ValueSource};
+use crossterm::{cursor, queue, terminal, style::{Color, Stylize}};
+use futures::{future, TryStreamExt};
+use sqlx::sqlite::SqlitePoolOptions;
+use tracing_subscriber::filter::LevelFilter;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    /// whether to color output: auto (only when writing to a terminal, and NO_COLOR isn't set), always or never
+    #[arg(long, global = true, default_value = "auto")]
+    color: ColorChoice,
+
+    /// how times are shown: iso (RFC 3339), unix (seconds since the epoch) or relative (to when scanning,
+    /// or the recording, started)
+    #[arg(long, global = true, default_value = "iso")]
+    timestamps: TimestampFormat,
+
+    /// settings file to use instead of ~/.config/blescan/config.toml, see README
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// append diagnostics to this file, rather than writing them to stderr
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice: {s} (expected auto, always or never)"))
+        }
+    }
+}
+
+/// Colors output, unless that has been turned off
+#[derive(Debug, Clone, Copy)]
+struct Palette {
+    enabled: bool
+}
+
+impl Palette {
+    fn new(choice: ColorChoice) -> Palette {
+        let enabled = match choice {
+            ColorChoice::Auto => std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && io::stdout().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false
+        };
+        Palette { enabled }
+    }
+
+    fn paint(self, text: &str, color: Color) -> String {
+        if self.enabled { text.with(color).to_string() } else { text.to_string() }
+    }
+
+    fn arrived(self, text: &str) -> String {
+        self.paint(text, Color::Green)
+    }
+
+    fn departed(self, text: &str) -> String {
+        self.paint(text, Color::Red)
+    }
+
+    /// From red for the weakest signals, through to green for the strongest
+    fn rssi(self, text: &str, rssi: i16) -> String {
+        let strength = (f64::from(rssi.clamp(-100, -40)) + 100.0) / 60.0;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let color = Color::Rgb { r: (255.0 * (1.0 - strength)) as u8, g: (255.0 * strength) as u8, b: 0 };
+        self.paint(text, color)
+    }
+}
+
+/// Which devices to show, for when there are too many to take in
+#[derive(clap::Args, Debug)]
+struct Filters {
+    /// only devices at least this loud, e.g. -70
+    #[arg(long, allow_hyphen_values = true)]
+    min_rssi: Option<i16>,
+
+    /// only named devices (--named-only=false turns off named_only from the config file)
+    #[arg(long, conflicts_with = "anon_only", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    named_only: Option<bool>,
+
+    /// only anonymous devices (--anon-only=false turns off anon_only from the config file)
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    anon_only: Option<bool>,
+
+    /// only devices whose name, or anonymous signature, contains this (ignoring case)
+    #[arg(long)]
+    filter: Option<String>,
+}
+
+impl Filters {
+    /// Fills in anything not given on the command line from `config`
+    fn apply_config(&mut self, config: &FilterConfig) {
+        self.min_rssi = self.min_rssi.or(config.min_rssi);
+        if self.named_only.is_none() && self.anon_only.is_none() {
+            self.named_only = Some(config.named_only);
+            self.anon_only = Some(config.anon_only);
+        }
+        self.filter = self.filter.take().or_else(|| config.substring.clone());
+    }
+
+    fn to_filter(&self) -> DeviceFilter {
+        DeviceFilter {
+            min_rssi: self.min_rssi,
+            named: match (self.named_only, self.anon_only) {
+                (Some(true), _) => Some(true),
+                (_, Some(true)) => Some(false),
+                _ => None
+            },
+            substring: self.filter.clone()
+        }
+    }
+}
+
+/// Shell commands to run as devices arrive and depart. Each is passed details
+/// of the device as BLESCAN_SIGNATURE, BLESCAN_NAME (empty for anonymous
+/// devices), BLESCAN_RSSI and BLESCAN_TIME.
+#[derive(clap::Args, Debug)]
+struct Hooks {
+    /// shell command to run when a device arrives
+    #[arg(long)]
+    on_arrive: Option<String>,
+
+    /// shell command to run when a device departs
+    #[arg(long)]
+    on_depart: Option<String>,
+
+    /// how long a device can go unseen before it's considered to have departed
+    #[arg(long, default_value = "1m")]
+    max_absence: humantime::Duration,
+}
+
+impl Hooks {
+    fn command(&self, change: PresenceChange) -> Option<&str> {
+        match change {
+            PresenceChange::Arrived => self.on_arrive.as_deref(),
+            PresenceChange::Departed => self.on_depart.as_deref()
+        }
+    }
+
+    /// Starts the command for `event`, if there is one, without waiting for it
+    /// to finish. Failures are logged, rather than stopping scanning.
+    fn run(&self, event: &PresenceEvent, rssi: i16) {
+        let Some(command) = self.command(event.change) else {
+            return;
+        };
+        let name = match &event.signature {
+            Signature::Named(name) => name.as_str(),
+            Signature::Anonymous(_) => ""
+        };
+        let spawned = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("BLESCAN_SIGNATURE", event.signature.value())
+            .env("BLESCAN_NAME", name)
+            .env("BLESCAN_RSSI", rssi.to_string())
+            .env("BLESCAN_TIME", event.date_time.to_rfc3339())
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                tracing::warn!("{command} couldn't be started: {e}");
+                return;
+            }
+        };
+        let command = command.to_string();
+        tokio::spawn(async move {
+            match child.wait().await {
+                Ok(status) if !status.success() => tracing::warn!("{command} failed: {status}"),
+                Err(e) => tracing::warn!("{command} failed: {e}"),
+                Ok(_) => {}
+            }
+        });
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct ScanArgs {
+    /// stop after this many scans (by default, scan until interrupted)
+    #[arg(long)]
+    count: Option<u64>,
+
+    /// stop after scanning for this long, e.g. 30s
+    #[arg(long)]
+    duration: Option<humantime::Duration>,
+
+    /// instead, wait until this device (a name or anonymous signature) is seen, exiting with 0;
+    /// or with 1, if --timeout passes first
+    #[arg(long, conflicts_with_all = ["count", "duration"])]
+    watch: Option<String>,
+
+    /// how long to wait for the --watch device
+    #[arg(long, requires = "watch")]
+    timeout: Option<humantime::Duration>,
+
+    /// how anonymous signatures are derived: md5, xxh3, hmac:<key-file>, or hmac to take the key from $BLESCAN_SIGNATURE_KEY
+    #[arg(long, default_value = "md5")]
+    signature_policy: SignaturePolicy,
+
+    /// which Bluetooth adapter to scan with, by its position in the list shown by `adapters` (defaults to the last)
+    #[arg(long)]
+    adapter: Option<usize>,
+
+    /// stop once this many scans in a row have seen exactly the same devices, and show just those
+    #[arg(long, conflicts_with = "watch", value_parser = clap::value_parser!(u64).range(2..))]
+    settle: Option<u64>,
+
+    /// redraw a table of the devices seen, in place, after each scan; like top, but much lighter than blescan itself
+    #[arg(long, conflicts_with = "watch")]
+    top: bool,
+
+    #[command(flatten)]
+    filters: Filters,
+
+    #[command(flatten)]
+    hooks: Hooks,
+}
+
+impl ScanArgs {
+    /// Fills in anything not given on the command line from `config`
+    fn apply_config(&mut self, config: &Config, matches: Option<&ArgMatches>) -> Result<(), Box<dyn Error>> {
+        self.filters.apply_config(&config.filter);
+        self.adapter = self.adapter.or(config.adapter);
+        let given = matches.and_then(|m| m.value_source("signature_policy")) == Some(ValueSource::CommandLine);
+        if let Some(policy) = config.signature_policy.as_ref().filter(|_| !given) {
+            self.signature_policy = policy.parse()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// delete everything recorded more than some time ago
+    Prune {
+        /// file recorded by blescan (.sqlite only)
+        #[arg(long)]
+        db: String,
+
+        /// how old events need to be for deletion, e.g. 30d
+        #[arg(long)]
+        older_than: humantime::Duration,
+    },
+    /// shrink the file, giving back the space left by deleted events
+    Vacuum {
+        /// file recorded by blescan (.sqlite only)
+        #[arg(long)]
+        db: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// scan, printing how many devices each scan found, and then a summary of them all
+    Scan(ScanArgs),
+    /// list the Bluetooth adapters which can be scanned with, numbered as --adapter (of blescan, or of scan) expects
+    Adapters,
+    /// summarise a recording: devices seen, when they were present, and the busiest hours
+    Stats {
+        /// file recorded by blescan (.sqlite, .jsonl or .jsonl.gz)
+        #[arg(long)]
+        db: String,
+
+        /// how many devices, and hours, to list
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+
+        /// how long a device can go unseen before it's considered to have left
+        #[arg(long, default_value = "5m")]
+        max_absence: humantime::Duration,
+
+        // devices are filtered by the loudest they were seen
+        #[command(flatten)]
+        filters: Filters,
+    },
+    /// compare the devices seen in two recordings, e.g. from two places or two periods
+    Diff {
+        /// file recorded by blescan (.sqlite, .jsonl or .jsonl.gz)
+        a: String,
+
+        /// file recorded by blescan (.sqlite, .jsonl or .jsonl.gz)
+        b: String,
+
+        /// the least change in mean RSSI, in dB, worth reporting
+        #[arg(long, default_value_t = 10.0)]
+        rssi_shift: f64,
+    },
+    /// manage the size of a .sqlite recording
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    /// combine recordings, e.g. made on different machines, into one
+    Merge {
+        /// where to write to (.sqlite, .jsonl, .jsonl.gz or .csv); must not already exist
+        output: String,
+
+        /// files recorded by blescan (.sqlite, .jsonl or .jsonl.gz)
+        #[arg(required = true)]
+        inputs: Vec<String>,
+    },
+    /// copy a recording to another format (.sqlite, .jsonl, .jsonl.gz or .csv), or to any other sink
+    Export {
+        /// file recorded by blescan (.sqlite, .jsonl or .jsonl.gz)
+        #[arg(long)]
+        from: String,
+
+        /// where to copy to; must not already exist
+        #[arg(long)]
+        to: String,
+    },
+    /// export the times a device was present as an iCalendar file
+    ExportIcal {
+        /// file recorded by blescan (.sqlite, .jsonl or .jsonl.gz)
+        #[arg(long)]
+        db: String,
+
+        /// name, or anonymous signature, of the device
+        #[arg(long)]
+        signature: String,
+
+        /// how long a device can go unseen before it's considered to have left
+        #[arg(long, default_value = "5m")]
+        max_absence: humantime::Duration,
+
+        /// file to write to (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// decrypt a recording made with --encrypt-key-file
+    Decrypt {
+        /// encrypted .jsonl or .csv file
+        input: String,
+
+        /// file holding the key the recording was encrypted with
+        #[arg(long)]
+        key_file: String,
+
+        /// file to write to (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let matches = Args::command().get_matches();
+    let Args { command, color, timestamps, config, log_file } = Args::from_arg_matches(&matches)?;
+    let diagnostics = tracing_subscriber::fmt().with_max_level(LevelFilter::INFO);
+    match log_file {
+        Some(path) => diagnostics.with_ansi(false).with_writer(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)).init(),
+        None => diagnostics.with_writer(io::stderr).init()
+    }
+    let config = Config::load(config.as_deref())?;
+    let palette = Palette::new(color);
+    match command {
+        Command::Scan(mut args) => {
+            args.apply_config(&config, matches.subcommand_matches("scan"))?;
+            match &args.watch {
+                Some(signature) => {
+                    if !watch(signature, args.timeout, args.signature_policy.clone(), args.adapter, timestamps).await? {
+                        std::process::exit(1);
+                    }
+                    Ok(())
+                },
+                None => scan(&args, palette, timestamps).await
+            }
+        },
+        Command::Adapters => {
+            adapters().await
+        },
+        Command::Stats { db, top, max_absence, mut filters } => {
+            filters.apply_config(&config.filter);
+            stats(&db, top, max_absence, &filters.to_filter(), timestamps).await
+        },
+        Command::Diff { a, b, rssi_shift } => {
+            diff(&a, &b, rssi_shift).await
+        },
+        Command::Db { command: DbCommand::Prune { db, older_than } } => {
+            let before = Utc::now() - chrono::Duration::from_std(*older_than)?;
+            let (events, gaps) = open_sqlite(&db).await?.prune(before).await?;
+            println!("deleted {events} events and {gaps} gaps from before {}", before.to_rfc3339());
+            Ok(())
+        },
+        Command::Db { command: DbCommand::Vacuum { db } } => {
+            open_sqlite(&db).await?.vacuum().await?;
+            Ok(())
+        },
+        Command::Merge { output, inputs } => {
+            merge(&inputs, &output).await
+        },
+        Command::Export { from, to } => {
+            export(&from, &to).await
+        },
+        Command::ExportIcal { db, signature, max_absence, output } => {
+            export_ical(&db, &signature, max_absence, output, timestamps).await
+        },
+        Command::Decrypt { input, key_file, output } => {
+            decrypt(&input, &key_file, output)
+        }
+    }
+}
+
+async fn scan(args: &ScanArgs, palette: Palette, timestamps: TimestampFormat) -> Result<(), Box<dyn Error>> {
+    let filter = args.filters.to_filter();
+    let mut scanner = Scanner::new_with_adapter(args.signature_policy.clone(), args.adapter).await?;
+    let mut state = State::default();
+    let mut presence = PresenceTracker::new(chrono::Duration::from_std(*args.hooks.max_absence)?);
+    let start = Utc::now();
+    let end = args.duration.map(|d| chrono::Duration::from_std(*d)).transpose()?.map(|d| start + d);
+    let (mut scans, mut failed) = (0u64, 0u64);
+    let mut previous = Snapshot::default();
+    // the devices seen by the latest scan, and how many scans in a row have seen just those
+    let mut seen_by_scan : (HashSet<Signature>, u64) = (HashSet::new(), 0);
+    let settled = |(_, scans): &(HashSet<Signature>, u64)| args.settle.is_some_and(|settle| *scans >= settle);
+    while args.count.is_none_or(|count| scans < count) && end.is_none_or(|end| Utc::now() < end) && !settled(&seen_by_scan) {
+        scans += 1;
+        match scanner.scan().await {
+            Ok(events) => {
+                let signatures : HashSet<Signature> = events.iter()
+                    .filter(|e| filter.matches(&e.signature, e.rssi))
+                    .map(|e| e.signature.clone())
+                    .collect();
+                seen_by_scan = if signatures == seen_by_scan.0 { (signatures, seen_by_scan.1 + 1) } else { (signatures, 1) };
+                state.discover(&events);
+                let snapshot = state.snapshot().filter(&filter);
+                let changes = presence.update(&snapshot, Utc::now());
+                if args.top {
+                    draw_top(&snapshot.order_by_age_and_volume(), &previous, &presence, scans, failed, palette)?;
+                }
+                else {
+                    println!("{} scan {scans}: {} devices", timestamps.format(Utc::now(), start), seen_by_scan.0.len());
+                    for change in &changes {
+                        match change.change {
+                            PresenceChange::Arrived => println!("  {}", palette.arrived(&format!("arrived: {}", change.signature.value()))),
+                            PresenceChange::Departed => println!("  {}", palette.departed(&format!("departed: {}", change.signature.value())))
+                        }
+                    }
+                }
+                // unfiltered, as a device can depart by dropping below --min-rssi
+                let seen = state.snapshot();
+                for event in changes {
+                    let rssi = seen.0.iter().find(|d| d.signature == event.signature).map_or(0, |d| d.rssi);
+                    args.hooks.run(&event, rssi);
+                }
+                previous = snapshot;
+            },
+            Err(e) => {
+                failed += 1;
+                tracing::warn!("scan {scans} failed: {e}");
+            }
+        }
+    }
+    let mut snapshot = state.snapshot().filter(&filter).order_by_age_and_volume();
+    if settled(&seen_by_scan) {
+        snapshot.0.retain(|d| seen_by_scan.0.contains(&d.signature));
+        println!("settled after {scans} scans");
+    }
+    print!("{snapshot}");
+    let named = snapshot.0.iter().filter(|d| matches!(d.signature, Signature::Named(_))).count();
+    println!("{scans} scans ({failed} failed) over {}, {} devices seen ({named} named, {} anonymous)",
+        humantime::format_duration((Utc::now() - start).truncate_to_seconds().to_std()?),
+        snapshot.0.len(), snapshot.0.len() - named);
+    Ok(())
+}
+
+/// Redraws the screen with as many of the devices in `snapshot` as fit;
+/// devices new since `previous` are marked as arrived, and those no longer
+/// `present`, as departed
+fn draw_top(snapshot: &Snapshot, previous: &Snapshot, present: &PresenceTracker, scans: u64, failed: u64, palette: Palette) -> Result<(), Box<dyn Error>> {
+    let now = Utc::now();
+    let (_, height) = terminal::size().unwrap_or((80, 24));
+    let mut stdout = io::stdout();
+    queue!(stdout, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0))?;
+    writeln!(stdout, "{scans} scans ({failed} failed), {} devices", snapshot.0.len())?;
+    writeln!(stdout, "{:>32} {:>9} {:>5} {:>8}", "Signature", "Kind", "RSSI", "Age")?;
+    for (device, comparison) in snapshot.compared_to(now, previous).iter().take(usize::from(height.saturating_sub(3))) {
+        let age = humantime::format_duration(comparison.relative_age.truncate_to_seconds().to_std().unwrap_or_default());
+        let signature = format!("{:>32}", device.signature.value());
+        let signature = if !present.is_present(&device.signature) {
+            palette.departed(&signature)
+        }
+        else if comparison.rssi == RssiComparison::New {
+            palette.arrived(&signature)
+        }
+        else {
+            signature
+        };
+        writeln!(stdout, "{signature} {:>9} {} {:>8}", device.signature.kind(), palette.rssi(&format!("{:>5}", device.rssi), device.rssi), age.to_string())?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Scans until `signature` is seen, or `timeout` passes, returning whether it was seen
+async fn watch(signature: &str, timeout: Option<humantime::Duration>, policy: SignaturePolicy, adapter: Option<usize>, timestamps: TimestampFormat)
+    -> Result<bool, Box<dyn Error>> {
+    let mut scanner = Scanner::new_with_adapter(policy, adapter).await?;
+    let start = Utc::now();
+    let end = timeout.map(|d| chrono::Duration::from_std(*d)).transpose()?.map(|d| start + d);
+    while end.is_none_or(|end| Utc::now() < end) {
+        match scanner.scan().await {
+            Ok(events) => {
+                if let Some(event) = events.iter().find(|e| e.signature.matches(signature)) {
+                    println!("{} {} seen, RSSI {}", timestamps.format(event.date_time, start), event.signature.value(), event.rssi);
+                    return Ok(true);
+                }
+            },
+            Err(e) => tracing::warn!("scan failed: {e}")
+        }
+    }
+    eprintln!("{signature} not seen");
+    Ok(false)
+}
+
+async fn open_source(path: &str) -> Result<Box<dyn EventSource>, Box<dyn Error>> {
+    Ok(EventSinkFormat::create_from_file(path)?.to_source().await?)
+}
+
+async fn open_sqlite(path: &str) -> Result<SQLLiteEventSink, Box<dyn Error>> {
+    if !matches!(EventSinkFormat::create_from_file(path)?, EventSinkFormat::SQLITE(_)) {
+        return Err(format!("{path} is not a .sqlite file").into());
+    }
+    if !Path::new(path).exists() {
+        return Err(format!("{path} does not exist").into());
+    }
+    let pool = SqlitePoolOptions::new().connect_with(connect_options(Path::new(path))).await?;
+    Ok(SQLLiteEventSink::create_from_pool(Arc::new(pool)).await?)
+}
+
+fn open_output(output: Option<String>) -> Result<Box<dyn Write>, Box<dyn Error>> {
+    Ok(match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout())
+    })
+}
+
+async fn recorded_devices(path: &str) -> Result<Vec<DeviceSummary>, Box<dyn Error>> {
+    let source = open_source(path).await?;
+    match source.time_range().await? {
+        Some(range) => Ok(distinct_devices(source.as_ref(), range).await?),
+        None => Ok(vec![])
+    }
+}
+
+async fn diff(a: &str, b: &str, rssi_shift: f64) -> Result<(), Box<dyn Error>> {
+    let before = recorded_devices(a).await?;
+    let after = recorded_devices(b).await?;
+    let print_devices = |title: &str, devices: Vec<&DeviceSummary>| {
+        println!("{title} ({}):", devices.len());
+        for device in devices {
+            println!("  {}: {} sightings, mean RSSI {:.1}", device.signature.value(), device.sightings, device.mean_rssi);
+        }
+    };
+    print_devices(&format!("Only in {a}"), before.iter().filter(|d| !after.iter().any(|o| o.signature == d.signature)).collect());
+    print_devices(&format!("Only in {b}"), after.iter().filter(|d| !before.iter().any(|o| o.signature == d.signature)).collect());
+    let mut shifted : Vec<(&DeviceSummary, &DeviceSummary)> = before.iter()
+        .filter_map(|d| after.iter().find(|o| o.signature == d.signature).map(|o| (d, o)))
+        .collect();
+    let shift = |(before, after): &(&DeviceSummary, &DeviceSummary)| (after.mean_rssi - before.mean_rssi).abs();
+    shifted.retain(|pair| shift(pair) >= rssi_shift);
+    shifted.sort_by(|x, y| shift(y).total_cmp(&shift(x)));
+    println!("Mean RSSI shifted by at least {rssi_shift}dB ({}):", shifted.len());
+    for (before, after) in shifted {
+        println!("  {}: {:.1} -> {:.1} ({:+.1})", before.signature.value(), before.mean_rssi, after.mean_rssi, after.mean_rssi - before.mean_rssi);
+    }
+    Ok(())
+}
+
+async fn export(from: &str, to: &str) -> Result<(), Box<dyn Error>> {
+    merge(&[from.to_string()], to).await
+}
+
+/// Copies everything recorded in `inputs` to `output`, leaving out identical
+/// events and gaps, e.g. from copies of the same recording. The inputs must
+/// have been recorded with the same signature policy, which is recorded in
+/// `output` too.
+async fn merge(inputs: &[String], output: &str) -> Result<(), Box<dyn Error>> {
+    if Path::new(output).exists() {
+        return Err(format!("{output} already exists").into());
+    }
+    let signature_policy = history::merge::common_signature_policy(inputs).await?;
+    let sources = future::try_join_all(inputs.iter().map(|input| open_source(input))).await?;
+    let options = SinkOptions { signature_policy, ..SinkOptions::default() };
+    let mut sink = EventSinkFormat::create_from_file(output)?.to_sink(&options).await?;
+    let (events, gaps) = history::merge::merge(&sources, sink.as_mut()).await?;
+    eprintln!("copied {events} events and {gaps} gaps to {output}");
+    sink.close().await?;
+    Ok(())
+}
+
+async fn export_ical(db: &str, signature: &str, max_absence: humantime::Duration, output: Option<String>, timestamps: TimestampFormat) 
+    -> Result<(), Box<dyn Error>> {
+    let source = open_source(db).await?;
+    let Some(range) = source.time_range().await? else {
+        return Err(format!("no events recorded in {db}").into());
+    };
+    let events : Vec<DiscoveryEvent> = source.stream_events(range.clone(), 10_000)
+        .map_ok(|chunk| chunk.into_iter().filter(|e| e.signature.matches(signature)).collect::<Vec<_>>())
+        .try_concat()
+        .await?;
+    let gaps = source.gaps(range.clone()).await?;
+    let max_absence = chrono::Duration::from_std(*max_absence)?;
+    let calendar = to_ical(&sessions(&events, &gaps, max_absence), Utc::now(), |t| timestamps.format(t, range.start));
+    open_output(output)?.write_all(calendar.as_bytes())?;
+    Ok(())
+}
+
+async fn adapters() -> Result<(), Box<dyn Error>> {
+    let adapters = Scanner::adapters().await?;
+    if adapters.is_empty() {
+        return Err("no Bluetooth adapters found".into());
+    }
+    for (index, adapter) in adapters.iter().enumerate() {
+        let default = if index == adapters.len() - 1 { " (default)" } else { "" };
+        println!("{index}: {adapter}{default}");
+    }
+    Ok(())
+}
+
+async fn stats(db: &str, top: usize, max_absence: humantime::Duration, filter: &DeviceFilter, timestamps: TimestampFormat)
+    -> Result<(), Box<dyn Error>> {
+    let source = open_source(db).await?;
+    let Some(range) = source.time_range().await? else {
+        return Err(format!("no events recorded in {db}").into());
+    };
+    let mut devices = distinct_devices(source.as_ref(), range.clone()).await?;
+    devices.retain(|d| filter.matches(&d.signature, d.max_rssi));
+    let hours = busiest_hours(source.as_ref(), range.clone()).await?;
+    let named = devices.iter().filter(|d| matches!(d.signature, Signature::Named(_))).count();
+    println!("Recorded from {} to {}", timestamps.format(range.start, range.start), timestamps.format(range.end, range.start));
+    println!("Devices seen: {} ({named} named, {} anonymous)", devices.len(), devices.len() - named);
+
+    println!("\nBusiest hours:");
+    for hour in hours.iter().take(top) {
+        println!("  {}  {} devices, {} sightings", timestamps.format(hour.hour, range.start), hour.distinct_devices, hour.sightings);
+    }
+
+    let devices = &devices[..top.min(devices.len())];
+    let events : Vec<DiscoveryEvent> = source.stream_events(range.clone(), 10_000)
+        .map_ok(|chunk| chunk.into_iter().filter(|e| devices.iter().any(|d| d.signature == e.signature)).collect::<Vec<_>>())
+        .try_concat()
+        .await?;
+    let sessions = sessions(&events, &source.gaps(range.clone()).await?, chrono::Duration::from_std(*max_absence)?);
+    println!("\nMost seen devices:");
+    for device in devices {
+        println!("  {}: {} sightings, RSSI {}/{:.1}/{} (min/mean/max)",
+            device.signature.value(), device.sightings, device.min_rssi, device.mean_rssi, device.max_rssi);
+        let mut windows : Vec<&PresenceSession> = sessions.iter().filter(|s| s.signature == device.signature).collect();
+        windows.sort_by_key(|s| s.start);
+        for window in windows {
+            println!("    present {} to {} ({})", timestamps.format(window.start, range.start), timestamps.format(window.end, range.start),
+                humantime::format_duration(window.duration().to_std()?));
+        }
+    }
+    Ok(())
+}
+
+fn decrypt(input: &str, key_file: &str, output: Option<String>) -> Result<(), Box<dyn Error>> {
+    let key = EncryptionKey::from_file(key_file)?;
+    let mut reader = DecryptingReader::create(BufReader::new(File::open(input)?), &key);
+    io::copy(&mut reader, &mut open_output(output)?)?;
+    Ok(())
+}
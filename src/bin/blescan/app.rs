@@ -0,0 +1,275 @@
+use blescan::{distance::DistanceEstimator, manufacturer::Manufacturers, presence::{PresenceEvent, PresenceChange}, signature::Signature, snapshot::Snapshot, watchlist::Watchlist};
+use std::{collections::HashSet, time::Duration};
+
+use chrono::{DateTime, Utc};
+use crossterm::event::KeyCode;
+use ratatui::widgets::TableState;
+
+use crate::{log_pane::LogBuffer, theme::Theme};
+
+/// How many arrivals/departures to keep for the presence log
+const PRESENCE_LOG_SIZE : usize = 1000;
+
+/// The range the scan interval can be adjusted within
+const MIN_SCAN_INTERVAL : Duration = Duration::from_millis(250);
+const MAX_SCAN_INTERVAL : Duration = Duration::from_secs(30);
+
+/// How long a toast stays on screen
+const TOAST_SECONDS : i64 = 3;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Tab {
+    All,
+    Named,
+    Anonymous,
+    Presence,
+    Stats,
+    Chart,
+    Compare
+}
+
+impl Tab {
+    pub const ALL : [Tab; 7] = [Tab::All, Tab::Named, Tab::Anonymous, Tab::Presence, Tab::Stats, Tab::Chart, Tab::Compare];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Tab::All => "All",
+            Tab::Named => "Named",
+            Tab::Anonymous => "Anonymous",
+            Tab::Presence => "Presence log",
+            Tab::Stats => "Stats",
+            Tab::Chart => "RSSI chart",
+            Tab::Compare => "Compare"
+        }
+    }
+
+    fn index(self) -> usize {
+        Tab::ALL.iter().position(|t| *t == self).unwrap_or(0)
+    }
+}
+
+/// What the RSSI column shows
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RssiMode {
+    Dbm,
+    Smoothed,
+    Distance
+}
+
+impl RssiMode {
+    fn next(self) -> RssiMode {
+        match self {
+            RssiMode::Dbm => RssiMode::Smoothed,
+            RssiMode::Smoothed => RssiMode::Distance,
+            RssiMode::Distance => RssiMode::Dbm
+        }
+    }
+}
+
+/// What the run loop needs to act on, following a key press
+#[derive(PartialEq, Debug)]
+pub enum Action {
+    Quit,
+    TogglePause,
+    Export,
+    /// a watched device arrived or departed
+    Alert,
+    /// move through a replay by this many steps
+    Scrub(i32),
+    Bookmark,
+    None
+}
+
+/// Everything about the UI which outlives a single frame
+pub struct App {
+    pub start: DateTime<Utc>,
+    pub theme: Theme,
+    pub tab: Tab,
+    pub paused: bool,
+    /// whether showing a recording rather than scanning
+    pub replaying: bool,
+    /// how long each scan listens for
+    pub scan_interval: Duration,
+    pub scans: u64,
+    pub failed_scans: u64,
+    pub presence_log: Vec<PresenceEvent>,
+    /// one per tab, so each keeps its own scroll position
+    pub table_states: [TableState; 7],
+    /// the device selected in the last device table shown
+    pub selected: Option<Signature>,
+    pub rssi_mode: RssiMode,
+    pub estimator: DistanceEstimator,
+    /// whether anonymous devices are grouped by manufacturer
+    pub grouped: bool,
+    /// the manufacturer of each device, as seen while scanning
+    pub manufacturers: Manufacturers,
+    /// the manufacturers whose groups show their devices
+    pub expanded: HashSet<Option<u16>>,
+    /// the manufacturer of the group selected in the last device table shown
+    pub selected_group: Option<Option<u16>>,
+    /// devices to plot on the chart, in the order they were added
+    pub charted: Vec<Signature>,
+    /// how many rows fit in a table, as of the last frame
+    pub page: usize,
+    pub watchlist: Watchlist,
+    /// a snapshot to compare against, and when it was taken
+    pub bookmark: Option<(DateTime<Utc>, Snapshot)>,
+    pub log: LogBuffer,
+    /// whether the log pane is shown
+    pub show_log: bool,
+    /// a short message, and when it was shown
+    pub toast: Option<Toast>
+}
+
+pub struct Toast {
+    pub message: String,
+    pub shown: DateTime<Utc>,
+    /// the watched devices this is alerting about, if any
+    pub alerting: Vec<Signature>
+}
+
+impl App {
+    pub fn new(start: DateTime<Utc>, theme: Theme, watchlist: Watchlist, scan_interval: Duration) -> App {
+        App {
+            start,
+            theme,
+            tab: Tab::All,
+            paused: false,
+            replaying: false,
+            scan_interval,
+            scans: 0,
+            failed_scans: 0,
+            presence_log: vec![],
+            table_states: Default::default(),
+            selected: None,
+            rssi_mode: RssiMode::Dbm,
+            estimator: DistanceEstimator::default(),
+            grouped: false,
+            manufacturers: Manufacturers::new(),
+            expanded: HashSet::new(),
+            selected_group: None,
+            charted: vec![],
+            page: 1,
+            watchlist,
+            bookmark: None,
+            log: LogBuffer::default(),
+            show_log: false,
+            toast: None
+        }
+    }
+
+    pub fn show_toast(&mut self, message: String, now: DateTime<Utc>) {
+        self.toast = Some(Toast { message, shown: now, alerting: vec![] });
+    }
+
+    /// The toast to show, if one was shown recently enough
+    pub fn current_toast(&self, now: DateTime<Utc>) -> Option<&Toast> {
+        self.toast.as_ref().filter(|t| now - t.shown < chrono::Duration::seconds(TOAST_SECONDS))
+    }
+
+    /// Whether `signature` is one of the devices currently being alerted about
+    pub fn is_alerting(&self, signature: &Signature, now: DateTime<Utc>) -> bool {
+        self.current_toast(now).is_some_and(|t| t.alerting.contains(signature))
+    }
+
+    pub fn table_state(&mut self) -> &mut TableState {
+        &mut self.table_states[self.tab.index()]
+    }
+
+    /// Adds to the presence log, alerting about any watched devices
+    pub fn log_presence(&mut self, events: Vec<PresenceEvent>, now: DateTime<Utc>) -> Action {
+        let watched : Vec<&PresenceEvent> = self.watchlist.filter(&events).collect();
+        let action = if watched.is_empty() {
+            Action::None
+        }
+        else {
+            let message = watched.iter()
+                .map(|e| match e.change {
+                    PresenceChange::Arrived => format!("{} arrived", e.signature.value()),
+                    PresenceChange::Departed => format!("{} departed", e.signature.value())
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let alerting = watched.iter().map(|e| e.signature.clone()).collect();
+            self.toast = Some(Toast { message, shown: now, alerting });
+            Action::Alert
+        };
+        self.presence_log.extend(events);
+        let excess = self.presence_log.len().saturating_sub(PRESENCE_LOG_SIZE);
+        self.presence_log.drain(..excess);
+        action
+    }
+
+    /// Adds the selected device to the chart, or removes it if already there
+    fn toggle_charted(&mut self) {
+        if let Some(signature) = self.selected.clone() {
+            match self.charted.iter().position(|s| *s == signature) {
+                Some(index) => { self.charted.remove(index); },
+                None => self.charted.push(signature)
+            }
+        }
+    }
+
+    fn switch_tab(&mut self, delta: isize) {
+        let next = self.tab.index().saturating_add_signed(delta).min(Tab::ALL.len() - 1);
+        self.tab = Tab::ALL[next];
+    }
+
+    pub fn on_key(&mut self, key: KeyCode) -> Action {
+        let page : isize = self.page.try_into().unwrap_or(isize::MAX);
+        match key {
+            KeyCode::Char('q') => return Action::Quit,
+            KeyCode::Char('p') => {
+                self.paused = !self.paused;
+                return Action::TogglePause;
+            },
+            KeyCode::Char('e') => return Action::Export,
+            KeyCode::Char('d') => self.rssi_mode = self.rssi_mode.next(),
+            KeyCode::Char('g') => self.grouped = !self.grouped,
+            KeyCode::Enter => {
+                if let Some(manufacturer_id) = self.selected_group {
+                    if !self.expanded.remove(&manufacturer_id) {
+                        self.expanded.insert(manufacturer_id);
+                    }
+                }
+            },
+            KeyCode::Char('l') => self.show_log = !self.show_log,
+            KeyCode::Char('+' | '=') => {
+                self.scan_interval = (self.scan_interval * 2).min(MAX_SCAN_INTERVAL);
+            },
+            KeyCode::Char('-') => {
+                self.scan_interval = (self.scan_interval / 2).max(MIN_SCAN_INTERVAL);
+            },
+            KeyCode::Char('c') => self.toggle_charted(),
+            KeyCode::Char('w') => {
+                if let Some(signature) = &self.selected {
+                    self.watchlist.toggle(signature);
+                }
+            },
+            KeyCode::Char('b') => return Action::Bookmark,
+            KeyCode::Char(c @ '1'..='7') => {
+                self.tab = Tab::ALL[c as usize - '1' as usize];
+            },
+            KeyCode::Left if self.replaying => return Action::Scrub(-1),
+            KeyCode::Right if self.replaying => return Action::Scrub(1),
+            KeyCode::Tab | KeyCode::Right => self.switch_tab(1),
+            KeyCode::BackTab | KeyCode::Left => self.switch_tab(-1),
+            KeyCode::Up => scroll(self.table_state(), -1, usize::MAX),
+            KeyCode::Down => scroll(self.table_state(), 1, usize::MAX),
+            KeyCode::PageUp => scroll(self.table_state(), -page, usize::MAX),
+            KeyCode::PageDown => scroll(self.table_state(), page, usize::MAX),
+            KeyCode::Home => self.table_state().select(Some(0)),
+            KeyCode::End => self.table_state().select(Some(usize::MAX)),
+            _ => {}
+        }
+        Action::None
+    }
+}
+
+/// Moves the selection by `delta` rows, keeping it within the first `len`
+pub fn scroll(state: &mut TableState, delta: isize, len: usize) {
+    let next = state.selected().unwrap_or(0)
+        .saturating_add_signed(delta)
+        .min(len.saturating_sub(1));
+    state.select((len > 0).then_some(next));
+}
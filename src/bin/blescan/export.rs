@@ -0,0 +1,102 @@
+use std::{error::Error, fs::{File, OpenOptions}, io::{self, BufWriter, Write}, path::{Path, PathBuf}, str::FromStr};
+
+use blescan::snapshot::Snapshot;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("unknown export format: {s} (expected json or csv)"))
+        }
+    }
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv"
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+    date_time: String,
+    kind: &'a str,
+    signature: &'a str,
+    rssi: i16
+}
+
+/// Creates `<stem>.<extension>` in `dir`, or if that already exists (e.g. from
+/// exporting twice in the same second) `<stem>-1.<extension>` and so on
+fn create_new(dir: &Path, stem: &str, extension: &str) -> io::Result<(PathBuf, File)> {
+    for attempt in 0.. {
+        let name = if attempt == 0 { format!("{stem}.{extension}") } else { format!("{stem}-{attempt}.{extension}") };
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+            Err(e) => return Err(e)
+        }
+    }
+    unreachable!("ran out of suffixes")
+}
+
+/// Writes `snapshot` to a new file in `dir`, named for when it was taken, and
+/// returns the file's path. Times in the file are formatted by `show_time`.
+pub fn export(snapshot: &Snapshot, dir: &Path, format: ExportFormat, show_time: impl Fn(DateTime<Utc>) -> String, now: DateTime<Utc>) -> Result<PathBuf, Box<dyn Error>> {
+    let (path, file) = create_new(dir, &format!("blescan-{}", now.format("%Y%m%dT%H%M%SZ")), format.extension())?;
+    let mut writer = BufWriter::new(file);
+    let ordered = snapshot.order_by_age_and_volume();
+    let devices = ordered.0.iter().map(|d| Device {
+        date_time: show_time(d.date_time),
+        kind: d.signature.kind(),
+        signature: d.signature.value(),
+        rssi: d.rssi
+    });
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &devices.collect::<Vec<_>>())?;
+            writer.write_all(b"\n")?;
+        },
+        ExportFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(&mut writer);
+            for device in devices {
+                csv.serialize(device)?;
+            }
+            csv.flush()?;
+        }
+    }
+    writer.flush()?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use blescan::snapshot::Snapshot;
+    use chrono::{Utc, TimeZone};
+
+    use super::{export, ExportFormat};
+
+    #[test]
+    fn exporting_twice_in_a_second_keeps_both() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc.timestamp_opt(0, 0).unwrap();
+        let paths : Vec<_> = (0..3).map(|_| export(&Snapshot::default(), dir.path(), ExportFormat::Json, |t| t.to_rfc3339(), now).unwrap()).collect();
+        let names : Vec<_> = paths.iter().map(|p| p.file_name().unwrap().to_string_lossy().to_string()).collect();
+        assert_eq!(names, vec!["blescan-19700101T000000Z.json", "blescan-19700101T000000Z-1.json", "blescan-19700101T000000Z-2.json"]);
+    }
+}
@@ -0,0 +1,56 @@
+use std::{collections::VecDeque, fmt::Debug, sync::{Arc, Mutex, PoisonError}};
+
+use chrono::{DateTime, Local};
+use tracing::{field::{Field, Visit}, Event, Level, Subscriber};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// How many lines to keep for the log pane
+const LOG_SIZE : usize = 500;
+
+#[derive(Clone, Debug)]
+pub struct LogLine {
+    pub date_time: DateTime<Local>,
+    pub level: Level,
+    pub message: String
+}
+
+/// Keeps recent log output, so it can be shown within the UI rather than
+/// being written over it
+#[derive(Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<VecDeque<LogLine>>>);
+
+impl LogBuffer {
+    /// The lines kept, oldest first
+    pub fn lines(&self) -> Vec<LogLine> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).iter().cloned().collect()
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBuffer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        let mut lines = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if lines.len() == LOG_SIZE {
+            lines.pop_front();
+        }
+        lines.push_back(LogLine { date_time: Local::now(), level: *event.metadata().level(), message: message.0 });
+    }
+}
+
+/// Joins an event's fields into one line, with the message first
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            self.0.push_str(&format!("{value:?}"));
+        }
+        else {
+            self.0.push_str(&format!("{}={value:?}", field.name()));
+        }
+    }
+}
@@ -0,0 +1,478 @@
+mod app;
+mod export;
+mod log_pane;
+mod picker;
+mod replay;
+mod scan;
+mod theme;
+mod ui;
+
+use std::{
+    io::{self, Stdout},
+    time::Duration, error::Error, path::{Path, PathBuf}, fs::OpenOptions, sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use blescan::{chrono_extra::TimestampFormat, config::Config, distance::DistanceEstimator, discover_btleplug::{Scanner, DEFAULT_DWELL}, state::State, signature::SignaturePolicy, snapshot::Snapshot, presence::PresenceTracker, rssi_history::RssiHistory, watchlist::Watchlist, history::{EventSink, EventSinkFormat, SinkOptions, rotate::{Rotation, RotationPeriod, parse_size}, noop::NoopEventSink, tee::TeeEventSink, buffered::BufferedEventSink, stats::{InstrumentedEventSink, SharedSinkStats}, encrypt::EncryptionKey, channel::{ChannelEventSink, OverflowPolicy}}, gap::{GapDetector, GapReason, ScanGap}};
+use chrono::{DateTime, Utc};
+use crossterm::{
+    event::{self, Event, KeyCode},
+    execute,
+    style::Print,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::prelude::*;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, parser::ValueSource};
+use tokio::sync::{watch, mpsc::error::TryRecvError};
+use tracing_subscriber::{fmt, filter::LevelFilter, prelude::*};
+
+use app::{App, Action};
+use export::ExportFormat;
+use log_pane::LogBuffer;
+use replay::Replay;
+use scan::ScanControl;
+use theme::{Theme, ThemeName};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// path to record discovery events to (.jsonl, .jsonl.gz, .sqlite or .csv), or an mqtt://, influx://, ws(s):// or http(s):// url;
+    /// can be given more than once to record to several places
+    #[arg(short, long)]
+    record: Vec<String>,
+
+    /// hold back up to this many events before recording them all at once
+    #[arg(long)]
+    buffer: Option<usize>,
+
+    /// the longest to hold back events for, when using --buffer
+    #[arg(long, default_value = "10s")]
+    buffer_interval: humantime::Duration,
+
+    /// record from a separate task, queueing up to this many saves, so that slow sinks don't hold up scanning
+    #[arg(long)]
+    queue: Option<usize>,
+
+    /// what to do when the --queue is full: block, drop-oldest, drop-newest or fail (skip recording that scan)
+    #[arg(long, default_value = "block")]
+    queue_overflow: OverflowPolicy,
+
+    /// start a new .jsonl or .csv file once the current one reaches this size (e.g. 10MB)
+    #[arg(long, value_parser = parse_size)]
+    rotate_size: Option<u64>,
+
+    /// start a new .jsonl or .csv file every hour or day
+    #[arg(long)]
+    rotate_every: Option<RotationPeriod>,
+
+    /// gzip .jsonl or .csv files once they have been rotated
+    #[arg(long)]
+    rotate_compress: bool,
+
+    /// label for where events were recorded from (e.g. "hallway"), stored alongside them
+    #[arg(long)]
+    source: Option<String>,
+
+    /// file to keep events in while an http(s):// endpoint can't be reached
+    #[arg(long)]
+    spool: Option<PathBuf>,
+
+    /// move rotated .jsonl files to an object store, e.g. s3://bucket/prefix
+    #[arg(long)]
+    upload: Option<String>,
+
+    /// file holding a key (64 hex characters) to encrypt .jsonl and .csv recordings with
+    #[arg(long, value_parser = |path: &str| EncryptionKey::from_file(path))]
+    encrypt_key_file: Option<EncryptionKey>,
+
+    /// which Bluetooth adapter to scan with, by its position in the list shown by --pick-adapter or `blescan-cli adapters` (defaults to the last)
+    #[arg(long)]
+    adapter: Option<usize>,
+
+    /// choose the Bluetooth adapter from a list before scanning starts
+    #[arg(long, conflicts_with = "adapter")]
+    pick_adapter: bool,
+
+    /// how long each scan listens for, adjustable while running with +/-
+    #[arg(long, default_value_t = DEFAULT_DWELL.into())]
+    scan_interval: humantime::Duration,
+
+    /// show a recording (.sqlite, .jsonl or .jsonl.gz) instead of scanning, moving through it with ←/→
+    #[arg(long, conflicts_with = "record")]
+    replay: Option<PathBuf>,
+
+    /// how far ←/→ move through a --replay
+    #[arg(long, default_value = "10s")]
+    replay_step: humantime::Duration,
+
+    /// expected RSSI at 1m, for estimating distance (see README)
+    #[arg(long, default_value_t = DistanceEstimator::default().measured_power, allow_negative_numbers = true)]
+    measured_power: f64,
+
+    /// how quickly signals fall off with distance, for estimating it: 2 in free space, up to about 4 indoors
+    #[arg(long, default_value_t = DistanceEstimator::default().path_loss_exponent)]
+    path_loss_exponent: f64,
+
+    /// light, dark, or auto to match the terminal's background (the default)
+    #[arg(long)]
+    theme: Option<ThemeName>,
+
+    /// TOML file with a theme and colors to use, see README
+    #[arg(long)]
+    theme_file: Option<PathBuf>,
+
+    /// directory to write snapshots to, when 'e' is pressed
+    #[arg(long, default_value = ".")]
+    export_dir: PathBuf,
+
+    /// format to write snapshots in: json or csv
+    #[arg(long, default_value = "json")]
+    export_format: ExportFormat,
+
+    /// how times are written in snapshots: iso (RFC 3339), unix (seconds since the epoch) or relative (to when scanning,
+    /// or the recording being replayed, started)
+    #[arg(long, default_value = "iso")]
+    timestamps: TimestampFormat,
+
+    /// file listing devices to watch, one name or digest per line; watched devices are pinned to the top, and alerted on when they arrive or depart
+    #[arg(long)]
+    watchlist: Option<PathBuf>,
+
+    /// don't ring the terminal bell when a watched device arrives or departs
+    #[arg(long)]
+    no_beep: bool,
+
+    /// how anonymous signatures are derived: md5, xxh3, hmac:<key-file>, or hmac to take the key from $BLESCAN_SIGNATURE_KEY
+    #[arg(long, default_value = "md5")]
+    signature_policy: SignaturePolicy,
+
+    /// settings file to use instead of ~/.config/blescan/config.toml, see README
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// also append diagnostics to this file, as well as showing them in the log pane
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+}
+
+impl Args {
+    /// Fills in anything not given on the command line from `config`
+    fn apply_config(&mut self, config: &Config, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+        if !self.pick_adapter {
+            self.adapter = self.adapter.or(config.adapter);
+        }
+        if self.record.is_empty() && self.replay.is_none() {
+            self.record.clone_from(&config.record);
+        }
+        if let Some(policy) = config.signature_policy.as_ref().filter(|_| matches.value_source("signature_policy") != Some(ValueSource::CommandLine)) {
+            self.signature_policy = policy.parse()?;
+        }
+        if self.theme.is_none() {
+            self.theme = config.theme.as_deref().map(str::parse).transpose()?;
+        }
+        self.theme_file = self.theme_file.take().or_else(|| config.theme_file.clone());
+        self.watchlist = self.watchlist.take().or_else(|| config.watchlist.clone());
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    args.apply_config(&Config::load(args.config.as_deref())?, &matches)?;
+    // logged within the UI, as anything written to the terminal would be drawn over
+    let log = LogBuffer::default();
+    let log_file = args.log_file.as_ref()
+        .map(|path| OpenOptions::new().create(true).append(true).open(path))
+        .transpose()?;
+    tracing_subscriber::registry()
+        .with(log.clone().with_filter(LevelFilter::INFO))
+        .with(log_file.map(|file| fmt::layer().with_ansi(false).with_writer(Mutex::new(file)).with_filter(LevelFilter::INFO)))
+        .init();
+    let theme = match &args.theme_file {
+        Some(path) => Theme::from_file(path, args.theme)?,
+        None => Theme::named(args.theme.unwrap_or_default())
+    };
+    let watchlist = match &args.watchlist {
+        Some(path) => Watchlist::from_file(path)?,
+        None => Watchlist::default()
+    };
+    let mut app = App::new(Utc::now(), theme, watchlist, *args.scan_interval);
+    app.log = log;
+    app.estimator = DistanceEstimator { measured_power: args.measured_power, path_loss_exponent: args.path_loss_exponent };
+    if let Some(path) = &args.replay {
+        let replay = Replay::load(path, chrono::Duration::from_std(*args.replay_step)?, chrono::Duration::minutes(5), chrono::Duration::seconds(60)).await?;
+        let mut terminal = setup_terminal().context("setup failed")?;
+        run_replay(replay, &args, app, &mut terminal).await?;
+        restore_terminal(&mut terminal).context("restore terminal failed")?;
+        return Ok(());
+    }
+    let adapters = if args.pick_adapter { Scanner::adapters().await? } else { vec![] };
+    let mut terminal = setup_terminal().context("setup failed")?;
+    let adapter = if args.pick_adapter {
+        match picker::pick(&mut terminal, "Bluetooth adapters", &adapters, &app.theme)? {
+            Some(index) => Some(index),
+            None => return restore_terminal(&mut terminal).map_err(Into::into)
+        }
+    }
+    else {
+        args.adapter
+    };
+    let last_recorded = last_recorded(&args).await;
+    let (mut sink, stats) = sink(&args).await?;
+    run(&mut sink, stats.as_ref(), &args, adapter, last_recorded, app, &mut terminal).await?;
+    sink.close().await?;
+    restore_terminal(&mut terminal).context("restore terminal failed")?;
+    Ok(())
+}
+
+fn sink_options(args: &Args) -> SinkOptions {
+    let rotation = if args.rotate_size.is_some() || args.rotate_every.is_some() {
+        Some(Rotation { 
+            max_bytes: args.rotate_size, 
+            period: args.rotate_every, 
+            compress: args.rotate_compress 
+        })
+    }
+    else {
+        None
+    };
+    SinkOptions {
+        signature_policy: Some(args.signature_policy.id()),
+        rotation,
+        source: args.source.clone(),
+        spool: args.spool.clone(),
+        encryption: args.encrypt_key_file.clone(),
+        upload: args.upload.clone()
+    }
+}
+
+/// The sink to record to, and its stats if recording. The stats are taken
+/// from the sinks actually being written to, underneath any buffering or
+/// queueing, so they don't count saves which haven't been written yet, or
+/// which have been dropped.
+async fn sink(args: &Args) -> Result<(Box<dyn EventSink>, Option<SharedSinkStats>), Box<dyn Error>> {
+    let options = sink_options(args);
+    let mut sinks : Vec<Box<dyn EventSink>> = vec![];
+    for name in &args.record {
+        let path = Path::new(&name);
+        let sink_format = EventSinkFormat::create_from_file(path)?;
+        sinks.push(sink_format.to_sink(&options).await?);
+    }
+    let sink : Box<dyn EventSink> = match sinks.len() {
+        0 => return Ok((Box::<NoopEventSink>::default(), None)),
+        1 => sinks.remove(0),
+        _ => Box::new(TeeEventSink::create_from_sinks(sinks))
+    };
+    let sink = InstrumentedEventSink::create_from_sink(sink);
+    let stats = sink.stats();
+    let sink : Box<dyn EventSink> = Box::new(sink);
+    let sink : Box<dyn EventSink> = match args.buffer {
+        Some(max_events) => Box::new(BufferedEventSink::create_from_sink(sink, max_events, *args.buffer_interval)),
+        None => sink
+    };
+    let sink : Box<dyn EventSink> = match args.queue {
+        Some(capacity) => Box::new(ChannelEventSink::create_from_sink(sink, capacity, args.queue_overflow)),
+        None => sink
+    };
+    Ok((sink, Some(stats)))
+}
+
+/// When the files being recorded to were last recorded to, if they already
+/// have anything in them, so that the time since then can be recorded as a gap
+async fn last_recorded(args: &Args) -> Option<DateTime<Utc>> {
+    if args.encrypt_key_file.is_some() {
+        // can't be read back without decrypting
+        return None;
+    }
+    let mut last = None;
+    for name in &args.record {
+        let path = Path::new(name);
+        if !path.is_file() {
+            continue;
+        }
+        match recorded_until(path).await {
+            Ok(until) => last = last.max(until),
+            // e.g. .csv, which can't be read back
+            Err(blescan::error::Error::Unsupported(_)) => {},
+            Err(e) => tracing::warn!("can't tell when {} was last recorded to: {e}", path.display())
+        }
+    }
+    last
+}
+
+/// The end of the last event or gap recorded in `path`
+async fn recorded_until(path: &Path) -> Result<Option<DateTime<Utc>>, blescan::error::Error> {
+    let source = EventSinkFormat::create_from_file(path)?.to_source().await?;
+    let Some(range) = source.time_range().await? else {
+        return Ok(None);
+    };
+    let gaps = source.gaps(range.clone()).await?;
+    Ok(gaps.iter().map(|gap| gap.end).chain([range.end]).max())
+}
+
+fn setup_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>> {
+    let mut stdout = io::stdout();
+    enable_raw_mode().context("failed to enable raw mode")?;
+    execute!(stdout, EnterAlternateScreen).context("unable to enter alternate screen")?;
+    Terminal::new(CrosstermBackend::new(stdout)).context("creating terminal failed")
+}
+
+fn restore_terminal(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
+    disable_raw_mode().context("failed to disable raw mode")?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)
+        .context("unable to switch to main screen")?;
+    terminal.show_cursor().context("unable to show cursor")
+}
+
+async fn run(sink: &mut Box<dyn EventSink>, stats: Option<&SharedSinkStats>, args: &Args, adapter: Option<usize>, last_recorded: Option<DateTime<Utc>>, mut app: App, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<(), Box<dyn Error>> {
+    let scanner = Scanner::new_with_adapter(args.signature_policy.clone(), adapter).await?;
+    let mut state = State::default();
+    let (control, control_receiver) = watch::channel(ScanControl { paused: app.paused, dwell: app.scan_interval });
+    let mut scans = scan::spawn(scanner, control_receiver);
+    let mut previous_snapshot = Snapshot::default();
+    let mut gap_detector = gap_detector(app.scan_interval)?;
+    if let Some(last_recorded) = last_recorded {
+        gap_detector.resume(last_recorded);
+    }
+    let mut presence = PresenceTracker::new(chrono::Duration::seconds(60));
+    let mut history = RssiHistory::new(chrono::Duration::minutes(5));
+    let mut current_snapshot = state.snapshot();
+    loop {
+        let now = Utc::now();
+        if app.log_presence(presence.update(&current_snapshot, now), now) == Action::Alert && !args.no_beep {
+            execute!(terminal.backend_mut(), Print('\x07'))?;
+        }
+        terminal.draw(|f| ui::draw(f, &mut app, now, &current_snapshot, &previous_snapshot, &history, stats))?;
+        if let Some(key) = next_key()? {
+            match app.on_key(key) {
+                Action::Quit => break,
+                Action::TogglePause if app.paused => gap_detector.interrupt(GapReason::Paused, Utc::now()),
+                Action::Export => export(&mut app, &current_snapshot, args, Utc::now()),
+                Action::Bookmark => bookmark(&mut app, &current_snapshot, now),
+                _ => {}
+            }
+        }
+        let wanted = ScanControl { paused: app.paused, dwell: app.scan_interval };
+        if *control.borrow() != wanted {
+            control.send_replace(wanted);
+            gap_detector.set_expected_interval(chrono::Duration::from_std(app.scan_interval)?);
+        }
+        let result = match scans.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => continue,
+            Err(TryRecvError::Disconnected) => return Err("scanning stopped unexpectedly".into())
+        };
+        if app.paused {
+            // a scan which was already under way when paused
+            continue;
+        }
+        let events = match result {
+            Ok((events, manufacturers)) => {
+                app.manufacturers.extend(manufacturers);
+                events
+            },
+            Err(e) => {
+                tracing::warn!("scan failed: {e}");
+                app.failed_scans += 1;
+                gap_detector.interrupt(GapReason::ScanFailed, Utc::now());
+                continue;
+            }
+        };
+        app.scans += 1;
+        if let Some(gap) = gap_detector.observe(Utc::now()) {
+            save_gap(sink, &gap).await?;
+        }
+        match sink.save(&events).await {
+            Err(blescan::error::Error::SinkFull) => tracing::warn!("recording queue full, {} events not recorded", events.len()),
+            result => result?
+        }
+        state.discover(&events);
+        history.record(&events);
+        previous_snapshot = std::mem::replace(&mut current_snapshot, state.snapshot());
+    }
+    if let Some(gap) = gap_detector.finish(Utc::now()) {
+        save_gap(sink, &gap).await?;
+    }
+    Ok(())
+}
+
+async fn save_gap(sink: &mut Box<dyn EventSink>, gap: &ScanGap) -> Result<(), blescan::error::Error> {
+    match sink.save_gap(gap).await {
+        Err(blescan::error::Error::SinkFull) => {
+            tracing::warn!("recording queue full, gap from {} not recorded", gap.start);
+            Ok(())
+        },
+        result => result
+    }
+}
+
+async fn run_replay(mut replay: Replay, args: &Args, mut app: App, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<(), Box<dyn Error>> {
+    app.start = replay.range.start;
+    app.replaying = true;
+    loop {
+        // only rebuilt when moving through the recording, as nothing else changes it
+        let now = replay.position;
+        app.presence_log = replay.presence_log();
+        while replay.position == now {
+            terminal.draw(|f| ui::draw(f, &mut app, now, replay.snapshot(), replay.previous_snapshot(), replay.rssi_history(), None))?;
+            let Some(key) = next_key()? else {
+                continue;
+            };
+            match app.on_key(key) {
+                Action::Quit => return Ok(()),
+                Action::Scrub(steps) => replay.scrub(steps).await?,
+                Action::Export => export(&mut app, replay.snapshot(), args, now),
+                Action::Bookmark => bookmark(&mut app, replay.snapshot(), now),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn export(app: &mut App, snapshot: &Snapshot, args: &Args, now: chrono::DateTime<Utc>) {
+    let message = match export::export(snapshot, &args.export_dir, args.export_format, |t| args.timestamps.format(t, app.start), now) {
+        Ok(path) => format!("Exported {} devices to {}", snapshot.0.len(), path.display()),
+        Err(e) => format!("Export failed: {e}")
+    };
+    app.show_toast(message, Utc::now());
+}
+
+fn bookmark(app: &mut App, snapshot: &Snapshot, now: chrono::DateTime<Utc>) {
+    app.bookmark = Some((now, snapshot.clone()));
+    app.show_toast(format!("Bookmarked {} devices, press 7 to compare", snapshot.0.len()), Utc::now());
+}
+
+/// Expects scans to be as far apart as they have been set to be, so that a long
+/// scan interval isn't taken for scanning having stalled
+fn gap_detector(scan_interval: Duration) -> Result<GapDetector, Box<dyn Error>> {
+    Ok(GapDetector::new(chrono::Duration::from_std(scan_interval)?, chrono::Duration::seconds(5)))
+}
+
+fn next_key() -> Result<Option<KeyCode>> {
+    if event::poll(Duration::from_millis(250)).context("event poll failed")? {
+        if let Event::Key(key) = event::read().context("event read failed")? {
+            return Ok(Some(key.code));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use chrono::{Utc, TimeZone};
+
+    use super::gap_detector;
+
+    #[test]
+    fn long_scan_intervals_are_not_stalls() {
+        let mut detector = gap_detector(Duration::from_secs(30)).unwrap();
+        for seconds in [0, 31, 62, 93] {
+            assert_eq!(detector.observe(Utc.timestamp_opt(seconds, 0).unwrap()), None);
+        }
+        assert!(detector.observe(Utc.timestamp_opt(200, 0).unwrap()).is_some());
+    }
+}
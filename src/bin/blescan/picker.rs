@@ -0,0 +1,42 @@
+use std::{error::Error, io::Stdout};
+
+use crossterm::event::KeyCode;
+use ratatui::{prelude::*, widgets::{Block, Borders, List, ListItem, ListState, Paragraph}};
+
+use crate::{next_key, theme::Theme};
+
+/// Asks which of `options` to use, returning its index, or `None` if the
+/// user quit instead
+pub fn pick(terminal: &mut Terminal<CrosstermBackend<Stdout>>, title: &str, options: &[String], theme: &Theme) -> Result<Option<usize>, Box<dyn Error>> {
+    let mut state = ListState::default();
+    state.select(Some(options.len().saturating_sub(1)));
+    loop {
+        terminal.draw(|f| draw(f, title, options, theme, &mut state))?;
+        let selected = state.selected().unwrap_or(0);
+        match next_key()? {
+            Some(KeyCode::Char('q') | KeyCode::Esc) => return Ok(None),
+            Some(KeyCode::Enter) if !options.is_empty() => return Ok(Some(selected)),
+            Some(KeyCode::Up) => state.select(Some(selected.saturating_sub(1))),
+            Some(KeyCode::Down) => state.select(Some((selected + 1).min(options.len().saturating_sub(1)))),
+            _ => {}
+        }
+    }
+}
+
+fn draw<B: Backend>(f: &mut Frame<'_, B>, title: &str, options: &[String], theme: &Theme, state: &mut ListState) {
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
+        .split(f.size());
+    let help = Paragraph::new("(press ↑↓ to choose, enter to start scanning, 'q' to quit)")
+        .block(Block::default().title("blescan").borders(Borders::ALL))
+        .style(Style::default().fg(theme.text));
+    f.render_widget(help, layout[0]);
+    let items : Vec<ListItem<'_>> = options.iter().map(|o| ListItem::new(o.as_str())).collect();
+    let list = List::new(items)
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .style(Style::default().fg(theme.text))
+        .highlight_style(Style::default().fg(theme.selected_tab).add_modifier(Modifier::REVERSED));
+    f.render_stateful_widget(list, layout[1], state);
+}
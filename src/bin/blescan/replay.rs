@@ -0,0 +1,201 @@
+use std::{collections::VecDeque, error::Error, ops::Range, path::Path, sync::Arc};
+
+use blescan::{discover::DiscoveryEvent, gap::ScanGap, history::{EventSinkFormat, EventSource}, presence::{PresenceChange, PresenceEvent, SessionBuilder}, rssi_history::RssiHistory, snapshot::Snapshot, state::State};
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use tokio::sync::mpsc;
+
+const CHUNK_SIZE : usize = 10_000;
+
+type Chunk = Result<Vec<DiscoveryEvent>, blescan::error::Error>;
+
+/// A recording, and how far through it we are. Events are read as the
+/// position moves forwards, and only read again from the start when it moves
+/// backwards.
+pub struct Replay {
+    source: Arc<dyn EventSource>,
+    gaps: Vec<ScanGap>,
+    pub range: Range<DateTime<Utc>>,
+    pub position: DateTime<Utc>,
+    step: Duration,
+    /// how far back the RSSI history goes
+    max_age: Duration,
+    /// how long a device goes unseen before it has departed
+    max_absence: Duration,
+    played: Played
+}
+
+impl Replay {
+    /// Loads `path` (.sqlite, .jsonl or .jsonl.gz), starting at its beginning,
+    /// and moving `step` at a time
+    pub async fn load(path: &Path, step: Duration, max_age: Duration, max_absence: Duration) -> Result<Replay, Box<dyn Error>> {
+        let source : Arc<dyn EventSource> = EventSinkFormat::create_from_file(path)?.to_source().await?.into();
+        let Some(range) = source.time_range().await? else {
+            return Err(format!("no events recorded in {}", path.display()).into());
+        };
+        let gaps = source.gaps(range.clone()).await?;
+        let played = Played::new(source.clone(), range.clone(), gaps.clone(), max_age, max_absence);
+        let mut replay = Replay { source, gaps, position: range.start, range, step, max_age, max_absence, played };
+        replay.play().await?;
+        Ok(replay)
+    }
+
+    /// Moves `steps` forwards (or backwards, if negative), staying within the recording
+    pub async fn scrub(&mut self, steps: i32) -> Result<(), blescan::error::Error> {
+        let position = self.position + self.step * steps;
+        self.position = position.clamp(self.range.start, self.range.end);
+        self.play().await
+    }
+
+    /// Brings what has been played up to the current position, starting again
+    /// if it has moved back past the step before it
+    async fn play(&mut self) -> Result<(), blescan::error::Error> {
+        if self.played.until == Some(self.position) {
+            return Ok(());
+        }
+        let previous = self.position - self.step;
+        if self.played.until.is_some_and(|until| until > previous) {
+            self.played = Played::new(self.source.clone(), self.range.clone(), self.gaps.clone(), self.max_age, self.max_absence);
+        }
+        self.played.previous = if self.played.until == Some(previous) {
+            std::mem::take(&mut self.played.current)
+        }
+        else {
+            self.played.play_until(previous).await?;
+            self.played.state.snapshot()
+        };
+        self.played.play_until(self.position).await?;
+        self.played.current = self.played.state.snapshot();
+        Ok(())
+    }
+
+    /// The snapshot as it would have been shown at the current position
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.played.current
+    }
+
+    /// The snapshot one step before the current position, to compare against
+    pub fn previous_snapshot(&self) -> &Snapshot {
+        &self.played.previous
+    }
+
+    pub fn rssi_history(&self) -> &RssiHistory {
+        &self.played.history
+    }
+
+    /// Arrivals and departures up to the current position, oldest first
+    pub fn presence_log(&self) -> Vec<PresenceEvent> {
+        let mut log = vec![];
+        for session in self.played.sessions.sessions() {
+            log.push(PresenceEvent { date_time: session.start, signature: session.signature.clone(), change: PresenceChange::Arrived });
+            if self.position - session.end > self.max_absence {
+                log.push(PresenceEvent { date_time: session.end, signature: session.signature, change: PresenceChange::Departed });
+            }
+        }
+        log.sort_by(|a, b| a.date_time.cmp(&b.date_time).then_with(|| a.signature.cmp(&b.signature)));
+        log
+    }
+}
+
+/// The recording as played up to and including `until`
+struct Played {
+    until: Option<DateTime<Utc>>,
+    chunks: mpsc::Receiver<Chunk>,
+    /// read, but after `until`
+    unplayed: VecDeque<DiscoveryEvent>,
+    state: State,
+    history: RssiHistory,
+    sessions: SessionBuilder,
+    current: Snapshot,
+    previous: Snapshot
+}
+
+impl Played {
+    fn new(source: Arc<dyn EventSource>, range: Range<DateTime<Utc>>, gaps: Vec<ScanGap>, max_age: Duration, max_absence: Duration) -> Played {
+        Played {
+            until: None,
+            chunks: read(source, range),
+            unplayed: VecDeque::new(),
+            state: State::default(),
+            history: RssiHistory::new(max_age),
+            sessions: SessionBuilder::new(gaps, max_absence),
+            current: Snapshot::default(),
+            previous: Snapshot::default()
+        }
+    }
+
+    async fn play_until(&mut self, at: DateTime<Utc>) -> Result<(), blescan::error::Error> {
+        loop {
+            let mut batch = vec![];
+            while self.unplayed.front().is_some_and(|e| e.date_time <= at) {
+                batch.push(self.unplayed.pop_front().expect("just checked"));
+            }
+            self.state.discover(&batch);
+            self.history.record(&batch);
+            self.sessions.add(&batch);
+            if !self.unplayed.is_empty() {
+                break;
+            }
+            match self.chunks.recv().await {
+                Some(chunk) => self.unplayed = chunk?.into(),
+                None => break
+            }
+        }
+        self.until = Some(at);
+        Ok(())
+    }
+}
+
+/// Reads `range` from `source` on a task of its own, a chunk at a time, as the
+/// chunks are taken. Stops once the returned receiver is dropped.
+fn read(source: Arc<dyn EventSource>, range: Range<DateTime<Utc>>) -> mpsc::Receiver<Chunk> {
+    let (chunks, receiver) = mpsc::channel(1);
+    tokio::spawn(async move {
+        let mut events = source.stream_events(range, CHUNK_SIZE);
+        while let Some(chunk) = events.try_next().await.transpose() {
+            if chunks.send(chunk).await.is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod test {
+    use blescan::{discover::DiscoveryEvent, history::{EventSinkFormat, SinkOptions}, signature::Signature, presence::PresenceChange};
+    use chrono::{Duration, Utc, TimeZone};
+
+    use super::Replay;
+
+    #[tokio::test]
+    async fn step_forwards_and_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let mut sink = EventSinkFormat::create_from_file(&path).unwrap().to_sink(&SinkOptions::default()).await.unwrap();
+        for seconds in [0, 10, 20, 30] {
+            if seconds == 30 {
+                sink.save(&[DiscoveryEvent::new(Utc.timestamp_opt(25, 0).unwrap(), Signature::Named("2".to_string()), -50)]).await.unwrap();
+            }
+            sink.save(&[DiscoveryEvent::new(Utc.timestamp_opt(seconds, 0).unwrap(), Signature::Named("1".to_string()), -10 - seconds as i16)]).await.unwrap();
+        }
+        sink.close().await.unwrap();
+
+        let mut replay = Replay::load(&path, Duration::seconds(10), Duration::minutes(5), Duration::seconds(60)).await.unwrap();
+        let rssi = |replay: &Replay| replay.snapshot().0.iter().map(|d| d.rssi).collect::<Vec<i16>>();
+        assert_eq!(rssi(&replay), vec![-10]);
+        assert!(replay.previous_snapshot().0.is_empty());
+
+        replay.scrub(2).await.unwrap();
+        assert_eq!(rssi(&replay), vec![-30]);
+        replay.scrub(1).await.unwrap();
+        assert_eq!(rssi(&replay), vec![-40, -50]);
+        assert_eq!(replay.previous_snapshot().0.iter().map(|d| d.rssi).collect::<Vec<i16>>(), vec![-30]);
+        assert_eq!(replay.presence_log().iter().map(|e| e.change).collect::<Vec<PresenceChange>>(), vec![PresenceChange::Arrived; 2]);
+
+        replay.scrub(-2).await.unwrap();
+        assert_eq!(rssi(&replay), vec![-20]);
+        assert_eq!(replay.previous_snapshot().0.iter().map(|d| d.rssi).collect::<Vec<i16>>(), vec![-10]);
+        assert_eq!(replay.rssi_history().readings(&Signature::Named("1".to_string())).count(), 2);
+    }
+}
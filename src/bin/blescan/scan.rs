@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use blescan::{discover::DiscoveryEvent, discover_btleplug::Scanner, error::Error, manufacturer::Manufacturers};
+use tokio::sync::{mpsc, watch};
+
+/// What the scanning task should be doing, as set from the UI
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct ScanControl {
+    pub paused: bool,
+    pub dwell: Duration
+}
+
+pub type ScanResult = Result<(Vec<DiscoveryEvent>, Manufacturers), Error>;
+
+/// Scans on a task of its own, so that drawing and key presses aren't held up
+/// waiting for a scan to finish. Stops once the returned receiver is dropped.
+pub fn spawn(mut scanner: Scanner, mut control: watch::Receiver<ScanControl>) -> mpsc::Receiver<ScanResult> {
+    let (results, receiver) = mpsc::channel(1);
+    tokio::spawn(async move {
+        loop {
+            let ScanControl { paused, dwell } = *control.borrow_and_update();
+            if paused {
+                if control.changed().await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            scanner.set_dwell(dwell);
+            let result = scanner.scan_with_manufacturers().await;
+            if results.send(result).await.is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
@@ -0,0 +1,192 @@
+use std::{error::Error, fs, path::Path, str::FromStr};
+
+use ratatui::style::Color;
+use serde::Deserialize;
+
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum ThemeName {
+    Light,
+    Dark,
+    /// pick light or dark to suit the terminal's background
+    #[default]
+    Auto
+}
+
+impl FromStr for ThemeName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "light" => Ok(ThemeName::Light),
+            "dark" => Ok(ThemeName::Dark),
+            "auto" => Ok(ThemeName::Auto),
+            _ => Err(format!("unknown theme: {s} (expected light, dark or auto)"))
+        }
+    }
+}
+
+/// The colors used throughout the UI
+#[derive(PartialEq, Debug, Clone)]
+pub struct Theme {
+    pub text: Color,
+    pub header: Color,
+    pub selected_tab: Color,
+    pub new_device: Color,
+    pub arrived: Color,
+    pub departed: Color
+}
+
+impl Theme {
+    pub fn light() -> Theme {
+        Theme {
+            text: Color::Black,
+            header: Color::Yellow,
+            selected_tab: Color::Yellow,
+            new_device: Color::Red,
+            arrived: Color::Green,
+            departed: Color::Red
+        }
+    }
+
+    pub fn dark() -> Theme {
+        Theme {
+            text: Color::White,
+            header: Color::LightYellow,
+            selected_tab: Color::LightYellow,
+            new_device: Color::LightRed,
+            arrived: Color::LightGreen,
+            departed: Color::LightRed
+        }
+    }
+
+    /// For when the background isn't known: the terminal's own text color,
+    /// and colors which can be read against light or dark backgrounds
+    pub fn terminal() -> Theme {
+        Theme {
+            text: Color::Reset,
+            header: Color::Yellow,
+            selected_tab: Color::Yellow,
+            new_device: Color::Red,
+            arrived: Color::Green,
+            departed: Color::Red
+        }
+    }
+
+    pub fn named(name: ThemeName) -> Theme {
+        match name {
+            ThemeName::Light => Theme::light(),
+            ThemeName::Dark => Theme::dark(),
+            ThemeName::Auto => match background_is_dark() {
+                Some(true) => Theme::dark(),
+                Some(false) => Theme::light(),
+                None => Theme::terminal()
+            }
+        }
+    }
+
+    /// Loads a theme from a TOML file like:
+    ///
+    /// ```toml
+    /// theme = "dark"
+    ///
+    /// [colors]
+    /// text = "white"
+    /// new_device = "#ff8800"
+    /// ```
+    ///
+    /// where `theme` picks the starting point, and `[colors]` overrides any of
+    /// its colors. `name`, if given, takes precedence over `theme`.
+    pub fn from_file(path: &Path, name: Option<ThemeName>) -> Result<Theme, Box<dyn Error>> {
+        let config : ThemeConfig = toml::from_str(&fs::read_to_string(path)?)?;
+        let name = match (name, config.theme) {
+            (Some(name), _) => name,
+            (None, Some(theme)) => theme.parse()?,
+            (None, None) => ThemeName::default()
+        };
+        let mut theme = Theme::named(name);
+        let colors = config.colors;
+        for (color, value) in [
+            (&mut theme.text, colors.text),
+            (&mut theme.header, colors.header),
+            (&mut theme.selected_tab, colors.selected_tab),
+            (&mut theme.new_device, colors.new_device),
+            (&mut theme.arrived, colors.arrived),
+            (&mut theme.departed, colors.departed)
+        ] {
+            if let Some(value) = value {
+                *color = Color::from_str(&value).map_err(|_| format!("unknown color: {value}"))?;
+            }
+        }
+        Ok(theme)
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct ThemeConfig {
+    theme: Option<String>,
+    #[serde(default)]
+    colors: ColorsConfig
+}
+
+#[derive(Deserialize, Default)]
+struct ColorsConfig {
+    text: Option<String>,
+    header: Option<String>,
+    selected_tab: Option<String>,
+    new_device: Option<String>,
+    arrived: Option<String>,
+    departed: Option<String>
+}
+
+/// Many terminals describe their colors in `COLORFGBG`, as e.g. `15;0`, where
+/// the last part is the background's ANSI color
+fn background_is_dark() -> Option<bool> {
+    colorfgbg_is_dark(&std::env::var("COLORFGBG").ok()?)
+}
+
+fn colorfgbg_is_dark(value: &str) -> Option<bool> {
+    let background : u8 = value.rsplit(';').next()?.parse().ok()?;
+    Some(matches!(background, 0..=6 | 8))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use ratatui::style::Color;
+
+    use super::{colorfgbg_is_dark, Theme, ThemeName};
+
+    #[test]
+    fn background_from_colorfgbg() {
+        assert_eq!(colorfgbg_is_dark("15;0"), Some(true));
+        assert_eq!(colorfgbg_is_dark("15;default;8"), Some(true));
+        assert_eq!(colorfgbg_is_dark("0;15"), Some(false));
+        assert_eq!(colorfgbg_is_dark("0;7"), Some(false));
+        assert_eq!(colorfgbg_is_dark("default;default"), None);
+        assert_eq!(colorfgbg_is_dark(""), None);
+    }
+
+    #[test]
+    fn override_colors_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("theme.toml");
+        fs::write(&path, "theme = \"dark\"\n\n[colors]\ntext = \"black\"\nnew_device = \"#ff8800\"\n").unwrap();
+
+        let theme = Theme::from_file(&path, None).unwrap();
+        assert_eq!(theme, Theme { text: Color::Black, new_device: Color::Rgb(0xff, 0x88, 0x00), ..Theme::dark() });
+        // a name given on the command line wins over the file's
+        let theme = Theme::from_file(&path, Some(ThemeName::Light)).unwrap();
+        assert_eq!(theme, Theme { text: Color::Black, new_device: Color::Rgb(0xff, 0x88, 0x00), ..Theme::light() });
+    }
+
+    #[test]
+    fn reject_unknown_colors_and_themes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("theme.toml");
+        fs::write(&path, "[colors]\ntext = \"plaid\"\n").unwrap();
+        assert_eq!(Theme::from_file(&path, Some(ThemeName::Dark)).unwrap_err().to_string(), "unknown color: plaid");
+        fs::write(&path, "theme = \"neon\"\n").unwrap();
+        assert!(Theme::from_file(&path, None).is_err());
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::{BTreeMap, HashSet};
+
+use blescan::{distance::DistanceEstimator, manufacturer::{manufacturer_name, Manufacturers}, signature::Signature, snapshot::{Snapshot, RssiComparison, Comparison, Difference}, device_state::DeviceState, presence::PresenceChange, history::stats::SharedSinkStats, rssi_history::RssiHistory};
+use chrono::{Utc, DateTime};
+use humantime::FormattedDuration;
+use ratatui::{prelude::*, widgets::{Paragraph, Row, Table, Cell, TableState, Scrollbar, ScrollbarOrientation, ScrollbarState, Tabs, Clear, Wrap, Chart, Dataset, Axis, GraphType}, symbols};
+use ratatui::{
+    layout::{Constraint, Direction, Layout},
+    widgets::{Block, Borders}
+};
+
+use tracing::Level;
+
+use crate::{app::{App, RssiMode, Tab, Toast, scroll}, theme::Theme};
+
+/// Draws everything as of `now`, which is only not the actual time when replaying
+pub fn draw<B: Backend>(f: &mut Frame<'_, B>, app: &mut App, now: DateTime<Utc>, current: &Snapshot, previous: &Snapshot, history: &RssiHistory, stats: Option<&SharedSinkStats>) {
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([Constraint::Length(6), Constraint::Length(3), Constraint::Min(0)].as_ref())
+        .split(f.size());
+
+    let footer = Paragraph::new(context(app, now, stats))
+        .wrap(Wrap { trim: true })
+        .block(Block::default().title("Context").borders(Borders::ALL))
+        .style(Style::default().fg(app.theme.text));
+    f.render_widget(footer, layout[0]);
+
+    let titles : Vec<Line<'_>> = Tab::ALL.iter()
+        .enumerate()
+        .map(|(i, tab)| Line::from(format!("{} {}", i + 1, tab.title())))
+        .collect();
+    let tabs = Tabs::new(titles)
+        .block(Block::default().borders(Borders::ALL))
+        .select(Tab::ALL.iter().position(|t| *t == app.tab).unwrap_or(0))
+        .style(Style::default().fg(app.theme.text))
+        .highlight_style(Style::default().fg(app.theme.selected_tab).add_modifier(Modifier::BOLD));
+    f.render_widget(tabs, layout[1]);
+
+    let area = if app.show_log {
+        let split = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(LOG_PANE_HEIGHT)].as_ref())
+            .split(layout[2]);
+        log_pane(f, split[1], app);
+        split[0]
+    }
+    else {
+        layout[2]
+    };
+    // less the borders and the header
+    app.page = usize::from(area.height.saturating_sub(4)).max(1);
+    match app.tab {
+        Tab::All | Tab::Named | Tab::Anonymous => device_table(f, area, app, current, previous, history, now),
+        Tab::Presence => presence_table(f, area, app),
+        Tab::Stats => stats_view(f, area, app, current, stats),
+        Tab::Chart => rssi_chart(f, area, app, history, now),
+        Tab::Compare => compare_table(f, area, app, current)
+    }
+
+    // toasts come and go in real time, even when replaying
+    if let Some(t) = app.current_toast(Utc::now()) {
+        toast(f, area, t, &app.theme);
+    }
+}
+
+/// Shows `message` in a box along the bottom of `area`, over whatever is there
+/// Alerts flash, to catch the eye
+fn toast<B: Backend>(f: &mut Frame<'_, B>, area: Rect, toast: &Toast, theme: &Theme) {
+    let width = u16::try_from(toast.message.chars().count()).unwrap_or(u16::MAX).saturating_add(4).min(area.width);
+    let height = 3.min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + area.height - height,
+        width,
+        height
+    };
+    let style = if toast.alerting.is_empty() {
+        Style::default().fg(theme.header).add_modifier(Modifier::BOLD)
+    }
+    else {
+        Style::default().fg(theme.new_device).add_modifier(Modifier::BOLD | Modifier::SLOW_BLINK)
+    };
+    let paragraph = Paragraph::new(toast.message.clone())
+        .block(Block::default().borders(Borders::ALL))
+        .style(style);
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
+
+/// Including its borders
+const LOG_PANE_HEIGHT : u16 = 8;
+
+/// The most recent log lines, as many as fit
+fn log_pane<B: Backend>(f: &mut Frame<'_, B>, area: Rect, app: &App) {
+    let lines = app.log.lines();
+    let shown = usize::from(area.height.saturating_sub(2));
+    let lines : Vec<Line<'_>> = lines[lines.len().saturating_sub(shown)..].iter()
+        .map(|l| {
+            let style = match l.level {
+                Level::ERROR => Style::default().fg(app.theme.departed),
+                Level::WARN => Style::default().fg(app.theme.header),
+                _ => Style::default().fg(app.theme.text)
+            };
+            Line::styled(format!("{} {:>5} {}", l.date_time.format("%H:%M:%S"), l.level, l.message), style)
+        })
+        .collect();
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().title("Log (press 'l' to hide)").borders(Borders::ALL))
+        .style(Style::default().fg(app.theme.text));
+    f.render_widget(paragraph, area);
+}
+
+fn context(app: &App, now: DateTime<Utc>, stats: Option<&SharedSinkStats>) -> String {
+    use humantime::format_duration;
+    use blescan::chrono_extra::Truncate;
+
+    let runtime = format_duration((now - app.start).truncate_to_seconds().to_std().unwrap_or_default());
+    if app.replaying {
+        return format!("REPLAY, At: {now}, Time into recording: {runtime}\n\nKeys: ←/→ move through the recording, 1-7/tab view, ↑↓/PgUp/PgDn scroll, c chart, w watch, g group, d rssi/average/distance, b bookmark, e export, l log, q quit");
+    }
+    let status = if app.paused { "PAUSED, " } else { "" };
+    let interval = format_duration(app.scan_interval);
+    format!("{status}Now: {now}, Total Run time: {runtime}, Scan interval: {interval}\n{}\nKeys: 1-7/tab view, ↑↓/PgUp/PgDn scroll, c chart, w watch, g group, d rssi/average/distance, p pause, +/- scan slower/faster, b bookmark, e export, l log, q quit", recorded(stats))
+}
+
+fn recorded(stats: Option<&SharedSinkStats>) -> String {
+    match stats {
+        Some(stats) => format!("Recorded: {}", stats.get()),
+        None => "Not recording".to_string()
+    }
+}
+
+/// The devices shown depend on which tab this is for
+fn device_table<B: Backend>(f: &mut Frame<'_, B>, area: Rect, app: &mut App, current: &Snapshot, previous: &Snapshot, history: &RssiHistory, now: DateTime<Utc>) {
+    let ordered = current.order_by_age_and_volume();
+    let mut compared : Vec<Compared> = ordered.compared_to(now, previous).into_iter()
+        .filter(|(state, _)| match app.tab {
+            Tab::Named => matches!(state.signature, Signature::Named(_)),
+            Tab::Anonymous => matches!(state.signature, Signature::Anonymous(_)),
+            _ => true
+        })
+        .collect();
+    // watched devices are pinned to the top
+    compared.sort_by_key(|(state, _)| !app.watchlist.contains(&state.signature));
+    let entries = if app.grouped { grouped(&compared, &app.manufacturers, &app.expanded) } else { compared.iter().map(Entry::Device).collect() };
+    let rows : Vec<Row<'_>> = entries.iter()
+        .map(|entry| match entry {
+            Entry::Device((state, comparison)) | Entry::Nested((state, comparison)) => {
+                let marks = Marks {
+                    charted: app.charted.contains(&state.signature),
+                    watched: app.watchlist.contains(&state.signature),
+                    alerting: app.is_alerting(&state.signature, Utc::now()),
+                    nested: matches!(entry, Entry::Nested(_))
+                };
+                let rssi = rssi_text(state, app.rssi_mode, history, &app.estimator);
+                device_row(state, comparison, rssi, &marks, &app.theme)
+            },
+            Entry::Group { manufacturer_id, count, expanded } => {
+                let arrow = if *expanded { "▾" } else { "▸" };
+                Row::new(vec![format!("{arrow} {} ({count})", manufacturer_name(*manufacturer_id))])
+                    .style(Style::default().fg(app.theme.header).add_modifier(Modifier::BOLD))
+            }
+        })
+        .collect();
+    let len = rows.len();
+    let rssi_header = match app.rssi_mode {
+        RssiMode::Dbm => "\nRssi",
+        RssiMode::Smoothed => "Avg\nRssi",
+        RssiMode::Distance => "\nDist"
+    };
+    let table = table(rows, app.tab.title(), &app.theme, &["\nName", "Last\nSeen", rssi_header, "\nChange"],
+        &[Constraint::Length(32), Constraint::Length(4), Constraint::Length(6), Constraint::Length(6)]);
+    render_scrollable(f, area, table, app.table_state(), len);
+    let selected = app.table_state().selected().and_then(|index| entries.get(index));
+    app.selected = match selected {
+        Some(Entry::Device((state, _)) | Entry::Nested((state, _))) => Some(state.signature.clone()),
+        _ => None
+    };
+    app.selected_group = match selected {
+        Some(Entry::Group { manufacturer_id, .. }) => Some(*manufacturer_id),
+        _ => None
+    };
+}
+
+/// A device, and how it compares to the previous snapshot
+type Compared = (DeviceState, Comparison);
+
+/// A row in a device table
+enum Entry<'a> {
+    Device(&'a Compared),
+    /// a device shown within its manufacturer's group
+    Nested(&'a Compared),
+    Group { manufacturer_id: Option<u16>, count: usize, expanded: bool }
+}
+
+/// Named devices as they are, followed by anonymous ones grouped by
+/// manufacturer, where only the groups in `expanded` show their devices
+fn grouped<'a>(compared: &'a [Compared], manufacturers: &Manufacturers, expanded: &HashSet<Option<u16>>) -> Vec<Entry<'a>> {
+    let mut groups : BTreeMap<String, (Option<u16>, Vec<&Compared>)> = BTreeMap::new();
+    let mut entries = vec![];
+    for device in compared {
+        match device.0.signature {
+            Signature::Named(_) => entries.push(Entry::Device(device)),
+            Signature::Anonymous(_) => {
+                let manufacturer_id = manufacturers.get(&device.0.signature).copied();
+                groups.entry(manufacturer_name(manufacturer_id))
+                    .or_insert_with(|| (manufacturer_id, vec![]))
+                    .1.push(device);
+            }
+        }
+    }
+    for (manufacturer_id, devices) in groups.into_values() {
+        let is_expanded = expanded.contains(&manufacturer_id);
+        entries.push(Entry::Group { manufacturer_id, count: devices.len(), expanded: is_expanded });
+        if is_expanded {
+            entries.extend(devices.into_iter().map(Entry::Nested));
+        }
+    }
+    entries
+}
+
+/// What, besides its state, affects how a device is shown
+struct Marks {
+    charted: bool,
+    watched: bool,
+    alerting: bool,
+    nested: bool
+}
+
+impl Marks {
+    fn name(&self, name: &str) -> String {
+        let nested = if self.nested { "  " } else { "" };
+        let watched = if self.watched { "★ " } else { "" };
+        let charted = if self.charted { "● " } else { "" };
+        format!("{nested}{watched}{charted}{name}")
+    }
+
+    fn style(&self, style: Style) -> Style {
+        match (self.watched, self.alerting) {
+            (_, true) => style.add_modifier(Modifier::BOLD | Modifier::SLOW_BLINK),
+            (true, false) => style.add_modifier(Modifier::BOLD),
+            _ => style
+        }
+    }
+}
+
+/// The RSSI column, in whichever form was asked for
+fn rssi_text(state: &DeviceState, mode: RssiMode, history: &RssiHistory, estimator: &DistanceEstimator) -> String {
+    let smoothed = || history.smoothed(&state.signature).unwrap_or(f64::from(state.rssi));
+    match mode {
+        RssiMode::Dbm => state.rssi.to_string(),
+        RssiMode::Smoothed => format!("{:.0}", smoothed()),
+        RssiMode::Distance => {
+            let metres = estimator.estimate(smoothed());
+            if metres < 10.0 { format!("{metres:.1}m") } else { format!("{metres:.0}m") }
+        }
+    }
+}
+
+fn device_row<'a>(state: &DeviceState, comparison: &Comparison, rssi: String, marks: &Marks, theme: &Theme) -> Row<'a> {
+    let default_style = marks.style(match comparison.rssi {
+        RssiComparison::New => Style::default().fg(theme.new_device),
+        _ => Style::default().fg(theme.text)
+    });
+    let shared_cells = vec![
+        Cell::from(age_summary(comparison).to_string()).style(default_style),
+        Cell::from(rssi).style(default_style),
+        Cell::from(rssi_summary(comparison)).style(default_style)
+    ];
+    match &state.signature {
+        Signature::Named(n) => {
+            let name_cell = Cell::from(marks.name(n)).style(default_style);
+            Row::new([vec![name_cell], shared_cells].concat())
+        },
+        Signature::Anonymous(d) => {
+            let name = d.clone();
+            let style = marks.style(match comparison.rssi {
+                RssiComparison::New => Style::default().fg(theme.new_device),
+                _ => match u8::from_str_radix(&name[0..2], 16) {
+                    Ok(index) => Style::default().fg(Color::Indexed(index)),
+                    _ => Style::default().fg(theme.text)
+                }
+            });
+            let name_cell = Cell::from(marks.name(&name)).style(style);
+            Row::new([vec![name_cell], shared_cells].concat())
+                .style(style)
+        }
+    }
+}
+
+fn compare_table<B: Backend>(f: &mut Frame<'_, B>, area: Rect, app: &mut App, current: &Snapshot) {
+    let Some((bookmarked, bookmark)) = &app.bookmark else {
+        let paragraph = Paragraph::new("Press 'b' to bookmark what's currently seen, to compare against later")
+            .block(Block::default().title(Tab::Compare.title()).borders(Borders::ALL))
+            .style(Style::default().fg(app.theme.text));
+        f.render_widget(paragraph, area);
+        return;
+    };
+    let differences = current.diff(bookmark);
+    let rows : Vec<Row<'_>> = differences.iter()
+        .map(|d| {
+            let (change, style, before, after) = match d {
+                Difference::Added(after) => ("added", Style::default().fg(app.theme.arrived), String::new(), after.rssi.to_string()),
+                Difference::Removed(before) => ("removed", Style::default().fg(app.theme.departed), before.rssi.to_string(), String::new()),
+                Difference::Seen { before, after } => ("seen", Style::default().fg(app.theme.text), before.rssi.to_string(), after.rssi.to_string())
+            };
+            Row::new(vec![
+                Cell::from(change).style(style),
+                Cell::from(d.signature().value().to_string()),
+                Cell::from(before),
+                Cell::from(after)
+            ])
+        })
+        .collect();
+    let len = rows.len();
+    let title = format!("Compared to bookmark from {}", bookmarked.with_timezone(&chrono::Local).format("%H:%M:%S"));
+    let table = table(rows, &title, &app.theme, &["\nChange", "\nName", "Rssi\nBefore", "Rssi\nNow"],
+        &[Constraint::Length(8), Constraint::Length(32), Constraint::Length(6), Constraint::Length(6)]);
+    render_scrollable(f, area, table, app.table_state(), len);
+}
+
+fn presence_table<B: Backend>(f: &mut Frame<'_, B>, area: Rect, app: &mut App) {
+    let rows : Vec<Row<'_>> = app.presence_log.iter().rev()
+        .map(|e| {
+            let (change, style) = match e.change {
+                PresenceChange::Arrived => ("arrived", Style::default().fg(app.theme.arrived)),
+                PresenceChange::Departed => ("departed", Style::default().fg(app.theme.departed))
+            };
+            Row::new(vec![
+                Cell::from(e.date_time.with_timezone(&chrono::Local).format("%H:%M:%S").to_string()),
+                Cell::from(change).style(style),
+                Cell::from(e.signature.to_string())
+            ])
+        })
+        .collect();
+    let len = rows.len();
+    let table = table(rows, "Presence log (newest first)", &app.theme, &["\nTime", "\nChange", "\nName"],
+        &[Constraint::Length(8), Constraint::Length(8), Constraint::Length(32)]);
+    render_scrollable(f, area, table, app.table_state(), len);
+}
+
+fn stats_view<B: Backend>(f: &mut Frame<'_, B>, area: Rect, app: &App, current: &Snapshot, stats: Option<&SharedSinkStats>) {
+    let named = current.0.iter().filter(|d| matches!(d.signature, Signature::Named(_))).count();
+    let anonymous = current.0.len() - named;
+    let mut lines = vec![
+        format!("Devices seen: {} ({named} named, {anonymous} anonymous)", current.0.len()),
+        format!("Scans: {} ({} failed)", app.scans, app.failed_scans),
+        recorded(stats),
+    ];
+    if let Some(stats) = stats.map(SharedSinkStats::get) {
+        lines.push(format!("Gaps recorded: {}", stats.gaps));
+        lines.push(format!("Recording errors: {}", stats.errors));
+    }
+    let paragraph = Paragraph::new(lines.join("\n"))
+        .block(Block::default().title(Tab::Stats.title()).borders(Borders::ALL))
+        .style(Style::default().fg(app.theme.text));
+    f.render_widget(paragraph, area);
+}
+
+/// How far back the chart goes
+const CHART_SECONDS : f64 = 300.0;
+
+/// Colors for successive devices on the chart
+const CHART_COLORS : [Color; 6] = [Color::Cyan, Color::Magenta, Color::Blue, Color::Green, Color::Yellow, Color::Red];
+
+/// Plots RSSI over time for the charted devices, or just the selected one if
+/// none have been charted
+fn rssi_chart<B: Backend>(f: &mut Frame<'_, B>, area: Rect, app: &App, history: &RssiHistory, now: DateTime<Utc>) {
+    let signatures : Vec<&Signature> = if app.charted.is_empty() {
+        app.selected.iter().collect()
+    }
+    else {
+        app.charted.iter().collect()
+    };
+    let points : Vec<Vec<(f64, f64)>> = signatures.iter()
+        .map(|signature| history.readings(signature)
+            .map(|(date_time, rssi)| {
+                #[allow(clippy::cast_precision_loss)]
+                let seconds_ago = (now - *date_time).num_milliseconds() as f64 / 1000.0;
+                (-seconds_ago, f64::from(*rssi))
+            })
+            .collect())
+        .collect();
+    let datasets : Vec<Dataset<'_>> = signatures.iter().zip(&points).enumerate()
+        .map(|(i, (signature, points))| Dataset::default()
+            .name(signature.value().chars().take(16).collect::<String>())
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(CHART_COLORS[i % CHART_COLORS.len()]))
+            .data(points))
+        .collect();
+    let title = if signatures.is_empty() {
+        "RSSI chart (select a device and press 'c' to chart it)"
+    }
+    else {
+        Tab::Chart.title()
+    };
+    let label_style = Style::default().fg(app.theme.text);
+    let chart = Chart::new(datasets)
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .style(label_style)
+        .x_axis(Axis::default()
+            .title("seconds ago")
+            .style(label_style)
+            .bounds([-CHART_SECONDS, 0.0])
+            .labels([format!("{CHART_SECONDS}"), format!("{}", CHART_SECONDS / 2.0), "0".to_string()].into_iter().map(Span::from).collect()))
+        .y_axis(Axis::default()
+            .title("rssi")
+            .style(label_style)
+            .bounds([-100.0, 0.0])
+            .labels(["-100", "-50", "0"].into_iter().map(Span::from).collect()));
+    f.render_widget(chart, area);
+}
+
+fn age_summary(comparison: &Comparison) -> FormattedDuration {
+    use humantime::format_duration;
+    use blescan::chrono_extra::Truncate;
+
+    format_duration(comparison.relative_age.truncate_to_seconds().to_std().unwrap())
+}
+
+fn rssi_summary(comparison: &Comparison) -> String {
+    match comparison.rssi {
+        RssiComparison::Louder => "↑",
+        RssiComparison::Quieter => "⌄",
+        RssiComparison::Same => "=",
+        RssiComparison::New => "*"
+    }.to_string()
+}
+
+fn render_scrollable<B: Backend>(f: &mut Frame<'_, B>, area: Rect, table: Table<'_>, state: &mut TableState, len: usize) {
+    scroll(state, 0, len);
+    f.render_stateful_widget(table, area, state);
+    let mut scrollbar_state = ScrollbarState::default()
+        .content_length(u16::try_from(len).unwrap_or(u16::MAX))
+        .position(u16::try_from(state.selected().unwrap_or(0)).unwrap_or(u16::MAX));
+    f.render_stateful_widget(
+        Scrollbar::new(ScrollbarOrientation::VerticalRight).begin_symbol(None).end_symbol(None),
+        area.inner(&Margin { vertical: 1, horizontal: 0 }),
+        &mut scrollbar_state);
+}
+
+fn table<'a>(rows: Vec<Row<'a>>, title: &'a str, theme: &Theme, header: &[&'a str], widths: &'a [Constraint]) -> Table<'a> {
+    Table::new(rows)
+        .style(Style::default().fg(theme.text))
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .widths(widths)
+        .header(
+            Row::new(header.to_vec())
+                .height(2)
+                .style(Style::default().fg(theme.header))
+        )
+}
@@ -1,4 +1,6 @@
-use chrono::Duration;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
 
 pub trait Truncate {
     fn truncate_to_seconds(&self) -> Duration;
@@ -10,11 +12,47 @@ impl Truncate for Duration {
     }
 }
 
+/// How times are shown to people: RFC 3339, seconds since the epoch (to line
+/// up with other logs), or the time since some start
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TimestampFormat {
+    Iso,
+    Unix,
+    Relative
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "iso" => Ok(TimestampFormat::Iso),
+            "unix" => Ok(TimestampFormat::Unix),
+            "relative" => Ok(TimestampFormat::Relative),
+            _ => Err(format!("unknown timestamp format: {s} (expected iso, unix or relative)"))
+        }
+    }
+}
+
+impl TimestampFormat {
+    /// Formats `date_time`, which if relative is shown as the time since `start`
+    #[must_use] pub fn format(self, date_time: DateTime<Utc>, start: DateTime<Utc>) -> String {
+        match self {
+            TimestampFormat::Iso => date_time.to_rfc3339(),
+            TimestampFormat::Unix => format!("{}.{:03}", date_time.timestamp(), date_time.timestamp_subsec_millis()),
+            TimestampFormat::Relative => {
+                let since = (date_time - start).truncate_to_seconds().to_std().unwrap_or_default();
+                format!("+{}", humantime::format_duration(since))
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use chrono::Duration;
+    use chrono::{Duration, Utc, TimeZone};
 
-    use super::Truncate;
+    use super::{Truncate, TimestampFormat};
 
     #[test]
     fn truncate_to_seconds() {
@@ -23,4 +61,13 @@ mod test {
         let actual = d.truncate_to_seconds();
         assert_eq!(actual, expected);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn format_timestamps() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let date_time = start + Duration::milliseconds(90_250);
+        assert_eq!(TimestampFormat::Iso.format(date_time, start), "2023-11-14T22:14:50.250+00:00");
+        assert_eq!(TimestampFormat::Unix.format(date_time, start), "1700000090.250");
+        assert_eq!(TimestampFormat::Relative.format(date_time, start), "+1m 30s");
+    }
+}
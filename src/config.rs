@@ -0,0 +1,110 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use serde::Deserialize;
+
+use crate::error::Error;
+
+/// Settings shared by `blescan` and `blescan-cli`, from a TOML file like:
+///
+/// ```toml
+/// adapter = 0
+/// record = ["events.sqlite"]
+/// signature_policy = "xxh3"
+/// theme = "dark"
+/// watchlist = "watchlist.txt"
+///
+/// [filter]
+/// min_rssi = -80
+/// named_only = true
+/// ```
+///
+/// Anything also given on the command line takes precedence. Settings only
+/// apply where the binary has the matching option, e.g. `[filter]` applies to
+/// `blescan-cli` only.
+#[derive(PartialEq, Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub adapter: Option<usize>,
+    /// where to record to, as with `--record`
+    #[serde(default)]
+    pub record: Vec<String>,
+    pub signature_policy: Option<String>,
+    pub theme: Option<String>,
+    pub theme_file: Option<PathBuf>,
+    pub watchlist: Option<PathBuf>,
+    #[serde(default)]
+    pub filter: FilterConfig
+}
+
+#[derive(PartialEq, Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterConfig {
+    pub min_rssi: Option<i16>,
+    #[serde(default)]
+    pub named_only: bool,
+    #[serde(default)]
+    pub anon_only: bool,
+    pub substring: Option<String>
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config, Error> {
+        toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|source| Error::Config { path: path.to_path_buf(), source })
+    }
+
+    /// `blescan/config.toml` in `$XDG_CONFIG_HOME`, or else in `~/.config`
+    #[must_use] pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("blescan").join("config.toml"))
+    }
+
+    /// Reads `path` if given, or otherwise the file at the default path, if
+    /// there is one
+    pub fn load(path: Option<&Path>) -> Result<Config, Error> {
+        match path {
+            Some(path) => Config::from_file(path),
+            None => match Config::default_path().filter(|path| path.exists()) {
+                Some(path) => Config::from_file(&path),
+                None => Ok(Config::default())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, path::PathBuf};
+
+    use tempfile::NamedTempFile;
+
+    use super::{Config, FilterConfig};
+
+    fn config_file(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "{contents}").unwrap();
+        file
+    }
+
+    #[test]
+    fn read_from_file() {
+        let file = config_file("adapter = 1\nrecord = [\"events.sqlite\"]\nwatchlist = \"watch.txt\"\n\n[filter]\nmin_rssi = -80\nnamed_only = true\n");
+        assert_eq!(Config::load(Some(file.path())).unwrap(), Config {
+            adapter: Some(1),
+            record: vec!["events.sqlite".to_string()],
+            watchlist: Some(PathBuf::from("watch.txt")),
+            filter: FilterConfig { min_rssi: Some(-80), named_only: true, ..FilterConfig::default() },
+            ..Config::default()
+        });
+    }
+
+    #[test]
+    fn unknown_settings_rejected() {
+        let file = config_file("adaptor = 1\n");
+        let error = Config::load(Some(file.path())).unwrap_err();
+        assert!(error.to_string().contains("adaptor"));
+    }
+}
@@ -3,7 +3,7 @@ use serde::{Serialize, Deserialize};
 
 use crate::signature::Signature;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct DiscoveryEvent {
     pub date_time: DateTime<Utc>,
     pub signature: Signature,
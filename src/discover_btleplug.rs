@@ -1,4 +1,3 @@
-use std::error::Error;
 use std::time::Duration;
 use chrono::Utc;
 use tokio::time;
@@ -6,47 +5,94 @@ use tokio::time;
 use btleplug::api::{Central, Manager as _, Peripheral, ScanFilter};
 use btleplug::platform::{Manager, Adapter};
 
-use crate::discover::DiscoveryEvent;
-use crate::signature::Signature;
+use crate::{discover::DiscoveryEvent, error::Error, manufacturer::Manufacturers};
+use crate::signature::{Signature, SignaturePolicy};
+
+/// How long each scan listens for, unless changed with `set_dwell`
+pub const DEFAULT_DWELL : Duration = Duration::from_secs(1);
 
 pub struct Scanner {
-    adapter: Adapter
+    adapter: Adapter,
+    policy: SignaturePolicy,
+    dwell: Duration
 }
 
 impl Scanner {
-    pub async fn new() -> Result<Scanner, Box<dyn Error>> {
-        
+    pub async fn new() -> Result<Scanner, Error> {
+        Scanner::new_with_policy(SignaturePolicy::default()).await
+    }
+
+    pub async fn new_with_policy(policy: SignaturePolicy) -> Result<Scanner, Error> {
+        Scanner::new_with_adapter(policy, None).await
+    }
+
+    /// Scans with the adapter at `index` in `adapters()`, or the last one if
+    /// not given
+    pub async fn new_with_adapter(policy: SignaturePolicy, index: Option<usize>) -> Result<Scanner, Error> {
         let manager = Manager::new().await?;
         let mut adapter_list = manager.adapters().await?;
-        if adapter_list.is_empty() {
-            eprintln!("No Bluetooth adapters found");
-        }
-        let adapter = adapter_list.pop().unwrap();
+        let adapter = match index {
+            Some(index) if index < adapter_list.len() => adapter_list.remove(index),
+            Some(index) => return Err(Error::NoSuchAdapter { index, count: adapter_list.len() }),
+            None => adapter_list.pop().ok_or(Error::NoAdapters)?
+        };
         Ok(Scanner {
-            adapter
+            adapter,
+            policy,
+            dwell: DEFAULT_DWELL
         })
     }
 
-    pub async fn scan(&mut self) -> Result<Vec<DiscoveryEvent>, Box<dyn Error>> {
+    /// Descriptions of the available adapters
+    pub async fn adapters() -> Result<Vec<String>, Error> {
+        let manager = Manager::new().await?;
+        let mut descriptions = vec![];
+        for adapter in manager.adapters().await? {
+            descriptions.push(adapter.adapter_info().await?);
+        }
+        Ok(descriptions)
+    }
+
+    /// Longer scans use the radio less often, but pick up changes more slowly
+    pub fn set_dwell(&mut self, dwell: Duration) {
+        self.dwell = dwell;
+    }
+
+    #[must_use] pub fn dwell(&self) -> Duration {
+        self.dwell
+    }
+
+    pub async fn scan(&mut self) -> Result<Vec<DiscoveryEvent>, Error> {
+        Ok(self.scan_with_manufacturers().await?.0)
+    }
+
+    /// As `scan`, along with the manufacturer of each device found, where its
+    /// advertisement says
+    pub async fn scan_with_manufacturers(&mut self) -> Result<(Vec<DiscoveryEvent>, Manufacturers), Error> {
         self.adapter
             .start_scan(ScanFilter::default())
-            .await
-            .expect("Can't scan BLE adapter for connected devices...");
-        time::sleep(Duration::from_secs(1)).await;
+            .await?;
+        time::sleep(self.dwell).await;
         let peripherals = self.adapter.peripherals().await?;
         let mut events = vec![];
+        let mut manufacturers = Manufacturers::new();
         let current_time = Utc::now();
         for peripheral in &peripherals {
-            let properties = peripheral.properties().await?.unwrap();
-            if let Some(signature) = Signature::find(&properties) {
+            // gone again since being listed
+            let Some(properties) = peripheral.properties().await? else {
+                continue;
+            };
+            if let Some(signature) = Signature::find_with_policy(&properties, &self.policy) {
                 if let Some(rssi) = properties.rssi {
+                    if let Some(manufacturer_id) = properties.manufacturer_data.keys().min() {
+                        manufacturers.insert(signature.clone(), *manufacturer_id);
+                    }
                     events.push(DiscoveryEvent::new(current_time, signature, rssi));
                 }
             }
         }
         self.adapter
-            .stop_scan().await
-            .expect("Can't stop scan");
-        Ok(events)
+            .stop_scan().await?;
+        Ok((events, manufacturers))
     }
 }
@@ -0,0 +1,37 @@
+/// Estimates how far away a device is from its RSSI, using the log-distance
+/// path loss model. This is only ever rough, as walls, bodies and antennas all
+/// affect signal strength.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct DistanceEstimator {
+    /// the RSSI expected at 1m
+    pub measured_power: f64,
+    /// how quickly the signal falls off with distance; 2 in free space, and
+    /// higher indoors
+    pub path_loss_exponent: f64
+}
+
+impl Default for DistanceEstimator {
+    fn default() -> Self {
+        DistanceEstimator { measured_power: -59.0, path_loss_exponent: 2.0 }
+    }
+}
+
+impl DistanceEstimator {
+    /// In metres
+    #[must_use] pub fn estimate(&self, rssi: f64) -> f64 {
+        10f64.powf((self.measured_power - rssi) / (10.0 * self.path_loss_exponent))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DistanceEstimator;
+
+    #[test]
+    fn estimates() {
+        let estimator = DistanceEstimator::default();
+        assert!((estimator.estimate(-59.0) - 1.0).abs() < 1e-9);
+        assert!((estimator.estimate(-79.0) - 10.0).abs() < 1e-9);
+        assert!(estimator.estimate(-49.0) < 1.0);
+    }
+}
@@ -0,0 +1,104 @@
+use std::{io, path::PathBuf};
+
+/// Errors from discovering devices, and from recording or reading back what
+/// was discovered. The first few variants are ones callers may want to react
+/// to; the rest wrap whatever went wrong underneath.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("no Bluetooth adapters found")]
+    NoAdapters,
+    #[error("no adapter {index}, there are only {count}")]
+    NoSuchAdapter { index: usize, count: usize },
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    /// A sink's queue was full, with `OverflowPolicy::Fail`
+    #[error("sink is full")]
+    SinkFull,
+    /// Recording to or reading back from a file or url which isn't supported
+    #[error("{0}")]
+    Unsupported(String),
+    #[error("invalid url {url}: {reason}")]
+    InvalidUrl { url: String, reason: String },
+    /// History recorded with one signature policy, and added to with another
+    #[error("{path} was recorded with signature policy {recorded}, not {requested}")]
+    SignaturePolicyMismatch { path: String, recorded: String, requested: String },
+    /// Every one of several sinks written to at once failed
+    #[error("sinks failed: {}", join(.0))]
+    Sinks(Vec<Error>),
+    #[error("{}: {source}", path.display())]
+    Config { path: PathBuf, source: toml::de::Error },
+    #[error(transparent)]
+    Bluetooth(btleplug::Error),
+    #[error(transparent)]
+    Io(io::Error),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    #[error(transparent)]
+    Mqtt(#[from] rumqttc::ClientError),
+    #[error(transparent)]
+    ObjectStore(#[from] object_store::Error),
+    #[error(transparent)]
+    Url(#[from] url::ParseError),
+    #[error(transparent)]
+    Compression(#[from] gzp::GzpError),
+    #[error(transparent)]
+    Task(#[from] tokio::task::JoinError),
+    /// Anything else, such as an error from a sink outside this crate
+    #[error(transparent)]
+    Other(Box<dyn std::error::Error + Send + Sync>)
+}
+
+impl From<btleplug::Error> for Error {
+    fn from(e: btleplug::Error) -> Self {
+        match e {
+            btleplug::Error::PermissionDenied => Error::PermissionDenied("Bluetooth".to_string()),
+            e => Error::Bluetooth(e)
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied(e.to_string()),
+            _ => Error::Io(e)
+        }
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(e))
+    }
+}
+
+fn join(errors: &[Error]) -> String {
+    errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::Error;
+
+    #[test]
+    fn permission_denied_recognised() {
+        let error : Error = io::Error::new(io::ErrorKind::PermissionDenied, "events.sqlite").into();
+        assert!(matches!(error, Error::PermissionDenied(_)));
+        let error : Error = btleplug::Error::PermissionDenied.into();
+        assert!(matches!(error, Error::PermissionDenied(_)));
+        let error : Error = io::Error::new(io::ErrorKind::NotFound, "events.sqlite").into();
+        assert!(matches!(error, Error::Io(_)));
+    }
+}
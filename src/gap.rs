@@ -0,0 +1,234 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Serialize, Deserialize};
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum GapReason {
+    Paused,
+    Stalled,
+    ScanFailed,
+    /// blescan wasn't running, between one recording to a file and the next
+    NotRunning
+}
+
+impl std::fmt::Display for GapReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use GapReason::{Paused, Stalled, ScanFailed, NotRunning};
+        match self {
+            Paused => write!(f, "paused"),
+            Stalled => write!(f, "stalled"),
+            ScanFailed => write!(f, "scan_failed"),
+            NotRunning => write!(f, "not_running")
+        }
+    }
+}
+
+impl std::str::FromStr for GapReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "paused" => Ok(GapReason::Paused),
+            "stalled" => Ok(GapReason::Stalled),
+            "scan_failed" => Ok(GapReason::ScanFailed),
+            "not_running" => Ok(GapReason::NotRunning),
+            _ => Err(format!("unknown gap reason: {s}"))
+        }
+    }
+}
+
+/// A period during which no scanning took place, so the absence of a device
+/// during it says nothing about whether the device was actually there
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct ScanGap {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub reason: GapReason,
+}
+
+impl ScanGap {
+    #[must_use] pub fn new(start: DateTime<Utc>, end: DateTime<Utc>, reason: GapReason) -> ScanGap {
+        ScanGap { start, end, reason }
+    }
+
+    #[must_use] pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    #[must_use] pub fn overlap(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Duration {
+        let start = self.start.max(from);
+        let end = self.end.min(to);
+        if end > start {
+            end - start
+        }
+        else {
+            Duration::zero()
+        }
+    }
+}
+
+/// The part of `from..to` which was actually covered by scanning
+#[must_use] pub fn observed_duration(from: DateTime<Utc>, to: DateTime<Utc>, gaps: &[ScanGap]) -> Duration {
+    if to <= from {
+        return Duration::zero();
+    }
+    let mut sorted : Vec<&ScanGap> = gaps.iter().collect();
+    sorted.sort_by_key(|g| g.start);
+    let mut unobserved = Duration::zero();
+    let mut covered_until = from;
+    for gap in sorted {
+        let start = gap.start.max(covered_until);
+        let end = gap.end.min(to);
+        if end > start {
+            unobserved += end - start;
+            covered_until = end;
+        }
+    }
+    (to - from) - unobserved
+}
+
+/// Notices when scans are further apart than expected (e.g. the machine slept),
+/// or when scanning was explicitly interrupted
+pub struct GapDetector {
+    expected_interval: Duration,
+    tolerance: Duration,
+    last_scan: Option<DateTime<Utc>>,
+    interruption: Option<(GapReason, DateTime<Utc>)>
+}
+
+impl GapDetector {
+    #[must_use] pub fn new(expected_interval: Duration, tolerance: Duration) -> GapDetector {
+        GapDetector { expected_interval, tolerance, last_scan: None, interruption: None }
+    }
+
+    pub fn observe(&mut self, scan_time: DateTime<Utc>) -> Option<ScanGap> {
+        let interruption = self.interruption.take();
+        let gap = match (self.last_scan, interruption) {
+            (Some(last), Some((reason, _))) => Some(ScanGap::new(last, scan_time, reason)),
+            // interrupted before any scan succeeded, so the gap starts there
+            (None, Some((reason, at))) => Some(ScanGap::new(at, scan_time, reason)),
+            (Some(last), None) if scan_time - last > self.expected_interval + self.tolerance => {
+                Some(ScanGap::new(last, scan_time, GapReason::Stalled))
+            },
+            _ => None
+        };
+        self.last_scan = Some(scan_time);
+        gap
+    }
+
+    /// For when the time between scans is changed while scanning
+    pub fn set_expected_interval(&mut self, expected_interval: Duration) {
+        self.expected_interval = expected_interval;
+    }
+
+    /// Only the first interruption since the last scan counts
+    pub fn interrupt(&mut self, reason: GapReason, at: DateTime<Utc>) {
+        self.interruption.get_or_insert((reason, at));
+    }
+
+    /// For when carrying on a recording which was last recorded to at
+    /// `last_recorded`, so that the time since then is a gap
+    pub fn resume(&mut self, last_recorded: DateTime<Utc>) {
+        self.last_scan = Some(last_recorded);
+        self.interruption = Some((GapReason::NotRunning, last_recorded));
+    }
+
+    /// For when scanning stops, so that an interruption which no scan has
+    /// come after yet (e.g. quitting while paused) isn't lost
+    pub fn finish(&mut self, at: DateTime<Utc>) -> Option<ScanGap> {
+        let (reason, interrupted) = self.interruption.take()?;
+        let start = self.last_scan.unwrap_or(interrupted);
+        (at > start).then(|| ScanGap::new(start, at, reason))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Utc, TimeZone, Duration};
+
+    use super::{ScanGap, GapReason, GapDetector, observed_duration};
+
+    #[test]
+    fn observed_duration_without_gaps() {
+        let from = Utc.timestamp_opt(0, 0).unwrap();
+        let to = Utc.timestamp_opt(100, 0).unwrap();
+        assert_eq!(observed_duration(from, to, &[]), Duration::seconds(100));
+    }
+
+    #[test]
+    fn observed_duration_excludes_gaps() {
+        let from = Utc.timestamp_opt(0, 0).unwrap();
+        let to = Utc.timestamp_opt(100, 0).unwrap();
+        let gaps = vec![
+            ScanGap::new(Utc.timestamp_opt(10, 0).unwrap(), Utc.timestamp_opt(20, 0).unwrap(), GapReason::Paused),
+            ScanGap::new(Utc.timestamp_opt(15, 0).unwrap(), Utc.timestamp_opt(30, 0).unwrap(), GapReason::Stalled),
+            ScanGap::new(Utc.timestamp_opt(90, 0).unwrap(), Utc.timestamp_opt(200, 0).unwrap(), GapReason::ScanFailed),
+        ];
+        assert_eq!(observed_duration(from, to, &gaps), Duration::seconds(70));
+    }
+
+    #[test]
+    fn detects_stalled_scans() {
+        let mut detector = GapDetector::new(Duration::seconds(1), Duration::seconds(2));
+        assert_eq!(detector.observe(Utc.timestamp_opt(0, 0).unwrap()), None);
+        assert_eq!(detector.observe(Utc.timestamp_opt(2, 0).unwrap()), None);
+        assert_eq!(
+            detector.observe(Utc.timestamp_opt(60, 0).unwrap()),
+            Some(ScanGap::new(Utc.timestamp_opt(2, 0).unwrap(), Utc.timestamp_opt(60, 0).unwrap(), GapReason::Stalled))
+        );
+    }
+
+    #[test]
+    fn longer_intervals_are_not_stalls() {
+        let mut detector = GapDetector::new(Duration::seconds(1), Duration::seconds(2));
+        assert_eq!(detector.observe(Utc.timestamp_opt(0, 0).unwrap()), None);
+        detector.set_expected_interval(Duration::seconds(10));
+        assert_eq!(detector.observe(Utc.timestamp_opt(10, 0).unwrap()), None);
+    }
+
+    #[test]
+    fn interruptions_always_produce_a_gap() {
+        let mut detector = GapDetector::new(Duration::seconds(1), Duration::seconds(2));
+        assert_eq!(detector.observe(Utc.timestamp_opt(0, 0).unwrap()), None);
+        detector.interrupt(GapReason::ScanFailed, Utc.timestamp_opt(0, 0).unwrap());
+        detector.interrupt(GapReason::Paused, Utc.timestamp_opt(1, 0).unwrap());
+        assert_eq!(
+            detector.observe(Utc.timestamp_opt(1, 0).unwrap()),
+            Some(ScanGap::new(Utc.timestamp_opt(0, 0).unwrap(), Utc.timestamp_opt(1, 0).unwrap(), GapReason::ScanFailed))
+        );
+        assert_eq!(detector.observe(Utc.timestamp_opt(2, 0).unwrap()), None);
+    }
+
+    #[test]
+    fn resuming_a_recording_starts_with_a_gap() {
+        let mut detector = GapDetector::new(Duration::seconds(1), Duration::seconds(2));
+        detector.resume(Utc.timestamp_opt(0, 0).unwrap());
+        assert_eq!(
+            detector.observe(Utc.timestamp_opt(2, 0).unwrap()),
+            Some(ScanGap::new(Utc.timestamp_opt(0, 0).unwrap(), Utc.timestamp_opt(2, 0).unwrap(), GapReason::NotRunning))
+        );
+        assert_eq!(detector.observe(Utc.timestamp_opt(3, 0).unwrap()), None);
+    }
+
+    #[test]
+    fn finishing_keeps_pending_interruptions() {
+        let mut detector = GapDetector::new(Duration::seconds(1), Duration::seconds(2));
+        assert_eq!(detector.finish(Utc.timestamp_opt(0, 0).unwrap()), None);
+        assert_eq!(detector.observe(Utc.timestamp_opt(0, 0).unwrap()), None);
+        detector.interrupt(GapReason::Paused, Utc.timestamp_opt(1, 0).unwrap());
+        assert_eq!(
+            detector.finish(Utc.timestamp_opt(30, 0).unwrap()),
+            Some(ScanGap::new(Utc.timestamp_opt(0, 0).unwrap(), Utc.timestamp_opt(30, 0).unwrap(), GapReason::Paused))
+        );
+        assert_eq!(detector.finish(Utc.timestamp_opt(31, 0).unwrap()), None);
+    }
+
+    #[test]
+    fn interruptions_before_the_first_scan() {
+        let mut detector = GapDetector::new(Duration::seconds(1), Duration::seconds(2));
+        detector.interrupt(GapReason::ScanFailed, Utc.timestamp_opt(0, 0).unwrap());
+        assert_eq!(
+            detector.observe(Utc.timestamp_opt(30, 0).unwrap()),
+            Some(ScanGap::new(Utc.timestamp_opt(0, 0).unwrap(), Utc.timestamp_opt(30, 0).unwrap(), GapReason::ScanFailed))
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
+
+use super::EventSink;
+
+/// Holds events back until there are at least `max_events` of them, or the
+/// oldest has been waiting for `max_wait`, and then saves them all at once
+pub struct BufferedEventSink {
+    inner: Box<dyn EventSink>,
+    max_events: usize,
+    max_wait: Duration,
+    buffer: Vec<DiscoveryEvent>,
+    oldest: Option<Instant>
+}
+
+impl BufferedEventSink {
+    #[must_use] pub fn create_from_sink(inner: Box<dyn EventSink>, max_events: usize, max_wait: Duration) -> BufferedEventSink {
+        BufferedEventSink { inner, max_events, max_wait, buffer: vec![], oldest: None }
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        if !self.buffer.is_empty() {
+            self.inner.save(&self.buffer).await?;
+            self.buffer.clear();
+        }
+        self.oldest = None;
+        Ok(())
+    }
+
+    fn should_flush(&self) -> bool {
+        self.buffer.len() >= self.max_events
+            || self.oldest.is_some_and(|oldest| oldest.elapsed() >= self.max_wait)
+    }
+}
+
+#[async_trait]
+impl EventSink for BufferedEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        if !events.is_empty() {
+            self.oldest.get_or_insert_with(Instant::now);
+            self.buffer.extend_from_slice(events);
+        }
+        if self.should_flush() {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error> {
+        // keep events and gaps in order
+        self.flush().await?;
+        self.inner.save_gap(gap).await
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        self.flush().await?;
+        self.inner.close().await
+    }
+    fn bytes_written(&self) -> Option<u64> {
+        self.inner.bytes_written()
+    }
+    fn errors_handled(&self) -> u64 {
+        self.inner.errors_handled()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::history::{EventSink, test_support::{events, Saves, RecordingEventSink}};
+
+    use super::BufferedEventSink;
+
+    #[tokio::test]
+    async fn flush_on_event_count() {
+        let saves = Saves::default();
+        let mut sink = BufferedEventSink::create_from_sink(
+            Box::new(RecordingEventSink::new(&saves)), 5, Duration::from_secs(3600));
+        sink.save(&events(2)).await.unwrap();
+        sink.save(&events(2)).await.unwrap();
+        assert!(saves.sizes().is_empty());
+        sink.save(&events(2)).await.unwrap();
+        sink.save(&events(1)).await.unwrap();
+        assert_eq!(saves.sizes(), vec![6]);
+        Box::new(sink).close().await.unwrap();
+        assert_eq!(saves.sizes(), vec![6, 1]);
+    }
+
+    #[tokio::test]
+    async fn flush_on_wait() {
+        let saves = Saves::default();
+        let mut sink = BufferedEventSink::create_from_sink(
+            Box::new(RecordingEventSink::new(&saves)), 1000, Duration::ZERO);
+        sink.save(&events(2)).await.unwrap();
+        assert_eq!(saves.sizes(), vec![2]);
+    }
+}
@@ -0,0 +1,301 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex, MutexGuard, PoisonError, atomic::{AtomicU64, Ordering}}};
+
+use async_trait::async_trait;
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
+
+use super::EventSink;
+
+/// What to do with a save when the queue is already full
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum OverflowPolicy {
+    /// wait for the sink to catch up
+    #[default]
+    Block,
+    /// make room by discarding the oldest queued save
+    DropOldest,
+    /// discard the save being made
+    DropNewest,
+    /// fail the save being made with `Error::SinkFull`, and leave it to the
+    /// caller
+    Fail
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "drop-newest" => Ok(OverflowPolicy::DropNewest),
+            "fail" => Ok(OverflowPolicy::Fail),
+            _ => Err(format!("unknown overflow policy: {s} (expected block, drop-oldest, drop-newest or fail)"))
+        }
+    }
+}
+
+enum Message {
+    Events(Vec<DiscoveryEvent>),
+    Gap(ScanGap)
+}
+
+#[derive(Default)]
+struct Queue {
+    messages: VecDeque<Message>,
+    closed: bool,
+    last_error: Option<Error>,
+    /// errors from the inner sink which were replaced by later ones before
+    /// they could be reported
+    unreported: u64,
+    /// as of the inner sink's last save
+    inner_errors_handled: u64,
+    bytes_written: Option<u64>
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    queued: Notify,
+    space: Notify,
+    dropped: AtomicU64
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Hands saves over to `inner` on a task of its own, through a queue of up to
+/// `capacity` saves, so a slow sink doesn't hold up scanning. An error from
+/// `inner` is reported by the next save.
+pub struct ChannelEventSink {
+    shared: Arc<Shared>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    task: JoinHandle<Result<(), Error>>
+}
+
+impl ChannelEventSink {
+    #[must_use] pub fn create_from_sink(inner: Box<dyn EventSink>, capacity: usize, policy: OverflowPolicy) -> ChannelEventSink {
+        let shared = Arc::new(Shared::default());
+        let task = tokio::spawn(drain(inner, shared.clone()));
+        ChannelEventSink { shared, capacity: capacity.max(1), policy, task }
+    }
+
+    /// How many saves have been discarded because the queue was full
+    #[must_use] pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    async fn send(&self, message: Message) -> Result<(), Error> {
+        loop {
+            let space = self.shared.space.notified();
+            {
+                let mut queue = self.shared.lock();
+                if let Some(e) = queue.last_error.take() {
+                    return Err(e);
+                }
+                if queue.messages.len() < self.capacity {
+                    queue.messages.push_back(message);
+                    self.shared.queued.notify_one();
+                    return Ok(());
+                }
+                match self.policy {
+                    OverflowPolicy::Block => {},
+                    OverflowPolicy::DropOldest => {
+                        queue.messages.pop_front();
+                        queue.messages.push_back(message);
+                        self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                        self.shared.queued.notify_one();
+                        return Ok(());
+                    },
+                    OverflowPolicy::DropNewest => {
+                        self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    },
+                    OverflowPolicy::Fail => return Err(Error::SinkFull)
+                }
+            }
+            space.await;
+        }
+    }
+}
+
+async fn drain(mut inner: Box<dyn EventSink>, shared: Arc<Shared>) -> Result<(), Error> {
+    loop {
+        let queued = shared.queued.notified();
+        let next = {
+            let mut queue = shared.lock();
+            match queue.messages.pop_front() {
+                Some(message) => Some(message),
+                None if queue.closed => break,
+                None => None
+            }
+        };
+        let Some(message) = next else {
+            queued.await;
+            continue;
+        };
+        shared.space.notify_one();
+        let result = match &message {
+            Message::Events(events) => inner.save(events).await,
+            Message::Gap(gap) => inner.save_gap(gap).await
+        };
+        let mut queue = shared.lock();
+        if let Err(e) = result {
+            tracing::warn!("recording failed: {e}");
+            if queue.last_error.replace(e).is_some() {
+                queue.unreported += 1;
+            }
+        }
+        queue.inner_errors_handled = inner.errors_handled();
+        queue.bytes_written = inner.bytes_written();
+    }
+    inner.close().await
+}
+
+#[async_trait]
+impl EventSink for ChannelEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.send(Message::Events(events.to_vec())).await
+    }
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error> {
+        self.send(Message::Gap(gap.clone())).await
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        self.shared.lock().closed = true;
+        self.shared.queued.notify_one();
+        (&mut self.task).await??;
+        match self.shared.lock().last_error.take() {
+            Some(e) => Err(e),
+            None => Ok(())
+        }
+    }
+    fn bytes_written(&self) -> Option<u64> {
+        self.shared.lock().bytes_written
+    }
+    fn errors_handled(&self) -> u64 {
+        let queue = self.shared.lock();
+        queue.inner_errors_handled + queue.unreported
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use async_trait::async_trait;
+    use tokio::sync::Semaphore;
+
+    use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap, history::{EventSink, test_support::{event, Saves, RecordingEventSink, FailingEventSink}}};
+
+    use super::{ChannelEventSink, OverflowPolicy};
+
+    async fn run(policy: OverflowPolicy) -> (Vec<i64>, u64) {
+        let gate = Arc::new(Semaphore::new(0));
+        let saves = Saves::default();
+        let mut sink = ChannelEventSink::create_from_sink(
+            Box::new(RecordingEventSink::gated(&saves, &gate)), 2, policy);
+        // the first is taken off the queue straight away, and then blocks on the gate
+        sink.save(&[event(1)]).await.unwrap();
+        tokio::task::yield_now().await;
+        for i in 2..=5 {
+            if policy == OverflowPolicy::Block && i > 3 {
+                gate.add_permits(1);
+            }
+            sink.save(&[event(i)]).await.unwrap();
+        }
+        gate.add_permits(10);
+        let dropped = sink.dropped();
+        Box::new(sink).close().await.unwrap();
+        (saves.timestamps(), dropped)
+    }
+
+    #[tokio::test]
+    async fn block_when_full() {
+        assert_eq!(run(OverflowPolicy::Block).await, (vec![1, 2, 3, 4, 5], 0));
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_when_full() {
+        assert_eq!(run(OverflowPolicy::DropOldest).await, (vec![1, 4, 5], 2));
+    }
+
+    #[tokio::test]
+    async fn drop_newest_when_full() {
+        assert_eq!(run(OverflowPolicy::DropNewest).await, (vec![1, 2, 3], 2));
+    }
+
+    #[tokio::test]
+    async fn fail_when_full() {
+        let gate = Arc::new(Semaphore::new(0));
+        let saves = Saves::default();
+        let mut sink = ChannelEventSink::create_from_sink(
+            Box::new(RecordingEventSink::gated(&saves, &gate)), 1, OverflowPolicy::Fail);
+        sink.save(&[event(1)]).await.unwrap();
+        tokio::task::yield_now().await;
+        sink.save(&[event(2)]).await.unwrap();
+        assert!(matches!(sink.save(&[event(3)]).await, Err(Error::SinkFull)));
+        gate.add_permits(10);
+        Box::new(sink).close().await.unwrap();
+        assert_eq!(saves.timestamps(), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn report_errors() {
+        let mut sink = ChannelEventSink::create_from_sink(Box::new(FailingEventSink), 10, OverflowPolicy::Block);
+        sink.save(&[event(1)]).await.unwrap();
+        assert!(matches!(Box::new(sink).close().await, Err(Error::Io(e)) if e.to_string() == "offline"));
+    }
+
+    #[tokio::test]
+    async fn pass_on_errors_handled() {
+        /// Handles its own errors, as a tee does when only some of its sinks fail
+        #[derive(Default)]
+        struct ForgivingEventSink {
+            errors: u64
+        }
+
+        #[async_trait]
+        impl EventSink for ForgivingEventSink {
+            async fn save(&mut self, _: &[DiscoveryEvent]) -> Result<(), Error> {
+                self.errors += 1;
+                Ok(())
+            }
+            async fn save_gap(&mut self, _: &ScanGap) -> Result<(), Error> {
+                Ok(())
+            }
+            async fn close(mut self: Box<Self>) -> Result<(), Error> {
+                Ok(())
+            }
+            fn errors_handled(&self) -> u64 {
+                self.errors
+            }
+        }
+
+        let mut sink = ChannelEventSink::create_from_sink(Box::<ForgivingEventSink>::default(), 10, OverflowPolicy::Block);
+        sink.save(&[event(1)]).await.unwrap();
+        sink.save(&[event(2)]).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sink.errors_handled() < 2 {
+                tokio::task::yield_now().await;
+            }
+        }).await.unwrap();
+        Box::new(sink).close().await.unwrap();
+    }
+
+    #[test]
+    fn parse_policies() {
+        assert_eq!("block".parse::<OverflowPolicy>().unwrap(), OverflowPolicy::Block);
+        assert_eq!("drop-oldest".parse::<OverflowPolicy>().unwrap(), OverflowPolicy::DropOldest);
+        assert_eq!("drop-newest".parse::<OverflowPolicy>().unwrap(), OverflowPolicy::DropNewest);
+        assert_eq!("fail".parse::<OverflowPolicy>().unwrap(), OverflowPolicy::Fail);
+        assert!("drop".parse::<OverflowPolicy>().is_err());
+    }
+}
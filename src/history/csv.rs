@@ -0,0 +1,133 @@
+use std::io::Write;
+
+use async_trait::async_trait;
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
+
+use super::EventSink;
+
+/// Writes one row per event, as `date_time,kind,signature,rssi`, for opening
+/// in a spreadsheet. There is no column for gaps, so they are not recorded.
+/// The signature policy, if known, is recorded in a `#` comment line before
+/// the header row.
+pub struct CsvEventSink {
+    writer: csv::Writer<CountingWriter>
+}
+
+const HEADER_PREFIX : &str = "# signature_policy: ";
+
+/// The signature policy id recorded before the header row, if `line` is it
+#[must_use] pub fn parse_header(line: &str) -> Option<String> {
+    line.trim_end().strip_prefix(HEADER_PREFIX).map(str::to_string)
+}
+
+struct CountingWriter {
+    inner: Box<dyn Write + Send>,
+    bytes: u64
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// What starts a file: the signature policy comment, if known, and the header
+/// row
+pub fn preamble(policy: Option<&str>) -> Result<Vec<u8>, Error> {
+    let mut preamble = vec![];
+    if let Some(policy) = policy {
+        writeln!(preamble, "{HEADER_PREFIX}{policy}")?;
+    }
+    let mut writer = csv::Writer::from_writer(&mut preamble);
+    writer.write_record(["date_time", "kind", "signature", "rssi"])?;
+    writer.flush()?;
+    drop(writer);
+    Ok(preamble)
+}
+
+impl CsvEventSink {
+    /// The header row is only written if `write_header` is set, so that
+    /// appending to an existing file doesn't repeat it
+    pub fn create_from_writer(writer: Box<dyn Write + Send>, write_header: bool, policy: Option<&str>) -> Result<CsvEventSink, Error> {
+        let mut counting = CountingWriter { inner: writer, bytes: 0 };
+        if write_header {
+            counting.write_all(&preamble(policy)?)?;
+            counting.flush()?;
+        }
+        Ok(CsvEventSink { writer: csv::Writer::from_writer(counting) })
+    }
+}
+
+#[async_trait]
+impl EventSink for CsvEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        for e in events {
+            self.writer.write_record([
+                e.date_time.to_rfc3339(),
+                e.signature.kind().to_string(),
+                e.signature.value().to_string(),
+                e.rssi.to_string()
+            ])?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+    async fn save_gap(&mut self, _: &ScanGap) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.writer.get_ref().bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+
+    use chrono::{Utc, TimeZone};
+
+    use crate::{discover::DiscoveryEvent, signature::{Signature, SignaturePolicy}, history::EventSink};
+
+    use super::{CsvEventSink, parse_header};
+
+    #[tokio::test]
+    async fn write_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.csv");
+        let mut sink = Box::new(CsvEventSink::create_from_writer(Box::new(File::create(&path).unwrap()), true, None).unwrap());
+        sink.save(&[
+            DiscoveryEvent::new(Utc.timestamp_opt(1, 0).unwrap(), Signature::Named("Device, 1".to_string()), -20),
+            DiscoveryEvent::new(Utc.timestamp_opt(2, 0).unwrap(), Signature::Anonymous("503eb25838435ebb288f3b657b9f9031".to_string()), -30),
+        ]).await.unwrap();
+        sink.close().await.unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\
+date_time,kind,signature,rssi
+1970-01-01T00:00:01+00:00,named,\"Device, 1\",-20
+1970-01-01T00:00:02+00:00,anonymous,503eb25838435ebb288f3b657b9f9031,-30
+");
+    }
+
+    #[tokio::test]
+    async fn record_signature_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.csv");
+        let sink = Box::new(CsvEventSink::create_from_writer(Box::new(File::create(&path).unwrap()), true, Some(&SignaturePolicy::Xxh3.id())).unwrap());
+        sink.close().await.unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(written, "# signature_policy: xxh3\ndate_time,kind,signature,rssi\n");
+        assert_eq!(parse_header(written.lines().next().unwrap()), Some("xxh3".to_string()));
+    }
+}
@@ -0,0 +1,363 @@
+use std::{fs, io::{self, Read, Write}, path::Path};
+
+use chacha20poly1305::{aead::{Aead, AeadCore, KeyInit, OsRng, Payload}, XChaCha20Poly1305, XNonce};
+
+const NONCE_SIZE : usize = 24;
+/// nonce, flags and ciphertext length
+const HEADER_SIZE : usize = NONCE_SIZE + 1 + 4;
+/// set on the last chunk written by an `EncryptingWriter`
+const FINAL : u8 = 1;
+
+/// Authenticates where a chunk comes in the run of chunks written by one
+/// `EncryptingWriter`, and whether it ends that run, so that chunks can't be
+/// reordered, or dropped from the end, without it being noticed
+fn associated_data(index: u64, flags: u8) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&index.to_be_bytes());
+    aad[8] = flags;
+    aad
+}
+
+/// A 256-bit key, kept in a file as 64 hex characters (e.g. as generated by
+/// `openssl rand -hex 32`)
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn from_hex(hex: &str) -> Result<EncryptionKey, String> {
+        let hex = hex.trim();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err("encryption key must be 64 hex characters".to_string());
+        }
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| "encryption key must be 64 hex characters".to_string())?;
+        }
+        Ok(EncryptionKey(key))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<EncryptionKey, String> {
+        let path = path.as_ref();
+        let hex = fs::read_to_string(path).map_err(|e| format!("can't read key from {}: {e}", path.display()))?;
+        EncryptionKey::from_hex(&hex)
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+impl PartialEq for EncryptionKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// Encrypts whatever has been written each time it is flushed, as a chunk of
+/// `nonce | flags | length | ciphertext`. Each chunk is bound to its place in
+/// the run written by this writer, and the run is ended by a final chunk when
+/// finished (or dropped). A file can be appended to across runs and still be
+/// read back with a `DecryptingReader`.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    cipher: XChaCha20Poly1305,
+    plaintext: Vec<u8>,
+    /// chunks written so far in this run
+    chunks: u64,
+    finished: bool
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn create(inner: W, key: &EncryptionKey) -> EncryptingWriter<W> {
+        EncryptingWriter { inner, cipher: key.cipher(), plaintext: vec![], chunks: 0, finished: false }
+    }
+
+    /// Writes out the final chunk. Anything written afterwards starts a new run.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.write_chunk(FINAL)?;
+            self.finished = true;
+        }
+        self.inner.flush()
+    }
+
+    fn write_chunk(&mut self, flags: u8) -> io::Result<()> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = associated_data(self.chunks, flags);
+        let ciphertext = self.cipher.encrypt(&nonce, Payload { msg: &self.plaintext, aad: &aad })
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        let length = u32::try_from(ciphertext.len()).map_err(io::Error::other)?;
+        let mut chunk = Vec::with_capacity(HEADER_SIZE + ciphertext.len());
+        chunk.extend_from_slice(&nonce);
+        chunk.push(flags);
+        chunk.extend_from_slice(&length.to_be_bytes());
+        chunk.extend_from_slice(&ciphertext);
+        self.inner.write_all(&chunk)?;
+        self.plaintext.clear();
+        self.chunks += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            self.finished = false;
+            self.chunks = 0;
+        }
+        self.plaintext.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.plaintext.is_empty() {
+            self.write_chunk(0)?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for EncryptingWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "truncated, or not encrypted")
+}
+
+fn undecryptable() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "can't decrypt (wrong key, or corrupted)")
+}
+
+/// Reads back what was written by an `EncryptingWriter`, failing if any chunk
+/// has been tampered with or reordered, or was encrypted with a different key.
+/// Runs that were cut short are read up to where they were cut, with a warning.
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    cipher: XChaCha20Poly1305,
+    plaintext: Vec<u8>,
+    position: usize,
+    /// where the next chunk should come in its run
+    index: u64,
+    /// whether the last chunk read ended its run, so that the input can end
+    finished: bool,
+    /// whether any chunk has been read, as otherwise a cut off chunk is more
+    /// likely to be a file that was never encrypted
+    started: bool
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn create(inner: R, key: &EncryptionKey) -> DecryptingReader<R> {
+        DecryptingReader { inner, cipher: key.cipher(), plaintext: vec![], position: 0, index: 0, finished: true, started: false }
+    }
+
+    /// Like `read_exact`, but returns how much was read if the input ends first
+    fn read_up_to(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.inner.read(&mut buf[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(read)
+    }
+
+    fn cut_off(&self) -> io::Result<bool> {
+        if !self.started {
+            return Err(truncated());
+        }
+        tracing::warn!("encrypted recording ends part way through a chunk, which has been left out");
+        Ok(false)
+    }
+
+    /// Returns false once there are no more chunks. A run that ends without
+    /// its final chunk is what a writer that was killed, or lost power, leaves
+    /// behind, so rather than losing everything after it, what it did write is
+    /// kept, with a warning that its end may be missing.
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        let mut header = [0u8; HEADER_SIZE];
+        match self.read_up_to(&mut header)? {
+            0 if self.finished => return Ok(false),
+            0 => {
+                tracing::warn!("encrypted recording ends part way through a run, so may be missing its end");
+                return Ok(false);
+            },
+            HEADER_SIZE => {},
+            _ => return self.cut_off()
+        }
+        let nonce = XNonce::from_slice(&header[..NONCE_SIZE]);
+        let flags = header[NONCE_SIZE];
+        let length = u32::from_be_bytes(header[NONCE_SIZE + 1..].try_into().unwrap_or_default());
+        let mut ciphertext = vec![0u8; length as usize];
+        if self.read_up_to(&mut ciphertext)? < ciphertext.len() {
+            return self.cut_off();
+        }
+        let decrypt = |index: u64| {
+            self.cipher.decrypt(nonce, Payload { msg: &ciphertext, aad: &associated_data(index, flags) }).ok()
+        };
+        let (index, plaintext) = match decrypt(self.index) {
+            Some(plaintext) => (self.index, plaintext),
+            None if !self.finished => match decrypt(0) {
+                Some(plaintext) => {
+                    tracing::warn!("encrypted recording has a run that ended without its final chunk, so may be missing its end");
+                    (0, plaintext)
+                },
+                None => return Err(undecryptable())
+            },
+            None => return Err(undecryptable())
+        };
+        self.plaintext = plaintext;
+        self.position = 0;
+        self.started = true;
+        self.finished = flags & FINAL != 0;
+        self.index = if self.finished { 0 } else { index + 1 };
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.plaintext.len() {
+            if !self.next_chunk()? {
+                return Ok(0);
+            }
+        }
+        let available = &self.plaintext[self.position..];
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Write, Read, Cursor};
+
+    use super::{EncryptionKey, EncryptingWriter, DecryptingReader, HEADER_SIZE};
+
+    fn key(byte: &str) -> EncryptionKey {
+        EncryptionKey::from_hex(&byte.repeat(32)).unwrap()
+    }
+
+    #[test]
+    fn parse_keys() {
+        assert!(EncryptionKey::from_hex(&"0a".repeat(32)).is_ok());
+        assert!(EncryptionKey::from_hex(&format!("{}\n", "0a".repeat(32))).is_ok());
+        assert!(EncryptionKey::from_hex("0a0a").is_err());
+        assert!(EncryptionKey::from_hex(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut encrypted = vec![];
+        {
+            let mut writer = EncryptingWriter::create(&mut encrypted, &key("01"));
+            writeln!(writer, "first").unwrap();
+            writer.flush().unwrap();
+            writeln!(writer, "second").unwrap();
+        }
+        assert!(!String::from_utf8_lossy(&encrypted).contains("first"));
+
+        let mut decrypted = String::new();
+        DecryptingReader::create(Cursor::new(&encrypted), &key("01")).read_to_string(&mut decrypted).unwrap();
+        assert_eq!(decrypted, "first\nsecond\n");
+    }
+
+    /// Two runs, as when a file is appended to, the first as two chunks
+    fn two_runs() -> Vec<u8> {
+        let mut encrypted = vec![];
+        {
+            let mut writer = EncryptingWriter::create(&mut encrypted, &key("01"));
+            writeln!(writer, "first").unwrap();
+            writer.flush().unwrap();
+            writeln!(writer, "second").unwrap();
+        }
+        {
+            let mut writer = EncryptingWriter::create(&mut encrypted, &key("01"));
+            writeln!(writer, "third").unwrap();
+        }
+        encrypted
+    }
+
+    fn decrypt(encrypted: &[u8]) -> std::io::Result<String> {
+        let mut decrypted = String::new();
+        DecryptingReader::create(Cursor::new(encrypted), &key("01")).read_to_string(&mut decrypted)?;
+        Ok(decrypted)
+    }
+
+    #[test]
+    fn read_appended_runs() {
+        assert_eq!(decrypt(&two_runs()).unwrap(), "first\nsecond\nthird\n");
+    }
+
+    #[test]
+    fn keep_what_was_written_before_being_cut_off() {
+        let encrypted = two_runs();
+        // the second run is a single chunk, holding "third\n" and its tag
+        let second_run = HEADER_SIZE + "third\n".len() + 16;
+        assert_eq!(decrypt(&encrypted[..encrypted.len() - second_run]).unwrap(), "first\nsecond\n");
+        assert_eq!(decrypt(&encrypted[..encrypted.len() - 1]).unwrap(), "first\nsecond\n");
+        assert!(decrypt(&encrypted[..HEADER_SIZE - 1]).is_err());
+        assert!(decrypt(b"{\"not\": \"encrypted\"}\n").is_err());
+    }
+
+    #[test]
+    fn read_runs_appended_after_a_killed_writer() {
+        let mut encrypted = vec![];
+        {
+            let mut writer = EncryptingWriter::create(&mut encrypted, &key("01"));
+            writeln!(writer, "first").unwrap();
+            writer.flush().unwrap();
+            writeln!(writer, "second").unwrap();
+            writer.flush().unwrap();
+            // killed, so never finished
+            std::mem::forget(writer);
+        }
+        {
+            let mut writer = EncryptingWriter::create(&mut encrypted, &key("01"));
+            writeln!(writer, "third").unwrap();
+            writer.flush().unwrap();
+            writeln!(writer, "fourth").unwrap();
+        }
+        assert_eq!(decrypt(&encrypted).unwrap(), "first\nsecond\nthird\nfourth\n");
+    }
+
+    #[test]
+    fn notice_reordering() {
+        let encrypted = two_runs();
+        let chunk = |start: usize| {
+            let length = u32::from_be_bytes(encrypted[start + HEADER_SIZE - 4..start + HEADER_SIZE].try_into().unwrap());
+            start..start + HEADER_SIZE + length as usize
+        };
+        let first = chunk(0);
+        let second = chunk(first.end);
+        let mut reordered = encrypted[second.clone()].to_vec();
+        reordered.extend_from_slice(&encrypted[first]);
+        reordered.extend_from_slice(&encrypted[second.end..]);
+        assert!(decrypt(&reordered).is_err());
+    }
+
+    #[test]
+    fn wrong_key() {
+        let mut encrypted = vec![];
+        {
+            let mut writer = EncryptingWriter::create(&mut encrypted, &key("01"));
+            writeln!(writer, "secret").unwrap();
+        }
+        let mut decrypted = String::new();
+        assert!(DecryptingReader::create(Cursor::new(&encrypted), &key("02")).read_to_string(&mut decrypted).is_err());
+    }
+}
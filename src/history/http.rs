@@ -0,0 +1,261 @@
+use std::{fs::{self, File, OpenOptions}, io::{self, BufRead, BufReader, Seek, SeekFrom, Write}, path::{Path, PathBuf}, time::Duration};
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
+
+use super::EventSink;
+
+const BATCH_SIZE : usize = 500;
+const MAX_ATTEMPTS : u32 = 3;
+const REQUEST_TIMEOUT : Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+struct GapLine<'a> {
+    gap: &'a ScanGap
+}
+
+/// POSTs events as newline-delimited JSON (the same lines as a `.jsonl`
+/// recording), e.g. to Vector or Logstash. Batches which can't be delivered
+/// are appended to a spool file, if given, which is sent (`BATCH_SIZE` lines
+/// at a time) ahead of the next batch.
+pub struct HttpEventSink {
+    client: reqwest::Client,
+    url: String,
+    spool: Option<PathBuf>,
+    pending: Vec<String>,
+    bytes: u64
+}
+
+impl HttpEventSink {
+    pub fn create_from_url(url: &str, spool: Option<PathBuf>) -> Result<HttpEventSink, Error> {
+        Ok(HttpEventSink {
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            url: url.to_string(),
+            spool,
+            pending: vec![],
+            bytes: 0
+        })
+    }
+
+    async fn post(&self, body: String) -> Result<(), reqwest::Error> {
+        let mut attempt = 1;
+        loop {
+            let request = self.client.post(&self.url)
+                .header("Content-Type", "application/x-ndjson")
+                .body(body.clone());
+            match request.send().await.and_then(reqwest::Response::error_for_status) {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt >= MAX_ATTEMPTS => return Err(e),
+                Err(e) => {
+                    tracing::warn!("posting to {} failed (attempt {attempt} of {MAX_ATTEMPTS}): {e}", self.url);
+                    tokio::time::sleep(Duration::from_millis(250 * 2u64.pow(attempt))).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Sends what was spooled, a batch at a time, dropping each batch from
+    /// the spool once it's delivered. Returns whether the spool is now empty.
+    async fn flush_spool(&mut self, spool: &Path) -> Result<bool, Error> {
+        if !spool.exists() {
+            return Ok(true);
+        }
+        let mut reader = BufReader::new(File::open(spool)?);
+        let mut delivered = 0;
+        loop {
+            let mut body = String::new();
+            let mut lines = 0;
+            while lines < BATCH_SIZE && reader.read_line(&mut body)? > 0 {
+                lines += 1;
+            }
+            if lines == 0 {
+                break;
+            }
+            let length = body.len() as u64;
+            if let Err(e) = self.post(body).await {
+                tracing::warn!("posting spooled events to {} failed: {e}", self.url);
+                drop(reader);
+                if delivered > 0 {
+                    keep_from(spool, delivered)?;
+                }
+                return Ok(false);
+            }
+            delivered += length;
+            self.bytes += length;
+        }
+        drop(reader);
+        fs::remove_file(spool)?;
+        Ok(true)
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        let spool_empty = match self.spool.clone() {
+            Some(spool) => self.flush_spool(&spool).await?,
+            None => true
+        };
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut body = self.pending.join("\n");
+        body.push('\n');
+        if spool_empty {
+            match self.post(body.clone()).await {
+                Ok(()) => {
+                    self.bytes += body.len() as u64;
+                    self.pending.clear();
+                    return Ok(());
+                },
+                // leave the lines pending, so they are retried on the next flush
+                Err(e) if self.spool.is_none() => return Err(e.into()),
+                Err(e) => tracing::warn!("posting to {} failed, spooling: {e}", self.url)
+            }
+        }
+        if let Some(spool) = &self.spool {
+            // keep hold of them on disk, behind anything already there, until the endpoint is back
+            OpenOptions::new().create(true).append(true).open(spool)?.write_all(body.as_bytes())?;
+        }
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Replaces `path` with what it holds from `offset` onwards
+fn keep_from(path: &Path, offset: u64) -> io::Result<()> {
+    let mut remaining = File::open(path)?;
+    remaining.seek(SeekFrom::Start(offset))?;
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let temp = path.with_file_name(name);
+    io::copy(&mut remaining, &mut File::create(&temp)?)?;
+    fs::rename(temp, path)
+}
+
+#[async_trait]
+impl EventSink for HttpEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        for event in events {
+            self.pending.push(serde_json::to_string(event)?);
+        }
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error> {
+        self.pending.push(serde_json::to_string(&GapLine { gap })?);
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        self.flush().await
+    }
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use chrono::{Utc, TimeZone};
+    use tokio::{net::TcpListener, io::{AsyncReadExt, AsyncWriteExt}, task::JoinHandle};
+
+    use crate::{discover::DiscoveryEvent, signature::Signature, history::EventSink};
+
+    use super::{HttpEventSink, BATCH_SIZE};
+
+    fn event(seconds: i64) -> DiscoveryEvent {
+        DiscoveryEvent::new(Utc.timestamp_opt(seconds, 0).unwrap(), Signature::Named("Device 1".to_string()), -20)
+    }
+
+    fn line(seconds: i64) -> String {
+        serde_json::to_string(&event(seconds)).unwrap() + "\n"
+    }
+
+    /// Accepts `count` POSTs and returns their bodies, after which nothing
+    /// is listening
+    async fn endpoint(count: usize) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut bodies = vec![];
+            while bodies.len() < count {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buf = [0u8; 4096];
+                loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                        let length : usize = headers.lines()
+                            .find_map(|h| h.to_lowercase().strip_prefix("content-length:").map(|l| l.trim().parse().unwrap()))
+                            .unwrap();
+                        if body.len() >= length {
+                            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await.unwrap();
+                            bodies.push(body.to_string());
+                            break;
+                        }
+                    }
+                }
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    async fn offline() -> String {
+        // nothing is listening once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}/ingest", listener.local_addr().unwrap())
+    }
+
+    #[tokio::test]
+    async fn post_ndjson() {
+        let (url, endpoint) = endpoint(1).await;
+        let mut sink = Box::new(HttpEventSink::create_from_url(&url, None).unwrap());
+        sink.save(&[event(1), event(2)]).await.unwrap();
+        sink.close().await.unwrap();
+
+        assert_eq!(endpoint.await.unwrap(), vec![line(1) + &line(2)]);
+    }
+
+    #[tokio::test]
+    async fn spool_while_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = dir.path().join("spool.jsonl");
+        let mut sink = Box::new(HttpEventSink::create_from_url(&offline().await, Some(spool.clone())).unwrap());
+        sink.save(&[event(1)]).await.unwrap();
+        sink.close().await.unwrap();
+        assert_eq!(fs::read_to_string(&spool).unwrap(), line(1));
+
+        let (url, endpoint) = endpoint(2).await;
+        let mut sink = Box::new(HttpEventSink::create_from_url(&url, Some(spool.clone())).unwrap());
+        sink.save(&[event(2)]).await.unwrap();
+        sink.close().await.unwrap();
+        assert_eq!(endpoint.await.unwrap(), vec![line(1), line(2)]);
+        assert!(!spool.exists());
+    }
+
+    #[tokio::test]
+    async fn send_spool_in_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = dir.path().join("spool.jsonl");
+        let spooled : Vec<String> = (0..=BATCH_SIZE as i64).map(line).collect();
+        fs::write(&spool, spooled.concat()).unwrap();
+
+        // the endpoint goes away after the first batch
+        let (url, endpoint) = endpoint(1).await;
+        let mut sink = Box::new(HttpEventSink::create_from_url(&url, Some(spool.clone())).unwrap());
+        sink.save(&[event(1_000)]).await.unwrap();
+        sink.close().await.unwrap();
+        assert_eq!(endpoint.await.unwrap(), vec![spooled[..BATCH_SIZE].concat()]);
+        assert_eq!(fs::read_to_string(&spool).unwrap(), spooled[BATCH_SIZE].clone() + &line(1_000));
+    }
+}
@@ -0,0 +1,321 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use url::Url;
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap, signature::Signature};
+
+use super::EventSink;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct InfluxConfig {
+    pub write_url: String,
+    pub token: Option<String>,
+    pub source: String,
+    pub batch_size: usize
+}
+
+/// Where the API token is taken from, so that it isn't part of the url
+pub const TOKEN_VAR : &str = "INFLUX_TOKEN";
+
+impl InfluxConfig {
+    /// Parses `influx://host[:port]/bucket?org=...[&source=...][&batch=...]`,
+    /// which is written to using the InfluxDB v2 HTTP API, with the token (if
+    /// any) from `$INFLUX_TOKEN`
+    pub fn from_url(url: &str) -> Result<InfluxConfig, Error> {
+        InfluxConfig::from_url_and_token(url, std::env::var(TOKEN_VAR).ok().filter(|t| !t.is_empty()))
+    }
+
+    fn from_url_and_token(url: &str, token: Option<String>) -> Result<InfluxConfig, Error> {
+        let parsed = Url::parse(url)?;
+        if parsed.scheme() != "influx" {
+            return Err(Error::InvalidUrl { url: url.to_string(), reason: "not influx://".to_string() });
+        }
+        let invalid = |reason: &str| Error::InvalidUrl { url: url.to_string(), reason: reason.to_string() };
+        let host = parsed.host_str().ok_or_else(|| invalid("no host"))?;
+        let port = parsed.port().unwrap_or(8086);
+        let bucket = parsed.path().trim_start_matches('/');
+        if bucket.is_empty() {
+            return Err(invalid("no bucket"));
+        }
+        let query = |name: &str| parsed.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.to_string());
+        if query("token").is_some() {
+            return Err(invalid(&format!("tokens in urls end up in shell history; set ${TOKEN_VAR} instead")));
+        }
+        let org = query("org").ok_or_else(|| invalid("no org"))?;
+        let source = query("source").unwrap_or("local".to_string());
+        if escape_tag(&source).is_none() {
+            return Err(invalid("empty source"));
+        }
+        let batch_size = match query("batch") {
+            Some(b) => b.parse().map_err(|_| invalid("invalid batch size"))?,
+            None => 500
+        };
+        let mut write_url = Url::parse(&format!("http://{host}:{port}/api/v2/write"))?;
+        write_url.query_pairs_mut()
+            .append_pair("org", &org)
+            .append_pair("bucket", bucket)
+            .append_pair("precision", "ns");
+        Ok(InfluxConfig {
+            write_url: write_url.to_string(),
+            token,
+            source,
+            batch_size
+        })
+    }
+}
+
+/// Writes events as InfluxDB line protocol, in batches of `batch_size` lines.
+/// While the server can't be reached, or is struggling, lines are kept (up to
+/// `MAX_PENDING`, after which the oldest are dropped) and retried with a
+/// growing backoff, rather than holding up saving. Batches the server refuses
+/// (e.g. a bad token, or an unknown bucket) are dropped, as retrying them
+/// would only fail again.
+pub struct InfluxEventSink {
+    client: reqwest::Client,
+    config: InfluxConfig,
+    pending: VecDeque<String>,
+    failures: u32,
+    retry_at: Option<Instant>,
+    dropped: u64,
+    bytes: u64
+}
+
+const REQUEST_TIMEOUT : Duration = Duration::from_secs(10);
+const MAX_PENDING : usize = 100_000;
+const MAX_BACKOFF : Duration = Duration::from_secs(60);
+
+impl InfluxEventSink {
+    pub fn create_from_config(config: &InfluxConfig) -> Result<InfluxEventSink, Error> {
+        Ok(InfluxEventSink {
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            config: config.clone(),
+            pending: VecDeque::new(),
+            failures: 0,
+            retry_at: None,
+            dropped: 0,
+            bytes: 0
+        })
+    }
+
+    fn queue(&mut self, lines: impl Iterator<Item = String>) {
+        self.pending.extend(lines);
+        let excess = self.pending.len().saturating_sub(MAX_PENDING);
+        if excess > 0 {
+            if self.dropped == 0 {
+                tracing::warn!("influx: too many lines waiting to be written, dropping the oldest");
+            }
+            self.pending.drain(..excess);
+            self.dropped += excess as u64;
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let body = self.pending.iter().map(String::as_str).collect::<Vec<_>>().join("\n");
+        let mut request = self.client.post(&self.config.write_url).body(body.clone());
+        if let Some(token) = &self.config.token {
+            request = request.header("Authorization", format!("Token {token}"));
+        }
+        request.send().await.and_then(reqwest::Response::error_for_status)?;
+        self.bytes += body.len() as u64;
+        self.pending.clear();
+        if self.dropped > 0 {
+            tracing::warn!("influx: dropped {} lines while the server couldn't be reached", self.dropped);
+            self.dropped = 0;
+        }
+        Ok(())
+    }
+
+    /// Failures are logged, and the lines either dropped, if the server
+    /// refused them, or kept to be retried after a backoff
+    async fn try_flush(&mut self) {
+        if self.retry_at.is_some_and(|at| Instant::now() < at) {
+            return;
+        }
+        match self.flush().await {
+            Ok(()) => {
+                self.failures = 0;
+                self.retry_at = None;
+            },
+            Err(Error::Http(e)) if e.status().is_some_and(|s| s.is_client_error() && s != StatusCode::TOO_MANY_REQUESTS) => {
+                tracing::warn!("influx refused {} lines, dropping them: {e}", self.pending.len());
+                self.pending.clear();
+                self.failures = 0;
+                self.retry_at = None;
+            },
+            Err(e) => {
+                self.failures += 1;
+                let backoff = Duration::from_millis(500 * 2u64.pow(self.failures.min(7))).min(MAX_BACKOFF);
+                tracing::warn!("writing to influx failed (retrying in {}s): {e}", backoff.as_secs());
+                self.retry_at = Some(Instant::now() + backoff);
+            }
+        }
+    }
+}
+
+/// Line protocol has no way of escaping line breaks, so they are replaced by
+/// spaces. `None` if nothing is left, as tags can't be empty.
+fn escape_tag(value: &str) -> Option<String> {
+    let escaped = value.replace(['\n', '\r'], " ")
+        .replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ");
+    (!escaped.is_empty()).then_some(escaped)
+}
+
+/// `None` for events which can't be written, as their signature is empty
+fn to_line(event: &DiscoveryEvent, source: &str) -> Option<String> {
+    let (kind, value) = match &event.signature {
+        Signature::Named(n) => ("named", n),
+        Signature::Anonymous(d) => ("anonymous", d)
+    };
+    Some(format!("rssi,kind={kind},signature={},source={} value={}i {}",
+        escape_tag(value)?, escape_tag(source)?, event.rssi, event.date_time.timestamp_nanos_opt().unwrap_or_default()))
+}
+
+fn gap_to_line(gap: &ScanGap, source: &str) -> Option<String> {
+    Some(format!("scan_gap,reason={},source={} duration={}i {}",
+        gap.reason, escape_tag(source)?, gap.duration().num_seconds(), gap.start.timestamp_nanos_opt().unwrap_or_default()))
+}
+
+#[async_trait]
+impl EventSink for InfluxEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        let lines : Vec<String> = events.iter().filter_map(|e| to_line(e, &self.config.source)).collect();
+        self.queue(lines.into_iter());
+        if self.pending.len() >= self.config.batch_size {
+            self.try_flush().await;
+        }
+        Ok(())
+    }
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error> {
+        let line = gap_to_line(gap, &self.config.source);
+        self.queue(line.into_iter());
+        Ok(())
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        self.flush().await
+    }
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Utc, TimeZone};
+
+    use tokio::{net::TcpListener, io::{AsyncReadExt, AsyncWriteExt}};
+
+    use crate::{discover::DiscoveryEvent, signature::Signature, gap::{ScanGap, GapReason}, history::EventSink};
+
+    use super::{InfluxConfig, InfluxEventSink, MAX_PENDING, to_line, gap_to_line};
+
+    #[test]
+    fn url_to_config() {
+        assert_eq!(InfluxConfig::from_url_and_token("influx://localhost/ble?org=home&source=hall&batch=10", Some("abc".to_string())).unwrap(), InfluxConfig {
+            write_url: "http://localhost:8086/api/v2/write?org=home&bucket=ble&precision=ns".to_string(),
+            token: Some("abc".to_string()),
+            source: "hall".to_string(),
+            batch_size: 10
+        });
+    }
+
+    #[test]
+    fn invalid_urls() {
+        for invalid in ["http://localhost/ble?org=home", "influx://localhost?org=home", "influx://localhost/ble", "influx://localhost/ble?org=home&token=abc"] {
+            assert!(InfluxConfig::from_url(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn event_line_protocol() {
+        let named = DiscoveryEvent::new(
+            Utc.timestamp_opt(1, 0).unwrap(),
+            Signature::Named("Device 1, v=2".to_string()),
+            -20);
+        assert_eq!(to_line(&named, "local").unwrap(),
+            "rssi,kind=named,signature=Device\\ 1\\,\\ v\\=2,source=local value=-20i 1000000000");
+        let anonymous = DiscoveryEvent::new(
+            Utc.timestamp_opt(2, 0).unwrap(),
+            Signature::Anonymous("503eb25838435ebb288f3b657b9f9031".to_string()),
+            -30);
+        assert_eq!(to_line(&anonymous, "local").unwrap(),
+            "rssi,kind=anonymous,signature=503eb25838435ebb288f3b657b9f9031,source=local value=-30i 2000000000");
+    }
+
+    #[test]
+    fn line_breaks_and_empty_tags() {
+        let named = |name: &str| DiscoveryEvent::new(Utc.timestamp_opt(1, 0).unwrap(), Signature::Named(name.to_string()), -20);
+        assert_eq!(to_line(&named("Device\nrssi,kind=named 1\r"), "local").unwrap(),
+            "rssi,kind=named,signature=Device\\ rssi\\,kind\\=named\\ 1\\ ,source=local value=-20i 1000000000");
+        assert_eq!(to_line(&named(""), "local"), None);
+        assert!(InfluxConfig::from_url("influx://localhost/ble?org=home&source=").is_err());
+    }
+
+    #[test]
+    fn gap_line_protocol() {
+        let gap = ScanGap::new(Utc.timestamp_opt(1, 0).unwrap(), Utc.timestamp_opt(61, 0).unwrap(), GapReason::Stalled);
+        assert_eq!(gap_to_line(&gap, "local").unwrap(), "scan_gap,reason=stalled,source=local duration=60i 1000000000");
+    }
+
+    #[tokio::test]
+    async fn keep_going_without_a_server() {
+        // nothing listens on port 1
+        let config = InfluxConfig::from_url_and_token("influx://127.0.0.1:1/ble?org=home&batch=1", None).unwrap();
+        let mut sink = InfluxEventSink::create_from_config(&config).unwrap();
+        let event = DiscoveryEvent::new(Utc.timestamp_opt(1, 0).unwrap(), Signature::Named("Device 1".to_string()), -20);
+        sink.save(std::slice::from_ref(&event)).await.unwrap();
+        assert_eq!(sink.pending.len(), 1);
+        assert!(sink.retry_at.is_some());
+        // backing off, so not tried again yet
+        sink.save(&vec![event; MAX_PENDING]).await.unwrap();
+        assert_eq!(sink.pending.len(), MAX_PENDING);
+        assert_eq!(sink.dropped, 1);
+        assert_eq!(sink.failures, 1);
+    }
+
+    /// Answers every write with `status`, e.g. "400 Bad Request"
+    async fn server(status: &'static str) -> InfluxConfig {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("influx://{}/ble?org=home&batch=1", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buf = [0u8; 4096];
+                loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                        let length : usize = headers.lines()
+                            .find_map(|h| h.to_lowercase().strip_prefix("content-length:").map(|l| l.trim().parse().unwrap()))
+                            .unwrap_or_default();
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                }
+                let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        InfluxConfig::from_url_and_token(&url, None).unwrap()
+    }
+
+    #[tokio::test]
+    async fn drop_refused_lines() {
+        let event = DiscoveryEvent::new(Utc.timestamp_opt(1, 0).unwrap(), Signature::Named("Device 1".to_string()), -20);
+        for (status, kept) in [("400 Bad Request", 0), ("401 Unauthorized", 0), ("429 Too Many Requests", 1), ("503 Service Unavailable", 1)] {
+            let mut sink = InfluxEventSink::create_from_config(&server(status).await).unwrap();
+            sink.save(std::slice::from_ref(&event)).await.unwrap();
+            assert_eq!(sink.pending.len(), kept, "{status}");
+            assert_eq!(sink.retry_at.is_some(), kept > 0, "{status}");
+        }
+    }
+}
@@ -1,59 +1,98 @@
-use std::{error::Error, io::Write};
+use std::io::Write;
 
 use async_trait::async_trait;
 use gzp::ZWriter;
+use serde::{Serialize, Deserialize};
 
-use crate::discover::DiscoveryEvent;
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
 
 use super::EventSink;
 pub struct JsonLinesEventSink<'a> {
-    writer: Writer<'a>
+    writer: Writer<'a>,
+    bytes: u64
 }
 
 pub enum Writer<'a> {
-    PLAIN(Box<dyn Write + 'a>),
-    COMPRESSED(Box<dyn ZWriter + 'a>)
+    PLAIN(Box<dyn Write + Send + 'a>),
+    COMPRESSED(Box<dyn ZWriter + Send + 'a>)
 }
 
 impl<'a> JsonLinesEventSink<'a> {
-    pub fn create_from_writer(writer: Box<dyn Write + 'a>) -> JsonLinesEventSink<'a> {
+    pub fn create_from_writer(writer: Box<dyn Write + Send + 'a>) -> JsonLinesEventSink<'a> {
         JsonLinesEventSink {
-            writer: Writer::PLAIN(writer)
+            writer: Writer::PLAIN(writer),
+            bytes: 0
         }
     }
 
-    pub fn create_from_zwriter(writer: Box<dyn ZWriter + 'a>) -> JsonLinesEventSink<'a> {
+    pub fn create_from_zwriter(writer: Box<dyn ZWriter + Send + 'a>) -> JsonLinesEventSink<'a> {
         JsonLinesEventSink {
-            writer: Writer::COMPRESSED(writer)
+            writer: Writer::COMPRESSED(writer),
+            bytes: 0
         }
     }
 }
 
-unsafe impl<'a> Send for JsonLinesEventSink<'a> {}
+#[derive(Serialize)]
+struct GapLine<'a> {
+    gap: &'a ScanGap
+}
 
-#[async_trait]
-impl<'a> EventSink for JsonLinesEventSink<'a> {
-    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Box<dyn Error>> {
+#[derive(Serialize, Deserialize)]
+struct HeaderLine {
+    signature_policy: String
+}
+
+/// The signature policy id recorded by `write_header`, if `line` is a header
+#[must_use] pub fn parse_header(line: &str) -> Option<String> {
+    serde_json::from_str::<HeaderLine>(line).ok().map(|h| h.signature_policy)
+}
+
+/// The line `write_header` writes, for writers which start new files
+/// themselves
+pub fn header(policy: &str) -> Result<Vec<u8>, Error> {
+    let mut line = serde_json::to_vec(&HeaderLine { signature_policy: policy.to_string() })?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+impl<'a> JsonLinesEventSink<'a> {
+    fn write_lines<T: Serialize>(&mut self, lines: &[T]) -> Result<(), Error> {
+        let mut buffer = vec![];
+        for line in lines {
+            serde_json::to_writer(&mut buffer, line)?;
+            buffer.push(b'\n');
+        }
         let writer = &mut self.writer;
         match writer {
             Writer::PLAIN(ref mut w) => {
-                for event in events {
-                    serde_json::to_writer(&mut *w, event)?;
-                    writeln!(w)?;
-                }
+                w.write_all(&buffer)?;
                 w.flush()?;
             },
             Writer::COMPRESSED(ref mut w) => {
-                for event in events {
-                    serde_json::to_writer(&mut *w, event)?;
-                    writeln!(w)?;
-                }
+                w.write_all(&buffer)?;
                 w.flush()?;
             },
         }
+        self.bytes += buffer.len() as u64;
         Ok(())
     }
-    async fn close(mut self: Box<Self>) -> Result<(), Box<dyn Error>> {
+
+    /// Records the id of the signature policy used, as the first line of a file
+    pub fn write_header(&mut self, policy: &str) -> Result<(), Error> {
+        self.write_lines(&[HeaderLine { signature_policy: policy.to_string() }])
+    }
+}
+
+#[async_trait]
+impl<'a> EventSink for JsonLinesEventSink<'a> {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        self.write_lines(events)
+    }
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error> {
+        self.write_lines(&[GapLine { gap }])
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
         match self.writer {
             Writer::PLAIN(_) => Ok(()),
             Writer::COMPRESSED(ref mut w) => {
@@ -62,6 +101,9 @@ impl<'a> EventSink for JsonLinesEventSink<'a> {
             }
         }
     }
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.bytes)
+    }
 }
 
 #[cfg(test)]
@@ -70,11 +112,12 @@ mod test {
 
     use chrono::{Utc, TimeZone};
 
-    use crate::{discover::DiscoveryEvent, signature::Signature, history::EventSink};
+    use crate::{discover::DiscoveryEvent, signature::{Signature, SignaturePolicy}, history::EventSink, gap::{ScanGap, GapReason}};
 
-    use super::JsonLinesEventSink;
+    use super::{JsonLinesEventSink, parse_header};
 
     #[tokio::test]
+    #[allow(clippy::useless_vec, clippy::needless_borrow, clippy::bool_assert_comparison)]
     async fn sink_multiple_events() {
         let events = &vec![
             DiscoveryEvent::new(
@@ -100,4 +143,36 @@ mod test {
         let actual = String::from_utf8(buf.get_ref().to_vec()).unwrap();
         assert_eq!(actual, expected);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn sink_gap() {
+        let gap = ScanGap::new(
+            Utc.timestamp_opt(1, 0).unwrap(),
+            Utc.timestamp_opt(61, 0).unwrap(),
+            GapReason::Stalled);
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut sink = JsonLinesEventSink::create_from_writer(Box::new(&mut buf));
+            sink.save_gap(&gap).await.unwrap();
+        }
+
+        let expected = 
+            "{\"gap\":{\"start\":\"1970-01-01T00:00:01Z\",\"end\":\"1970-01-01T00:01:01Z\",\"reason\":\"Stalled\"}}\n";
+        let actual = String::from_utf8(buf.get_ref().to_vec()).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn sink_header() {
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut sink = JsonLinesEventSink::create_from_writer(Box::new(&mut buf));
+            sink.write_header(&SignaturePolicy::Xxh3.id()).unwrap();
+        }
+
+        let actual = String::from_utf8(buf.get_ref().to_vec()).unwrap();
+        assert_eq!(actual, "{\"signature_policy\":\"xxh3\"}\n");
+        assert_eq!(parse_header(&actual), Some("xxh3".to_string()));
+        assert_eq!(parse_header("{\"date_time\":\"1970-01-01T00:00:01Z\",\"signature\":{\"Named\":\"Device 1\"},\"rssi\":-20}"), None);
+    }
+}
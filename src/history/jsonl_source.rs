@@ -0,0 +1,199 @@
+use std::{fs::File, io::{self, BufRead, BufReader}, ops::Range, path::{Path, PathBuf}};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use flate2::read::MultiGzDecoder;
+use futures::{stream, StreamExt};
+use serde::Deserialize;
+use tokio::sync::{mpsc, OnceCell};
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
+
+use super::{EventSource, EventStream};
+
+/// Reads back events written by a `JsonLinesEventSink`. As the file has no
+/// index, it is read from the start: once for the time range and gaps, which
+/// are then kept, and again for each stream of events. Reading happens on
+/// blocking threads, so that large files don't hold up other tasks.
+pub struct JsonLinesEventSource {
+    path: PathBuf,
+    compressed: bool,
+    summary: OnceCell<Summary>
+}
+
+/// What is needed from the whole file before reading its events
+struct Summary {
+    range: Option<Range<DateTime<Utc>>>,
+    gaps: Vec<ScanGap>
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Gap { gap: ScanGap },
+    // only the sink checks the signature policy
+    #[allow(dead_code)]
+    Header { signature_policy: String },
+    Event(DiscoveryEvent)
+}
+
+type Lines = Box<dyn Iterator<Item = Result<Line, Error>> + Send>;
+
+fn lines(path: &Path, compressed: bool) -> io::Result<Lines> {
+    let file = File::open(path)?;
+    let reader : Box<dyn BufRead + Send> = if compressed {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    }
+    else {
+        Box::new(BufReader::new(file))
+    };
+    Ok(Box::new(reader.lines()
+        .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .map(|line| {
+            let line = line?;
+            Ok(serde_json::from_str::<Line>(&line)?)
+        })))
+}
+
+fn summarise(path: &Path, compressed: bool) -> Result<Summary, Error> {
+    let mut range : Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    let mut gaps = vec![];
+    for line in lines(path, compressed)? {
+        match line? {
+            Line::Event(event) => {
+                range = Some(match range {
+                    Some((first, last)) => (first.min(event.date_time), last.max(event.date_time)),
+                    None => (event.date_time, event.date_time)
+                });
+            },
+            Line::Gap { gap } => gaps.push(gap),
+            Line::Header { .. } => {}
+        }
+    }
+    gaps.sort_by_key(|g| g.start);
+    Ok(Summary { range: range.map(|(first, last)| first..last + chrono::Duration::seconds(1)), gaps })
+}
+
+/// The events from `lines` within `range`, `chunk_size` at a time
+fn chunks(mut lines: Lines, range: Range<DateTime<Utc>>, chunk_size: usize) -> impl Iterator<Item = Result<Vec<DiscoveryEvent>, Error>> {
+    std::iter::from_fn(move || {
+        let mut chunk = vec![];
+        for line in lines.by_ref() {
+            match line {
+                Ok(Line::Event(event)) if range.contains(&event.date_time) => {
+                    chunk.push(event);
+                    if chunk.len() >= chunk_size {
+                        break;
+                    }
+                },
+                Ok(_) => {},
+                Err(e) => return Some(Err(e))
+            }
+        }
+        if chunk.is_empty() { None } else { Some(Ok(chunk)) }
+    })
+}
+
+impl JsonLinesEventSource {
+    #[must_use] pub fn create_from_file(path: PathBuf, compressed: bool) -> JsonLinesEventSource {
+        JsonLinesEventSource { path, compressed, summary: OnceCell::new() }
+    }
+
+    async fn summary(&self) -> Result<&Summary, Error> {
+        self.summary.get_or_try_init(|| {
+            let (path, compressed) = (self.path.clone(), self.compressed);
+            async move { tokio::task::spawn_blocking(move || summarise(&path, compressed)).await? }
+        }).await
+    }
+}
+
+#[async_trait]
+impl EventSource for JsonLinesEventSource {
+    async fn time_range(&self) -> Result<Option<Range<DateTime<Utc>>>, Error> {
+        Ok(self.summary().await?.range.clone())
+    }
+
+    async fn gaps(&self, range: Range<DateTime<Utc>>) -> Result<Vec<ScanGap>, Error> {
+        Ok(self.summary().await?.gaps.iter()
+            .filter(|gap| gap.end > range.start && gap.start < range.end)
+            .cloned()
+            .collect())
+    }
+
+    fn stream_events(&self, range: Range<DateTime<Utc>>, chunk_size: usize) -> EventStream<'_> {
+        let (path, compressed) = (self.path.clone(), self.compressed);
+        let (sender, mut receiver) = mpsc::channel(1);
+        tokio::task::spawn_blocking(move || {
+            let lines = match lines(&path, compressed) {
+                Ok(lines) => lines,
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e.into()));
+                    return;
+                }
+            };
+            for chunk in chunks(lines, range, chunk_size) {
+                // stops once the stream is dropped
+                if sender.blocking_send(chunk).is_err() {
+                    break;
+                }
+            }
+        });
+        stream::poll_fn(move |cx| receiver.poll_recv(cx)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, io::Write};
+
+    use chrono::{Utc, TimeZone};
+    use futures::TryStreamExt;
+
+    use crate::{discover::DiscoveryEvent, signature::Signature, gap::{ScanGap, GapReason}, history::{EventSource, EventSink, jsonl::JsonLinesEventSink}};
+
+    use super::JsonLinesEventSource;
+
+    #[tokio::test]
+    async fn read_back_events_and_gaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let events : Vec<DiscoveryEvent> = (0..5).map(|i| {
+            DiscoveryEvent::new(Utc.timestamp_opt(i, 0).unwrap(), Signature::Named(format!("Device {i}")), -20)
+        }).collect();
+        let gap = ScanGap::new(Utc.timestamp_opt(2, 0).unwrap(), Utc.timestamp_opt(3, 0).unwrap(), GapReason::Stalled);
+        {
+            let mut sink = JsonLinesEventSink::create_from_writer(Box::new(File::create(&path).unwrap()));
+            sink.save(&events[0..3]).await.unwrap();
+            sink.save_gap(&gap).await.unwrap();
+            sink.save(&events[3..5]).await.unwrap();
+        }
+
+        let source = JsonLinesEventSource::create_from_file(path, false);
+        assert_eq!(source.time_range().await.unwrap(),
+            Some(Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(5, 0).unwrap()));
+        let all = Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(5, 0).unwrap();
+        assert_eq!(source.gaps(all.clone()).await.unwrap(), vec![gap]);
+        let chunks : Vec<Vec<DiscoveryEvent>> = source
+            .stream_events(Utc.timestamp_opt(1, 0).unwrap()..Utc.timestamp_opt(5, 0).unwrap(), 3)
+            .try_collect().await.unwrap();
+        assert_eq!(chunks, vec![events[1..4].to_vec(), events[4..5].to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn read_compressed() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl.gz");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        writeln!(encoder, "{{\"date_time\":\"1970-01-01T00:00:01Z\",\"signature\":{{\"Named\":\"Device 1\"}},\"rssi\":-20}}").unwrap();
+        encoder.finish().unwrap();
+
+        let source = JsonLinesEventSource::create_from_file(path, true);
+        let all = Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(5, 0).unwrap();
+        let events : Vec<Vec<DiscoveryEvent>> = source.stream_events(all, 10).try_collect().await.unwrap();
+        assert_eq!(events, vec![vec![
+            DiscoveryEvent::new(Utc.timestamp_opt(1, 0).unwrap(), Signature::Named("Device 1".to_string()), -20)
+        ]]);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{collections::{HashSet, VecDeque}, path::Path};
+
+use futures::TryStreamExt;
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
+
+use super::{EventSink, EventSinkFormat, EventSource, EventStream};
+
+const CHUNK_SIZE : usize = 10_000;
+
+/// One source's events, read a chunk at a time
+struct Cursor<'a> {
+    chunks: EventStream<'a>,
+    chunk: VecDeque<DiscoveryEvent>
+}
+
+impl Cursor<'_> {
+    async fn peek(&mut self) -> Result<Option<&DiscoveryEvent>, Error> {
+        while self.chunk.is_empty() {
+            match self.chunks.try_next().await? {
+                Some(chunk) => self.chunk = chunk.into(),
+                None => return Ok(None)
+            }
+        }
+        Ok(self.chunk.front())
+    }
+}
+
+/// Copies the events and gaps from several recordings into `sink`, in time
+/// order, leaving out any already seen. As each recording is in time order,
+/// they are read side by side, so only events with the same timestamp need
+/// comparing. Returns how many events and gaps were copied.
+pub async fn merge(sources: &[Box<dyn EventSource>], sink: &mut dyn EventSink) -> Result<(usize, usize), Error> {
+    let mut cursors = vec![];
+    let mut gaps : Vec<ScanGap> = vec![];
+    for source in sources {
+        if let Some(range) = source.time_range().await? {
+            gaps.extend(source.gaps(range.clone()).await?);
+            cursors.push(Cursor { chunks: source.stream_events(range, CHUNK_SIZE), chunk: VecDeque::new() });
+        }
+    }
+    // a gap is recorded just before the first scan after it
+    gaps.sort_by_key(|gap| (gap.end, gap.start));
+    gaps.dedup();
+    let mut gaps : VecDeque<ScanGap> = gaps.into();
+
+    let mut batch = vec![];
+    let mut copied = (0, 0);
+    loop {
+        let mut next = None;
+        for cursor in &mut cursors {
+            if let Some(event) = cursor.peek().await? {
+                next = Some(next.map_or(event.date_time, |next| event.date_time.min(next)));
+            }
+        }
+        let Some(date_time) = next else {
+            break;
+        };
+        if gaps.front().is_some_and(|gap| gap.end <= date_time) {
+            copied.0 += save(sink, &mut batch).await?;
+            while gaps.front().is_some_and(|gap| gap.end <= date_time) {
+                sink.save_gap(&gaps.pop_front().expect("just checked")).await?;
+                copied.1 += 1;
+            }
+        }
+        let mut seen = HashSet::new();
+        for cursor in &mut cursors {
+            while cursor.peek().await?.is_some_and(|event| event.date_time == date_time) {
+                let event = cursor.chunk.pop_front().expect("just peeked");
+                if seen.insert((event.signature.clone(), event.rssi)) {
+                    batch.push(event);
+                }
+            }
+        }
+        if batch.len() >= CHUNK_SIZE {
+            copied.0 += save(sink, &mut batch).await?;
+        }
+    }
+    copied.0 += save(sink, &mut batch).await?;
+    for gap in gaps {
+        sink.save_gap(&gap).await?;
+        copied.1 += 1;
+    }
+    Ok(copied)
+}
+
+/// The signature policy the recordings at `paths` were all recorded with, for
+/// recording in what they are merged into. Anonymous signatures from different
+/// policies never match, so recordings made with different ones are refused.
+/// Recordings made before policies were recorded are taken to match.
+pub async fn common_signature_policy<P: AsRef<Path>>(paths: &[P]) -> Result<Option<String>, Error> {
+    let mut common : Option<String> = None;
+    for path in paths {
+        let path = path.as_ref();
+        let Some(recorded) = EventSinkFormat::create_from_file(path)?.recorded_signature_policy().await? else {
+            continue;
+        };
+        match &common {
+            Some(requested) if *requested != recorded => {
+                return Err(Error::SignaturePolicyMismatch { path: path.display().to_string(), recorded, requested: requested.clone() });
+            },
+            Some(_) => {},
+            None => common = Some(recorded)
+        }
+    }
+    Ok(common)
+}
+
+async fn save(sink: &mut dyn EventSink, batch: &mut Vec<DiscoveryEvent>) -> Result<usize, Error> {
+    if !batch.is_empty() {
+        sink.save(batch).await?;
+    }
+    Ok(std::mem::take(batch).len())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use chrono::{Utc, TimeZone};
+    use futures::TryStreamExt;
+
+    use crate::{discover::DiscoveryEvent, gap::{GapReason, ScanGap}, history::{EventSinkFormat, EventSource, SinkOptions}, signature::{Signature, SignaturePolicy}};
+
+    use super::{merge, common_signature_policy};
+
+    fn event(seconds: i64, name: &str) -> DiscoveryEvent {
+        DiscoveryEvent::new(Utc.timestamp_opt(seconds, 0).unwrap(), Signature::Named(name.to_string()), -20)
+    }
+
+    async fn record(path: &Path, events: &[DiscoveryEvent], gaps: &[ScanGap]) -> Box<dyn EventSource> {
+        let format = EventSinkFormat::create_from_file(path).unwrap();
+        let mut sink = format.to_sink(&SinkOptions::default()).await.unwrap();
+        for gap in gaps {
+            sink.save_gap(gap).await.unwrap();
+        }
+        sink.save(events).await.unwrap();
+        sink.close().await.unwrap();
+        format.to_source().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn merge_in_time_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let gap = ScanGap::new(Utc.timestamp_opt(3, 0).unwrap(), Utc.timestamp_opt(5, 0).unwrap(), GapReason::Paused);
+        let sources = vec![
+            record(&dir.path().join("a.jsonl"), &[event(1, "A"), event(3, "A"), event(5, "A")], std::slice::from_ref(&gap)).await,
+            record(&dir.path().join("b.jsonl"), &[event(2, "B"), event(3, "A"), event(3, "B"), event(6, "B")], std::slice::from_ref(&gap)).await,
+        ];
+        let format = EventSinkFormat::create_from_file(dir.path().join("merged.jsonl")).unwrap();
+        let mut sink = format.to_sink(&SinkOptions::default()).await.unwrap();
+        assert_eq!(merge(&sources, sink.as_mut()).await.unwrap(), (6, 1));
+        sink.close().await.unwrap();
+
+        let merged = format.to_source().await.unwrap();
+        let range = merged.time_range().await.unwrap().unwrap();
+        let events : Vec<DiscoveryEvent> = merged.stream_events(range.clone(), 100).try_concat().await.unwrap();
+        assert_eq!(events, vec![event(1, "A"), event(2, "B"), event(3, "A"), event(3, "B"), event(5, "A"), event(6, "B")]);
+        assert_eq!(merged.gaps(range).await.unwrap(), vec![gap]);
+    }
+
+    #[tokio::test]
+    async fn refuse_mixed_signature_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = ["xxh3.jsonl", "xxh3.sqlite", "unknown.jsonl", "md5.csv"].map(|name| dir.path().join(name));
+        for (path, policy) in paths.iter().zip([Some(SignaturePolicy::Xxh3), Some(SignaturePolicy::Xxh3), None, Some(SignaturePolicy::Md5Legacy)]) {
+            let options = SinkOptions { signature_policy: policy.map(|p| p.id()), ..SinkOptions::default() };
+            let mut sink = EventSinkFormat::create_from_file(path).unwrap().to_sink(&options).await.unwrap();
+            sink.save(&[event(1, "A")]).await.unwrap();
+            sink.close().await.unwrap();
+        }
+        assert_eq!(common_signature_policy(&paths[..3]).await.unwrap(), Some(SignaturePolicy::Xxh3.id()));
+        assert_eq!(common_signature_policy(&paths[2..3]).await.unwrap(), None);
+        assert!(common_signature_policy(&paths).await.is_err());
+    }
+}
@@ -1,30 +1,143 @@
 pub mod sqllite;
 pub mod noop;
 pub mod jsonl;
-use std::{path::{Path, PathBuf}, error::Error, io::BufWriter, fs::OpenOptions, ffi::OsStr, sync::Arc};
+pub mod jsonl_source;
+pub mod mqtt;
+pub mod influx;
+pub mod tee;
+pub mod buffered;
+pub mod rotate;
+pub mod query;
+pub mod websocket;
+pub mod http;
+pub mod csv;
+pub mod stats;
+pub mod encrypt;
+pub mod upload;
+pub mod channel;
+pub mod merge;
+#[cfg(test)]
+pub(crate) mod test_support;
+use std::{path::{Path, PathBuf}, io::{self, BufRead, BufReader, BufWriter, Read, Write}, fs::{File, OpenOptions}, ffi::OsStr, sync::Arc, ops::Range};
 
 use async_trait::async_trait;
 use gzp::Compression;
+use chrono::{DateTime, Utc};
+use flate2::read::MultiGzDecoder;
+use futures::{future, stream::BoxStream, StreamExt, TryStreamExt};
 use sqlx::sqlite::SqlitePoolOptions;
 
-use crate::{discover::DiscoveryEvent, history::sqllite::SQLLiteEventSink};
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap, history::sqllite::{SQLLiteEventSink, SQLLiteEventSource}, signature::Signature};
+
+use self::{rotate::{Rotation, RotatingWriter}, jsonl::JsonLinesEventSink, jsonl_source::JsonLinesEventSource, mqtt::{MqttConfig, MqttEventSink}, influx::{InfluxConfig, InfluxEventSink}, websocket::WebSocketEventSink, http::HttpEventSink, csv::CsvEventSink, encrypt::{EncryptionKey, EncryptingWriter, DecryptingReader}, upload::{SegmentUploader, UploadingEventSink}};
+
+/// Settings which affect how sinks are created, where they apply
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct SinkOptions {
+    /// the id (see `SignaturePolicy::id`) of the signature policy, recorded
+    /// with, and checked against, what is already in `.sqlite`, `.jsonl` and
+    /// `.csv` files; `None` when not known, in which case neither happens
+    pub signature_policy: Option<String>,
+    /// applies to `.jsonl` and `.csv` files only, which can't then be
+    /// encrypted
+    pub rotation: Option<Rotation>,
+    /// where events were seen from; applies to `.sqlite` files only
+    pub source: Option<String>,
+    /// where undelivered events are kept; applies to `http://` urls only
+    pub spool: Option<PathBuf>,
+    /// applies to `.jsonl` and `.csv` files only
+    pub encryption: Option<EncryptionKey>,
+    /// object store url to move rotated segments to; applies to rotated
+    /// `.jsonl` files only
+    pub upload: Option<String>
+}
+
+impl SinkOptions {
+    fn encrypted<W: Write + Send + 'static>(&self, writer: W) -> Box<dyn Write + Send> {
+        match &self.encryption {
+            Some(key) => Box::new(EncryptingWriter::create(writer, key)),
+            None => Box::new(writer)
+        }
+    }
+
+    /// Like the metadata table in `.sqlite` files, the first line of a
+    /// `.jsonl` or `.csv` file records the signature policy, so that appending
+    /// with a different one can be refused. Returns the policy if the file is
+    /// new, and so still needs it recording.
+    fn check_recorded_policy(&self, path: &Path, compressed: bool, parse: fn(&str) -> Option<String>) -> Result<Option<&str>, Error> {
+        let Some(policy) = &self.signature_policy else {
+            return Ok(None);
+        };
+        let Some(line) = first_line(path, compressed, self.encryption.as_ref())? else {
+            return Ok(Some(policy));
+        };
+        match parse(&line) {
+            Some(recorded) if recorded != *policy => {
+                Err(Error::SignaturePolicyMismatch { path: path.display().to_string(), recorded, requested: policy.clone() })
+            },
+            // either matching, or recorded before policies were
+            _ => Ok(None)
+        }
+    }
+}
 
-use self::jsonl::JsonLinesEventSink;
+/// `None` if the file is missing or empty
+fn first_line(path: &Path, compressed: bool, encryption: Option<&EncryptionKey>) -> io::Result<Option<String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e)
+    };
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    let reader : Box<dyn Read> = match encryption {
+        Some(key) => Box::new(DecryptingReader::create(file, key)),
+        None => Box::new(file)
+    };
+    let reader : Box<dyn Read> = if compressed { Box::new(MultiGzDecoder::new(reader)) } else { reader };
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line)?;
+    Ok(Some(line))
+}
 
 #[derive(PartialEq, Debug)]
 #[allow(non_camel_case_types)]
 pub enum EventSinkFormat {
     JSONL(PathBuf),
     JSONL_GZIP(PathBuf),
-    SQLITE(PathBuf)
+    SQLITE(PathBuf),
+    CSV(PathBuf),
+    MQTT(MqttConfig),
+    INFLUX(InfluxConfig),
+    WEBSOCKET(String),
+    HTTP(String)
 }
 
 impl EventSinkFormat {
-    pub fn create_from_file<P>(path_arg: P) -> Result<EventSinkFormat, Box<dyn Error>> 
+    pub fn create_from_file<P>(path_arg: P) -> Result<EventSinkFormat, Error> 
         where P: AsRef<Path>
     {
         let path = path_arg.as_ref();
-        if Some(OsStr::new("jsonl")) == path.extension() {
+        if let Some(url) = path.to_str().filter(|p| p.starts_with("mqtt://")) {
+            Ok(EventSinkFormat::MQTT(MqttConfig::from_url(url)?))
+        }
+        else if let Some(url) = path.to_str().filter(|p| p.starts_with("influx://")) {
+            Ok(EventSinkFormat::INFLUX(InfluxConfig::from_url(url)?))
+        }
+        else if let Some(url) = path.to_str().filter(|p| p.starts_with("ws://") || p.starts_with("wss://")) {
+            Ok(EventSinkFormat::WEBSOCKET(url.to_string()))
+        }
+        else if let Some(url) = path.to_str().filter(|p| p.starts_with("http://") || p.starts_with("https://")) {
+            Ok(EventSinkFormat::HTTP(url.to_string()))
+        }
+        else if path.to_str().is_some_and(|p| p.starts_with("postgres://") || p.starts_with("postgresql://")) {
+            Err(Error::Unsupported("postgres:// is not supported; record to a .sqlite file instead".to_string()))
+        }
+        else if Some(OsStr::new("parquet")) == path.extension() {
+            Err(Error::Unsupported(".parquet is not supported; use a .csv file instead".to_string()))
+        }
+        else if Some(OsStr::new("jsonl")) == path.extension() {
             Ok(EventSinkFormat::JSONL(path.to_path_buf()))
         }
         else if Some(OsStr::new("gz")) == path.extension() {
@@ -32,60 +145,214 @@ impl EventSinkFormat {
                 Ok(EventSinkFormat::JSONL_GZIP(path.to_path_buf()))
             }
             else {
-                Err(format!("unknown type: {}", path.display()).into())
+                Err(Error::Unsupported(format!("unknown type: {}", path.display())))
             }
         }
         else if Some(OsStr::new("sqlite")) == path.extension() {
             Ok(EventSinkFormat::SQLITE(path.to_path_buf()))
         }
+        else if Some(OsStr::new("csv")) == path.extension() {
+            Ok(EventSinkFormat::CSV(path.to_path_buf()))
+        }
         else {
-            Err(format!("unknown type: {}", path.display()).into())
+            Err(Error::Unsupported(format!("unknown type: {}", path.display())))
         }
     }
 
-    pub async fn to_sink(&self) -> Result<Box<dyn EventSink>, Box<dyn Error>>  {
+    pub async fn to_sink(&self, options: &SinkOptions) -> Result<Box<dyn EventSink>, Error>  {
         use EventSinkFormat::*;
+        if options.rotation.is_some() && !matches!(self, JSONL(_) | CSV(_)) {
+            return Err(Error::Unsupported("only .jsonl and .csv files can be rotated".to_string()));
+        }
+        if options.encryption.is_some() && !matches!(self, JSONL(_) | CSV(_)) {
+            return Err(Error::Unsupported("only .jsonl and .csv files can be encrypted".to_string()));
+        }
+        if options.rotation.is_some() && options.encryption.is_some() {
+            return Err(Error::Unsupported("encrypted files can't be rotated".to_string()));
+        }
+        if options.upload.is_some() && !matches!(self, JSONL(_)) {
+            return Err(Error::Unsupported("only rotated .jsonl files can be uploaded".to_string()));
+        }
         match self {
+            JSONL(path_buf) if options.rotation.is_some() => {
+                options.check_recorded_policy(path_buf, false, jsonl::parse_header)?;
+                let preamble = options.signature_policy.as_deref().map(jsonl::header).transpose()?.unwrap_or_default();
+                let writer = RotatingWriter::create(path_buf, options.rotation.clone().unwrap_or_default())?
+                    .with_preamble(preamble)?;
+                let sink = Box::new(JsonLinesEventSink::create_from_writer(Box::new(writer)));
+                match &options.upload {
+                    Some(url) => {
+                        let uploader = SegmentUploader::create_from_url(url, path_buf.clone())?;
+                        Ok(Box::new(UploadingEventSink::create_from_sink(sink, uploader)))
+                    },
+                    None => Ok(sink)
+                }
+            },
+            JSONL(_) if options.upload.is_some() => {
+                Err(Error::Unsupported("uploading needs the .jsonl file to be rotated".to_string()))
+            },
             JSONL(path_buf) => {
+                let policy = options.check_recorded_policy(path_buf, false, jsonl::parse_header)?;
                 let file = OpenOptions::new()
                     .create(true)
                     .append(true)
                     .open(path_buf)?;
                 let buf_writer = BufWriter::new(file);
-                Ok(Box::new(JsonLinesEventSink::create_from_writer(Box::new(buf_writer))))
+                let mut sink = JsonLinesEventSink::create_from_writer(options.encrypted(buf_writer));
+                if let Some(policy) = policy {
+                    sink.write_header(policy)?;
+                }
+                Ok(Box::new(sink))
             },
             JSONL_GZIP(path_buf) => {
-                use gzp::{deflate::Gzip, ZBuilder};
+                use gzp::{deflate::Gzip, par::compress::ParCompressBuilder};
                 
+                let policy = options.check_recorded_policy(path_buf, true, jsonl::parse_header)?;
                 let file = OpenOptions::new()
                     .create(true)
                     .append(true)
                     .open(path_buf)?;
                 let buf_writer = BufWriter::new(file);
-                let compressed_writer = ZBuilder::<Gzip, _>::new()
+                // built directly, rather than through ZBuilder, as that loses
+                // the writer being Send
+                let compressed_writer = ParCompressBuilder::<Gzip>::new()
                     .compression_level(Compression::best())
                     .from_writer(buf_writer);
-                Ok(Box::new(JsonLinesEventSink::create_from_writer(Box::new(compressed_writer))))
+                let mut sink = JsonLinesEventSink::create_from_zwriter(Box::new(compressed_writer));
+                if let Some(policy) = policy {
+                    sink.write_header(policy)?;
+                }
+                Ok(Box::new(sink))
             },
             SQLITE(path_buf) => {
-                let url = format!("sqlite://{}?mode=rwc", path_buf.display());
-                let pool = Arc::new(SqlitePoolOptions::new().connect(&url).await.unwrap());
-                let sink = SQLLiteEventSink::create_from_pool(pool.clone()).await?;
+                let pool = Arc::new(SqlitePoolOptions::new().connect_with(sqllite::connect_options(path_buf)).await?);
+                let sink = SQLLiteEventSink::create_from_pool(pool.clone()).await?
+                    .with_source(options.source.clone());
+                if let Some(policy) = &options.signature_policy {
+                    sink.check_signature_policy(policy).await?;
+                }
                 Ok(Box::new(sink))
+            },
+            CSV(path_buf) if options.rotation.is_some() => {
+                options.check_recorded_policy(path_buf, false, csv::parse_header)?;
+                let writer = RotatingWriter::create(path_buf, options.rotation.clone().unwrap_or_default())?
+                    .with_preamble(csv::preamble(options.signature_policy.as_deref())?)?;
+                Ok(Box::new(CsvEventSink::create_from_writer(Box::new(writer), false, None)?))
+            },
+            CSV(path_buf) => {
+                let policy = options.check_recorded_policy(path_buf, false, csv::parse_header)?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path_buf)?;
+                let is_new = file.metadata()?.len() == 0;
+                Ok(Box::new(CsvEventSink::create_from_writer(options.encrypted(BufWriter::new(file)), is_new, policy)?))
+            },
+            MQTT(config) => {
+                Ok(Box::new(MqttEventSink::create_from_config(config)))
+            },
+            INFLUX(config) => {
+                Ok(Box::new(InfluxEventSink::create_from_config(config)?))
+            },
+            WEBSOCKET(url) => {
+                Ok(Box::new(WebSocketEventSink::connect(url).await?))
+            },
+            HTTP(url) => {
+                Ok(Box::new(HttpEventSink::create_from_url(url, options.spool.clone())?))
             }
         }
     }
 }
 
+impl EventSinkFormat {
+    /// For reading back what was recorded to this format
+    pub async fn to_source(&self) -> Result<Box<dyn EventSource>, Error> {
+        use EventSinkFormat::*;
+        match self {
+            JSONL(path_buf) => Ok(Box::new(JsonLinesEventSource::create_from_file(path_buf.clone(), false))),
+            JSONL_GZIP(path_buf) => Ok(Box::new(JsonLinesEventSource::create_from_file(path_buf.clone(), true))),
+            SQLITE(path_buf) => {
+                let options = sqllite::connect_options(path_buf).create_if_missing(false);
+                let pool = Arc::new(SqlitePoolOptions::new().connect_with(options).await?);
+                // as when recording to it, so that older files can be read back
+                sqllite::migrate(&pool).await?;
+                Ok(Box::new(SQLLiteEventSource::create_from_pool(pool)))
+            },
+            CSV(_) => Err(Error::Unsupported("can't read back from .csv files".to_string())),
+            MQTT(_) | INFLUX(_) | WEBSOCKET(_) | HTTP(_) => Err(Error::Unsupported("can only read back from files".to_string()))
+        }
+    }
+
+    /// The id of the signature policy recorded in this file, if there is one
+    /// (e.g. so that copying from it can keep it)
+    pub async fn recorded_signature_policy(&self) -> Result<Option<String>, Error> {
+        use EventSinkFormat::*;
+        match self {
+            JSONL(path_buf) => Ok(first_line(path_buf, false, None)?.as_deref().and_then(jsonl::parse_header)),
+            JSONL_GZIP(path_buf) => Ok(first_line(path_buf, true, None)?.as_deref().and_then(jsonl::parse_header)),
+            CSV(path_buf) => Ok(first_line(path_buf, false, None)?.as_deref().and_then(csv::parse_header)),
+            SQLITE(path_buf) => {
+                let options = sqllite::connect_options(path_buf).create_if_missing(false);
+                let pool = SqlitePoolOptions::new().connect_with(options).await?;
+                sqllite::migrate(&pool).await?;
+                sqllite::recorded_signature_policy(&pool).await
+            },
+            MQTT(_) | INFLUX(_) | WEBSOCKET(_) | HTTP(_) => Ok(None)
+        }
+    }
+}
+
 #[async_trait]
 pub trait EventSink : Send {
-    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Box<dyn Error>>;
-    async fn close(mut self: Box<Self>) -> Result<(), Box<dyn Error>>;
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error>;
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error>;
+    async fn close(mut self: Box<Self>) -> Result<(), Error>;
+    /// How much has been written so far (before any compression), for sinks
+    /// where that is known
+    fn bytes_written(&self) -> Option<u64> {
+        None
+    }
+    /// How many failures the sink has dealt with itself rather than
+    /// returning, e.g. when one of several sinks written to at once fails
+    fn errors_handled(&self) -> u64 {
+        0
+    }
+}
+
+pub type EventStream<'a> = BoxStream<'a, Result<Vec<DiscoveryEvent>, Error>>;
+
+/// The counterpart to `EventSink`, for reading recorded events back
+#[async_trait]
+pub trait EventSource : Send + Sync {
+    /// The span from the first recorded event to just after the last one
+    async fn time_range(&self) -> Result<Option<Range<DateTime<Utc>>>, Error>;
+    /// Gaps which overlap `range`
+    async fn gaps(&self, range: Range<DateTime<Utc>>) -> Result<Vec<ScanGap>, Error>;
+    /// Events within `range`, in the order they were recorded, in chunks of
+    /// up to `chunk_size`, so that arbitrarily large histories can be
+    /// processed without loading them all into memory
+    fn stream_events(&self, range: Range<DateTime<Utc>>, chunk_size: usize) -> EventStream<'_>;
+    /// Like `stream_events`, but only for the device with `signature`. This
+    /// filters `stream_events`, so sources which can look a device up
+    /// directly should override it.
+    fn stream_device_events(&self, signature: &Signature, range: Range<DateTime<Utc>>, chunk_size: usize) -> EventStream<'_> {
+        let signature = signature.clone();
+        self.stream_events(range, chunk_size)
+            .map_ok(move |chunk| chunk.into_iter().filter(|e| e.signature == signature).collect::<Vec<_>>())
+            .try_filter(|chunk| future::ready(!chunk.is_empty()))
+            .boxed()
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::EventSinkFormat;
+    use chrono::{Utc, TimeZone};
+    use futures::TryStreamExt;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use crate::{discover::DiscoveryEvent, error::Error, signature::{Signature, SignaturePolicy}};
+
+    use super::{EventSinkFormat, SinkOptions, Rotation, EncryptionKey};
 
     #[test]
     fn jsonl_format_matching() {
@@ -108,6 +375,119 @@ mod test {
         assert_eq!(EventSinkFormat::create_from_file(valid).unwrap(), EventSinkFormat::SQLITE(valid.into()));        
     }
 
+    #[test]
+    fn mqtt_format_matching() {
+        let valid = "mqtt://localhost/blescan";
+
+        assert!(matches!(EventSinkFormat::create_from_file(valid).unwrap(), EventSinkFormat::MQTT(_)));
+    }
+
+    #[test]
+    fn influx_format_matching() {
+        let valid = "influx://localhost/blescan?org=home";
+
+        assert!(matches!(EventSinkFormat::create_from_file(valid).unwrap(), EventSinkFormat::INFLUX(_)));
+    }
+
+    #[test]
+    fn websocket_format_matching() {
+        for valid in ["ws://collector:9000/events", "wss://collector/events"] {
+            assert_eq!(EventSinkFormat::create_from_file(valid).unwrap(), EventSinkFormat::WEBSOCKET(valid.to_string()));
+        }
+    }
+
+    #[test]
+    fn http_format_matching() {
+        for valid in ["http://localhost:8686/blescan", "https://ingest.example.com/blescan"] {
+            assert_eq!(EventSinkFormat::create_from_file(valid).unwrap(), EventSinkFormat::HTTP(valid.to_string()));
+        }
+    }
+
+    #[test]
+    fn csv_format_matching() {
+        let valid = "foop.csv";
+
+        assert_eq!(EventSinkFormat::create_from_file(valid).unwrap(), EventSinkFormat::CSV(valid.into()));
+    }
+
+    #[test]
+    fn postgres_not_supported() {
+        let error = EventSinkFormat::create_from_file("postgres://localhost/blescan").unwrap_err();
+
+        assert!(error.to_string().contains("postgres:// is not supported"));
+    }
+
+    #[test]
+    fn parquet_not_supported() {
+        let error = EventSinkFormat::create_from_file("events.parquet").unwrap_err();
+
+        assert!(error.to_string().contains(".parquet is not supported"));
+    }
+
+    #[tokio::test]
+    async fn signature_policy_must_match_file() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["events.jsonl", "events.csv"] {
+            let format = EventSinkFormat::create_from_file(dir.path().join(name)).unwrap();
+            let options = |policy: SignaturePolicy| SinkOptions { signature_policy: Some(policy.id()), ..SinkOptions::default() };
+            format.to_sink(&options(SignaturePolicy::Xxh3)).await.unwrap().close().await.unwrap();
+            format.to_sink(&options(SignaturePolicy::Xxh3)).await.unwrap().close().await.unwrap();
+            assert!(format.to_sink(&options(SignaturePolicy::Md5Legacy)).await.is_err());
+            format.to_sink(&SinkOptions::default()).await.unwrap().close().await.unwrap();
+        }
+        let source = EventSinkFormat::create_from_file(dir.path().join("events.jsonl")).unwrap().to_source().await.unwrap();
+        assert_eq!(source.time_range().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn only_line_based_files_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SinkOptions { rotation: Some(Rotation::default()), ..SinkOptions::default() };
+        for name in ["events.jsonl", "events.csv"] {
+            let format = EventSinkFormat::create_from_file(dir.path().join(name)).unwrap();
+            format.to_sink(&options).await.unwrap().close().await.unwrap();
+        }
+        for name in ["events.jsonl.gz", "events.sqlite"] {
+            let format = EventSinkFormat::create_from_file(dir.path().join(name)).unwrap();
+            assert!(matches!(format.to_sink(&options).await, Err(Error::Unsupported(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn read_sqlite_recorded_before_migrations() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.sqlite");
+        let pool = SqlitePoolOptions::new().connect_with(super::sqllite::connect_options(&path)).await.unwrap();
+        sqlx::query(include_str!("../../migrations/20230812000000_discovery_events.sql")).execute(&pool).await.unwrap();
+        sqlx::query("INSERT INTO discovery_events (date_time, signature, rssi) VALUES (?, ?, ?)")
+            .bind(Utc.timestamp_opt(1, 0).unwrap())
+            .bind("  Device 1")
+            .bind(-20)
+            .execute(&pool).await.unwrap();
+        pool.close().await;
+
+        let source = EventSinkFormat::create_from_file(&path).unwrap().to_source().await.unwrap();
+        let range = source.time_range().await.unwrap().unwrap();
+        let events : Vec<DiscoveryEvent> = source.stream_events(range, 10).try_concat().await.unwrap();
+        assert_eq!(events, vec![DiscoveryEvent::new(Utc.timestamp_opt(1, 0).unwrap(), Signature::Named("Device 1".to_string()), -20)]);
+    }
+
+    #[tokio::test]
+    async fn only_plain_files_encrypt() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SinkOptions { encryption: Some(EncryptionKey::from_hex(&"01".repeat(32)).unwrap()), ..SinkOptions::default() };
+        for name in ["events.jsonl", "events.csv"] {
+            let format = EventSinkFormat::create_from_file(dir.path().join(name)).unwrap();
+            format.to_sink(&options).await.unwrap().close().await.unwrap();
+        }
+        for name in ["events.jsonl.gz", "events.sqlite"] {
+            let format = EventSinkFormat::create_from_file(dir.path().join(name)).unwrap();
+            assert!(matches!(format.to_sink(&options).await, Err(Error::Unsupported(_))));
+        }
+        let format = EventSinkFormat::create_from_file("http://localhost:1/events").unwrap();
+        assert!(matches!(format.to_sink(&options).await, Err(Error::Unsupported(_))));
+    }
+
     #[test]
     fn format_not_matching() {
         let invalid = vec!["foop.json", "farp", "feep.txt"];
@@ -1,8 +1,6 @@
-use std::error::Error;
-
 use async_trait::async_trait;
 
-use crate::discover::DiscoveryEvent;
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
 
 use super::EventSink;
 
@@ -11,10 +9,13 @@ pub struct NoopEventSink;
 
 #[async_trait]
 impl EventSink for NoopEventSink {
-    async fn save(&mut self, _: &[DiscoveryEvent]) -> Result<(), Box<dyn Error>> {
+    async fn save(&mut self, _: &[DiscoveryEvent]) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn save_gap(&mut self, _: &ScanGap) -> Result<(), Error> {
         Ok(())
     }
-    async fn close(mut self: Box<Self>) -> Result<(), Box<dyn Error>> {
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
         Ok(())
     }
 }
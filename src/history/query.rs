@@ -0,0 +1,170 @@
+use std::{collections::{HashMap, HashSet}, ops::Range};
+
+use chrono::{DateTime, Utc, DurationRound, Duration};
+use futures::TryStreamExt;
+
+use crate::{discover::DiscoveryEvent, error::Error, signature::Signature};
+
+use super::EventSource;
+
+const CHUNK_SIZE : usize = 10_000;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct RssiSample {
+    pub date_time: DateTime<Utc>,
+    pub rssi: i16,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct DeviceSummary {
+    pub signature: Signature,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub sightings: usize,
+    pub min_rssi: i16,
+    pub max_rssi: i16,
+    pub mean_rssi: f64,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct HourActivity {
+    pub hour: DateTime<Utc>,
+    pub distinct_devices: usize,
+    pub sightings: usize,
+}
+
+async fn for_each_event<F>(source: &dyn EventSource, range: Range<DateTime<Utc>>, mut f: F) -> Result<(), Error>
+    where F: FnMut(&DiscoveryEvent)
+{
+    let mut chunks = source.stream_events(range, CHUNK_SIZE);
+    while let Some(chunk) = chunks.try_next().await? {
+        chunk.iter().for_each(&mut f);
+    }
+    Ok(())
+}
+
+/// Every sighting of one device, in the order recorded
+pub async fn time_series(source: &dyn EventSource, signature: &Signature, range: Range<DateTime<Utc>>)
+    -> Result<Vec<RssiSample>, Error> {
+    let mut samples = vec![];
+    let mut chunks = source.stream_device_events(signature, range, CHUNK_SIZE);
+    while let Some(chunk) = chunks.try_next().await? {
+        samples.extend(chunk.iter().map(|e| RssiSample { date_time: e.date_time, rssi: e.rssi }));
+    }
+    Ok(samples)
+}
+
+/// Each device seen, most frequently seen first
+pub async fn distinct_devices(source: &dyn EventSource, range: Range<DateTime<Utc>>)
+    -> Result<Vec<DeviceSummary>, Error> {
+    let mut summaries : HashMap<Signature, (DeviceSummary, i64)> = HashMap::new();
+    for_each_event(source, range, |e| {
+        summaries.entry(e.signature.clone())
+            .and_modify(|(s, total_rssi)| {
+                s.first_seen = s.first_seen.min(e.date_time);
+                s.last_seen = s.last_seen.max(e.date_time);
+                s.sightings += 1;
+                s.min_rssi = s.min_rssi.min(e.rssi);
+                s.max_rssi = s.max_rssi.max(e.rssi);
+                *total_rssi += i64::from(e.rssi);
+            })
+            .or_insert((DeviceSummary {
+                signature: e.signature.clone(),
+                first_seen: e.date_time,
+                last_seen: e.date_time,
+                sightings: 1,
+                min_rssi: e.rssi,
+                max_rssi: e.rssi,
+                mean_rssi: 0.0
+            }, i64::from(e.rssi)));
+    }).await?;
+    let mut devices : Vec<DeviceSummary> = summaries.into_values()
+        .map(|(mut s, total_rssi)| {
+            #[allow(clippy::cast_precision_loss)]
+            let mean = total_rssi as f64 / s.sightings as f64;
+            s.mean_rssi = mean;
+            s
+        })
+        .collect();
+    devices.sort_by(|a, b| b.sightings.cmp(&a.sightings).then_with(|| a.signature.cmp(&b.signature)));
+    Ok(devices)
+}
+
+/// Activity per hour, for hours in which anything was seen, busiest first
+pub async fn busiest_hours(source: &dyn EventSource, range: Range<DateTime<Utc>>)
+    -> Result<Vec<HourActivity>, Error> {
+    let mut hours : HashMap<DateTime<Utc>, (HashSet<Signature>, usize)> = HashMap::new();
+    for_each_event(source, range, |e| {
+        let hour = e.date_time.duration_trunc(Duration::hours(1)).unwrap_or(e.date_time);
+        let (devices, sightings) = hours.entry(hour).or_default();
+        devices.insert(e.signature.clone());
+        *sightings += 1;
+    }).await?;
+    let mut activity : Vec<HourActivity> = hours.into_iter()
+        .map(|(hour, (devices, sightings))| HourActivity { hour, distinct_devices: devices.len(), sightings })
+        .collect();
+    activity.sort_by(|a, b| b.distinct_devices.cmp(&a.distinct_devices).then_with(|| a.hour.cmp(&b.hour)));
+    Ok(activity)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use chrono::{Utc, TimeZone, DateTime};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use crate::{discover::DiscoveryEvent, signature::Signature, history::{EventSink, sqllite::{SQLLiteEventSink, SQLLiteEventSource}}};
+
+    use super::{time_series, distinct_devices, busiest_hours, RssiSample};
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    async fn source() -> SQLLiteEventSource {
+        let pool = Arc::new(SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap());
+        let mut sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap();
+        sink.save(&[
+            DiscoveryEvent::new(at(0), Signature::Named("1".to_string()), -10),
+            DiscoveryEvent::new(at(10), Signature::Named("1".to_string()), -20),
+            DiscoveryEvent::new(at(20), Signature::Named("2".to_string()), -30),
+            DiscoveryEvent::new(at(3600), Signature::Named("1".to_string()), -60),
+        ]).await.unwrap();
+        SQLLiteEventSource::create_from_pool(pool)
+    }
+
+    #[tokio::test]
+    async fn device_time_series() {
+        let source = source().await;
+        let actual = time_series(&source, &Signature::Named("1".to_string()), at(0)..at(3601)).await.unwrap();
+        assert_eq!(actual, vec![
+            RssiSample { date_time: at(0), rssi: -10 },
+            RssiSample { date_time: at(10), rssi: -20 },
+            RssiSample { date_time: at(3600), rssi: -60 },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn devices_in_range() {
+        let source = source().await;
+        let actual = distinct_devices(&source, at(0)..at(3600)).await.unwrap();
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual[0].signature, Signature::Named("1".to_string()));
+        assert_eq!(actual[0].sightings, 2);
+        assert_eq!((actual[0].min_rssi, actual[0].max_rssi), (-20, -10));
+        assert!((actual[0].mean_rssi - -15.0).abs() < f64::EPSILON);
+        assert_eq!((actual[0].first_seen, actual[0].last_seen), (at(0), at(10)));
+        assert_eq!(actual[1].signature, Signature::Named("2".to_string()));
+    }
+
+    #[tokio::test]
+    async fn hours_by_activity() {
+        let source = source().await;
+        let actual = busiest_hours(&source, at(0)..at(7200)).await.unwrap();
+        let summary : Vec<(i64, usize, usize)> = actual.iter()
+            .map(|h| (h.hour.timestamp(), h.distinct_devices, h.sightings))
+            .collect();
+        assert_eq!(summary, vec![(0, 2, 3), (3600, 1, 1)]);
+    }
+}
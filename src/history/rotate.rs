@@ -0,0 +1,355 @@
+use std::{fs::{File, OpenOptions, self}, io::{self, BufWriter, Write, BufReader}, path::{Path, PathBuf}, thread::{self, JoinHandle}};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use gzp::{deflate::Gzip, ZBuilder, Compression};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RotationPeriod {
+    Hourly,
+    Daily
+}
+
+impl std::str::FromStr for RotationPeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => Ok(RotationPeriod::Hourly),
+            "daily" => Ok(RotationPeriod::Daily),
+            _ => Err(format!("unknown rotation period: {s} (expected hourly or daily)"))
+        }
+    }
+}
+
+impl RotationPeriod {
+    fn bucket(self, date_time: &DateTime<Utc>) -> String {
+        match self {
+            RotationPeriod::Hourly => date_time.format("%Y%m%d%H").to_string(),
+            RotationPeriod::Daily => date_time.format("%Y%m%d").to_string()
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub period: Option<RotationPeriod>,
+    /// gzip files once they have been rotated out
+    pub compress: bool
+}
+
+/// Parses sizes like `500`, `64KB`, `10MB` or `1GB`
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let upper = s.trim().to_uppercase();
+    let (number, multiplier) = [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10), ("B", 1)]
+        .into_iter()
+        .find_map(|(suffix, multiplier)| upper.strip_suffix(suffix).map(|n| (n.to_string(), multiplier)))
+        .unwrap_or((upper.clone(), 1));
+    number.trim().parse::<u64>().ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size: {s}"))
+}
+
+/// A file which, when flushed, moves itself aside to a timestamped name if it
+/// has grown too big or is from an earlier period, and starts afresh.
+/// Compressing the file moved aside happens on a thread of its own, so as not
+/// to hold up writing.
+pub struct RotatingWriter {
+    path: PathBuf,
+    rotation: Rotation,
+    preamble: Vec<u8>,
+    file: BufWriter<File>,
+    opened_at: DateTime<Utc>,
+    written: u64,
+    compressing: Option<(PathBuf, JoinHandle<io::Result<()>>)>
+}
+
+impl RotatingWriter {
+    pub fn create<P: AsRef<Path>>(path: P, rotation: Rotation) -> io::Result<RotatingWriter> {
+        let path = path.as_ref().to_path_buf();
+        let (file, written) = open(&path)?;
+        let opened_at = if written > 0 { started(&path)? } else { Utc::now() };
+        Ok(RotatingWriter { path, rotation, preamble: vec![], file, opened_at, written, compressing: None })
+    }
+
+    /// Written at the start of each new file, e.g. a header row
+    pub fn with_preamble(mut self, preamble: Vec<u8>) -> io::Result<RotatingWriter> {
+        if self.written == 0 {
+            self.write_all(&preamble)?;
+        }
+        self.preamble = preamble;
+        Ok(self)
+    }
+
+    fn due(&self, now: &DateTime<Utc>) -> bool {
+        let too_big = self.rotation.max_bytes.is_some_and(|max| self.written >= max);
+        let too_old = self.rotation.period.is_some_and(|period| period.bucket(&self.opened_at) != period.bucket(now));
+        // a file with nothing but its preamble is left be
+        self.written > self.preamble.len() as u64 && (too_big || too_old)
+    }
+
+    fn rotated_path(&self) -> PathBuf {
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let suffix = self.path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+        let timestamp = self.opened_at.format("%Y%m%dT%H%M%S");
+        let mut attempt = 0;
+        loop {
+            let counter = if attempt == 0 { String::new() } else { format!("-{attempt}") };
+            let candidate = self.path.with_file_name(format!("{stem}-{timestamp}{counter}{suffix}"));
+            if !candidate.exists() && !gzipped_path(&candidate).exists() {
+                return candidate;
+            }
+            attempt += 1;
+        }
+    }
+
+    fn rotate(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        self.file.flush()?;
+        let rotated = self.rotated_path();
+        if self.rotation.compress {
+            self.finish_compressing();
+            // created before the segment appears, so it is never taken as
+            // ready to upload while being compressed
+            let partial = File::create(partial_gzipped_path(&rotated))?;
+            fs::rename(&self.path, &rotated)?;
+            let path = rotated.clone();
+            self.compressing = Some((rotated, thread::spawn(move || gzip(&path, partial))));
+        }
+        else {
+            fs::rename(&self.path, &rotated)?;
+        }
+        let (file, written) = open(&self.path)?;
+        self.file = file;
+        self.written = written;
+        self.opened_at = now;
+        let preamble = std::mem::take(&mut self.preamble);
+        self.write_all(&preamble)?;
+        self.preamble = preamble;
+        Ok(())
+    }
+
+    fn finish_compressing(&mut self) {
+        if let Some((path, compressing)) = self.compressing.take() {
+            match compressing.join() {
+                Ok(Ok(())) => {},
+                Ok(Err(e)) => tracing::warn!("compressing {} failed: {e}", path.display()),
+                Err(_) => tracing::warn!("compressing {} panicked", path.display())
+            }
+        }
+    }
+}
+
+impl Drop for RotatingWriter {
+    fn drop(&mut self) {
+        self.finish_compressing();
+    }
+}
+
+/// When the segment was started, and its counter, if `name` is one
+/// `rotated_path` would give, i.e. `{stem}-%Y%m%dT%H%M%S[-N]{suffix}`,
+/// possibly gzipped
+fn segment_order(name: &str, stem: &str, suffix: &str) -> Option<(NaiveDateTime, u32)> {
+    let rest = name.strip_prefix(stem)?.strip_prefix('-')?;
+    let rest = rest.strip_suffix(".gz").unwrap_or(rest);
+    let rest = rest.strip_suffix(suffix)?;
+    let (timestamp, counter) = match rest.split_once('-') {
+        Some((_, counter)) if !counter.bytes().all(|b| b.is_ascii_digit()) => return None,
+        Some((timestamp, counter)) => (timestamp, counter.parse().ok()?),
+        None => (rest, 0)
+    };
+    if timestamp.len() != 15 {
+        return None;
+    }
+    Some((NaiveDateTime::parse_from_str(timestamp, "%Y%m%dT%H%M%S").ok()?, counter))
+}
+
+/// Files which have been rotated out from `path` (and possibly gzipped),
+/// oldest first. Any which are still being gzipped are left out.
+pub fn rotated_segments(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let suffix = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new(".")
+    };
+    let mut segments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let compressing = partial_gzipped_path(&entry.path()).exists() || gzipped_path(&entry.path()).exists();
+        if let Some(order) = segment_order(&name, &stem, &suffix) {
+            if !compressing && entry.file_type()?.is_file() {
+                segments.push((order, entry.path()));
+            }
+        }
+    }
+    segments.sort();
+    Ok(segments.into_iter().map(|(_, path)| path).collect())
+}
+
+fn open(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let written = file.metadata()?.len();
+    Ok((BufWriter::new(file), written))
+}
+
+/// When an existing file was started, so that appending to it doesn't put off
+/// its rotation
+fn started(path: &Path) -> io::Result<DateTime<Utc>> {
+    let metadata = fs::metadata(path)?;
+    Ok(metadata.created().or_else(|_| metadata.modified())?.into())
+}
+
+fn gzipped_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// Where `path` is gzipped to, until finished
+fn partial_gzipped_path(path: &Path) -> PathBuf {
+    let mut name = gzipped_path(path).into_os_string();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+fn gzip(path: &Path, partial: File) -> io::Result<()> {
+    let mut compressed = ZBuilder::<Gzip, _>::new()
+        .compression_level(Compression::best())
+        .from_writer(BufWriter::new(partial));
+    io::copy(&mut BufReader::new(File::open(path)?), &mut compressed)?;
+    compressed.finish().map_err(io::Error::other)?;
+    fs::rename(partial_gzipped_path(path), gzipped_path(path))?;
+    fs::remove_file(path)
+}
+
+impl Write for RotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let now = Utc::now();
+        if self.due(&now) {
+            self.rotate(now)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::{Write, Read}, fs::{self, File}};
+
+    use chrono::{Utc, TimeZone};
+    use flate2::read::GzDecoder;
+
+    use super::{RotatingWriter, Rotation, RotationPeriod, parse_size, rotated_segments};
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(parse_size("64KB").unwrap(), 64 * 1024);
+        assert_eq!(parse_size("10mb").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_size("1GB").unwrap(), 1024 * 1024 * 1024);
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("100000000000GB").is_err());
+    }
+
+    #[test]
+    fn rotate_on_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let rotation = Rotation { max_bytes: Some(10), period: None, compress: false };
+        let mut writer = RotatingWriter::create(&path, rotation).unwrap();
+        writeln!(writer, "short").unwrap();
+        writer.flush().unwrap();
+        writeln!(writer, "long enough").unwrap();
+        writer.flush().unwrap();
+        writeln!(writer, "next").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "next\n");
+        let rotated : Vec<String> = fs::read_dir(dir.path()).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|n| n != "events.jsonl")
+            .collect();
+        assert_eq!(rotated.len(), 1);
+        assert!(rotated[0].starts_with("events-") && rotated[0].ends_with(".jsonl"));
+        assert_eq!(fs::read_to_string(dir.path().join(&rotated[0])).unwrap(), "short\nlong enough\n");
+    }
+
+    #[test]
+    fn rotate_on_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let rotation = Rotation { max_bytes: None, period: Some(RotationPeriod::Hourly), compress: false };
+        let mut writer = RotatingWriter::create(dir.path().join("events.jsonl"), rotation).unwrap();
+        writer.opened_at = Utc.with_ymd_and_hms(2023, 8, 12, 10, 59, 0).unwrap();
+        assert!(!writer.due(&Utc.with_ymd_and_hms(2023, 8, 12, 11, 0, 0).unwrap()));
+        writeln!(writer, "event").unwrap();
+        assert!(!writer.due(&Utc.with_ymd_and_hms(2023, 8, 12, 10, 59, 59).unwrap()));
+        assert!(writer.due(&Utc.with_ymd_and_hms(2023, 8, 12, 11, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn preamble_in_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.csv");
+        let rotation = Rotation { max_bytes: Some(10), period: None, compress: false };
+        let mut writer = RotatingWriter::create(&path, rotation).unwrap().with_preamble(b"header\n".to_vec()).unwrap();
+        writeln!(writer, "first").unwrap();
+        writer.flush().unwrap();
+        writeln!(writer, "2").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "header\n2\n");
+        let rotated = rotated_segments(&path).unwrap();
+        assert_eq!(fs::read_to_string(&rotated[0]).unwrap(), "header\nfirst\n");
+    }
+
+    #[test]
+    fn find_rotated_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        for name in [
+            "events.jsonl", "events-20230812T110000.jsonl.gz", "events-20230812T100000.jsonl", "events-20230812T100000-1.jsonl",
+            "other-20230812T100000.jsonl", "events-notes.txt", "events-notes.jsonl", "events-2023.jsonl", "events-20230812T100000-x.jsonl",
+            // still being compressed
+            "events-20230812T120000.jsonl", "events-20230812T120000.jsonl.gz.tmp"
+        ] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let segments : Vec<String> = rotated_segments(&path).unwrap().iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(segments, vec!["events-20230812T100000.jsonl", "events-20230812T100000-1.jsonl", "events-20230812T110000.jsonl.gz"]);
+    }
+
+    #[test]
+    fn compress_rotated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let rotation = Rotation { max_bytes: Some(1), period: None, compress: true };
+        let mut writer = RotatingWriter::create(&path, rotation).unwrap();
+        writeln!(writer, "event").unwrap();
+        writer.flush().unwrap();
+        // waits for compressing to finish
+        drop(writer);
+
+        let rotated : Vec<_> = fs::read_dir(dir.path()).unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| *p != path)
+            .collect();
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(rotated[0].extension().unwrap(), "gz");
+        let mut decompressed = String::new();
+        GzDecoder::new(File::open(&rotated[0]).unwrap()).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, "event\n");
+    }
+}
@@ -1,54 +1,277 @@
-use std::{error::Error, sync::Arc};
+use std::{sync::Arc, ops::Range};
 
 use async_trait::async_trait;
-use sqlx::{Pool, Sqlite};
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use sqlx::{Pool, Sqlite, QueryBuilder, sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous}};
 
-use crate::discover::DiscoveryEvent;
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap, signature::Signature};
 
-use super::EventSink;
+use super::{EventSink, EventSource, EventStream};
+
+/// Keeps each multi-row insert well under SQLite's limit on bound parameters
+const ROWS_PER_INSERT : usize = 100;
+
+/// WAL lets readers (e.g. blescan-cli) work alongside an ongoing recording,
+/// and makes each commit much cheaper
+pub fn connect_options(path: &std::path::Path) -> SqliteConnectOptions {
+    SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .synchronous(SqliteSynchronous::Normal)
+}
+
+/// Brings the tables up to date, e.g. adding the structured signature columns
+/// to files recorded before them
+pub async fn migrate(pool: &Pool<Sqlite>) -> Result<(), Error> {
+    sqlx::migrate!("./migrations").run(pool).await?;
+    Ok(())
+}
+
+/// The id of the signature policy the history was recorded with, if known
+pub async fn recorded_signature_policy(pool: &Pool<Sqlite>) -> Result<Option<String>, Error> {
+    let recorded : Option<(String,)> 
+        = sqlx::query_as("SELECT value FROM metadata WHERE key = 'signature_policy'")
+            .fetch_optional(pool)
+            .await?;
+    Ok(recorded.map(|(id,)| id))
+}
 
 pub struct SQLLiteEventSink {
-    pool: Arc<Pool<Sqlite>>
+    pool: Arc<Pool<Sqlite>>,
+    source: Option<String>,
+    session_id: String
 }
 
 impl SQLLiteEventSink {
-    pub async fn create_from_pool(pool: Arc<Pool<Sqlite>>) -> Result<SQLLiteEventSink, Box<dyn Error>> {
-        sqlx::migrate!("./migrations")
-            .run(&*pool.clone())
-            .await?;
+    pub async fn create_from_pool(pool: Arc<Pool<Sqlite>>) -> Result<SQLLiteEventSink, Error> {
+        migrate(&pool).await?;
         Ok(SQLLiteEventSink {
-            pool: pool.clone()
+            pool: pool.clone(),
+            source: None,
+            session_id: format!("{}-{}", Utc::now().format("%Y%m%dT%H%M%S"), std::process::id())
         })
     }
+
+    /// Label events with where they were seen from, e.g. "hallway"
+    #[must_use] pub fn with_source(mut self, source: Option<String>) -> SQLLiteEventSink {
+        self.source = source;
+        self
+    }
+}
+
+impl SQLLiteEventSink {
+    /// Anonymous signatures are only comparable if they were all derived using
+    /// the same policy, so refuse to mix policies within one DB
+    pub async fn check_signature_policy(&self, id: &str) -> Result<(), Error> {
+        match recorded_signature_policy(&self.pool).await? {
+            Some(recorded_id) if recorded_id != id => {
+                Err(Error::SignaturePolicyMismatch { path: "history".to_string(), recorded: recorded_id, requested: id.to_string() })
+            },
+            Some(_) => Ok(()),
+            None => {
+                sqlx::query("INSERT INTO metadata (key, value) VALUES ('signature_policy', ?)")
+                    .bind(id)
+                    .execute(&*self.pool)
+                    .await?;
+                Ok(())
+            }
+        }
+    }
 }
 
-unsafe impl Send for SQLLiteEventSink {}
+impl SQLLiteEventSink {
+    /// Deletes the events, and gaps, from before `before`, returning how many
+    /// of each were deleted
+    pub async fn prune(&self, before: DateTime<Utc>) -> Result<(u64, u64), Error> {
+        let mut tx = self.pool.begin().await?;
+        let events = sqlx::query("DELETE FROM discovery_events WHERE date_time < ?")
+            .bind(before)
+            .execute(&mut *tx)
+            .await?;
+        let gaps = sqlx::query("DELETE FROM scan_gaps WHERE end < ?")
+            .bind(before)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok((events.rows_affected(), gaps.rows_affected()))
+    }
+
+    /// Gives back the space left behind by deleted rows
+    pub async fn vacuum(&self) -> Result<(), Error> {
+        sqlx::query("VACUUM").execute(&*self.pool).await?;
+        Ok(())
+    }
+}
 
 #[async_trait]
 impl EventSink for SQLLiteEventSink {
-    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Box<dyn Error>> {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
         let p = self.pool.clone();
         let mut tx = p.begin().await?;
         
-        for e in events {
-            sqlx::query("
-            INSERT INTO discovery_events (date_time, signature, rssi) 
-            VALUES (?, ?, ?)")
-                .bind(e.date_time)
-                .bind(format!("{}", e.signature))
-                .bind(e.rssi)
+        for chunk in events.chunks(ROWS_PER_INSERT) {
+            let mut query = QueryBuilder::<Sqlite>::new("
+            INSERT INTO discovery_events (date_time, signature, rssi, signature_kind, signature_value, source, session_id) ");
+            query.push_values(chunk, |mut row, e| {
+                row.push_bind(e.date_time)
+                    .push_bind(format!("{}", e.signature))
+                    .push_bind(e.rssi)
+                    .push_bind(e.signature.kind())
+                    .push_bind(e.signature.value().to_string())
+                    .push_bind(self.source.clone())
+                    .push_bind(self.session_id.clone());
+            });
+            query.build()
+                .persistent(true)
                 .execute(&mut *tx)
                 .await?;
         }
         tx.commit().await?;
         Ok(())
     }
-    async fn close(mut self: Box<Self>) -> Result<(), Box<dyn Error>> {
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error> {
+        sqlx::query("
+        INSERT INTO scan_gaps (start, end, reason) 
+        VALUES (?, ?, ?)")
+            .bind(gap.start)
+            .bind(gap.end)
+            .bind(format!("{}", gap.reason))
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
         self.pool.close().await;
         Ok(())
     }
 }
 
+/// Reads back events written by a `SQLLiteEventSink`
+pub struct SQLLiteEventSource {
+    pool: Arc<Pool<Sqlite>>
+}
+
+impl SQLLiteEventSource {
+    #[must_use] pub fn create_from_pool(pool: Arc<Pool<Sqlite>>) -> SQLLiteEventSource {
+        SQLLiteEventSource { pool }
+    }
+}
+
+#[async_trait]
+impl EventSource for SQLLiteEventSource {
+    async fn time_range(&self) -> Result<Option<Range<DateTime<Utc>>>, Error> {
+        let first_and_last : (Option<DateTime<Utc>>, Option<DateTime<Utc>>) 
+            = sqlx::query_as("SELECT MIN(date_time), MAX(date_time) FROM discovery_events")
+                .fetch_one(&*self.pool)
+                .await?;
+        Ok(match first_and_last {
+            (Some(first), Some(last)) => Some(first..last + chrono::Duration::seconds(1)),
+            _ => None
+        })
+    }
+
+    async fn gaps(&self, range: Range<DateTime<Utc>>) -> Result<Vec<ScanGap>, Error> {
+        let rows : Vec<(DateTime<Utc>, DateTime<Utc>, String)> 
+            = sqlx::query_as("
+            SELECT start, end, reason FROM scan_gaps 
+            WHERE end > ? AND start < ?
+            ORDER BY start")
+                .bind(range.start)
+                .bind(range.end)
+                .fetch_all(&*self.pool)
+                .await?;
+        Ok(rows.into_iter()
+            .filter_map(|(start, end, reason)| {
+                reason.parse().ok().map(|reason| ScanGap::new(start, end, reason))
+            })
+            .collect())
+    }
+
+    fn stream_events(&self, range: Range<DateTime<Utc>>, chunk_size: usize) -> EventStream<'_> {
+        stream::try_unfold(Some(0i64), move |after_rowid| {
+            let range = range.clone();
+            async move {
+                let Some(after_rowid) = after_rowid else {
+                    return Ok(None);
+                };
+                let rows : Vec<(i64, DateTime<Utc>, String, String, i16)> 
+                    = sqlx::query_as("
+                    SELECT rowid, date_time, signature_kind, signature_value, rssi FROM discovery_events 
+                    WHERE rowid > ? AND date_time >= ? AND date_time < ?
+                    ORDER BY rowid
+                    LIMIT ?")
+                        .bind(after_rowid)
+                        .bind(range.start)
+                        .bind(range.end)
+                        .bind(i64::try_from(chunk_size).unwrap_or(i64::MAX))
+                        .fetch_all(&*self.pool)
+                        .await?;
+                if rows.is_empty() {
+                    return Ok(None);
+                }
+                let next = if rows.len() < chunk_size {
+                    None
+                }
+                else {
+                    rows.last().map(|(rowid, _, _, _, _)| *rowid)
+                };
+                let events = rows.into_iter()
+                    .filter_map(|(_, date_time, kind, value, rssi)| {
+                        Signature::from_kind_and_value(&kind, &value)
+                            .map(|signature| DiscoveryEvent::new(date_time, signature, rssi))
+                    })
+                    .collect();
+                Ok(Some((events, next)))
+            }
+        }).boxed()
+    }
+
+    /// Uses the `(signature_kind, signature_value, date_time)` index, reading
+    /// on from the last `(date_time, rowid)` seen
+    fn stream_device_events(&self, signature: &Signature, range: Range<DateTime<Utc>>, chunk_size: usize) -> EventStream<'_> {
+        let signature = signature.clone();
+        stream::try_unfold(Some((range.start, i64::MIN)), move |after| {
+            let (signature, end) = (signature.clone(), range.end);
+            async move {
+                let Some((after_date_time, after_rowid)) = after else {
+                    return Ok(None);
+                };
+                let rows : Vec<(i64, DateTime<Utc>, i16)>
+                    = sqlx::query_as("
+                    SELECT rowid, date_time, rssi FROM discovery_events
+                    WHERE signature_kind = ? AND signature_value = ?
+                    AND (date_time > ? OR (date_time = ? AND rowid > ?)) AND date_time < ?
+                    ORDER BY date_time, rowid
+                    LIMIT ?")
+                        .bind(signature.kind())
+                        .bind(signature.value())
+                        .bind(after_date_time)
+                        .bind(after_date_time)
+                        .bind(after_rowid)
+                        .bind(end)
+                        .bind(i64::try_from(chunk_size).unwrap_or(i64::MAX))
+                        .fetch_all(&*self.pool)
+                        .await?;
+                if rows.is_empty() {
+                    return Ok(None);
+                }
+                let next = if rows.len() < chunk_size {
+                    None
+                }
+                else {
+                    rows.last().map(|(rowid, date_time, _)| (*date_time, *rowid))
+                };
+                let events = rows.into_iter()
+                    .map(|(_, date_time, rssi)| DiscoveryEvent::new(date_time, signature.clone(), rssi))
+                    .collect();
+                Ok(Some((events, next)))
+            }
+        }).boxed()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::sync::Arc;
@@ -56,11 +279,24 @@ mod test {
     use chrono::{Utc, TimeZone, DateTime};
     use sqlx::{sqlite::{SqlitePoolOptions, SqliteRow}, Row};
 
-    use crate::{discover::DiscoveryEvent, signature::Signature, history::EventSink};
+    use crate::{discover::DiscoveryEvent, signature::{Signature, SignaturePolicy}, history::{EventSink, EventSource}, gap::{ScanGap, GapReason}};
+
+    use super::{SQLLiteEventSink, SQLLiteEventSource};
 
-    use super::SQLLiteEventSink;
+    #[tokio::test]
+    async fn sink_from_another_task() {
+        let pool = Arc::new(SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap());
+        let sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap();
+        tokio::spawn(async move {
+            let mut sink = sink;
+            sink.save(&[DiscoveryEvent::new(Utc.timestamp_opt(1, 0).unwrap(), Signature::Named("1".to_string()), -10)]).await.unwrap();
+        }).await.unwrap();
+        let (count,) : (i64,) = sqlx::query_as("SELECT COUNT(*) FROM discovery_events").fetch_one(&*pool).await.unwrap();
+        assert_eq!(count, 1);
+    }
     
     #[tokio::test]
+    #[allow(clippy::useless_vec, clippy::needless_borrow, clippy::get_first)]
     async fn sink_multiple_events() {
         let events = &vec![
             DiscoveryEvent::new(
@@ -85,6 +321,186 @@ mod test {
         assert_row_eq(&rows.get(1).unwrap(), &events[1]);
     }
 
+    #[tokio::test]
+    async fn sink_gap() {
+        let gap = ScanGap::new(
+            Utc.timestamp_opt(1, 0).unwrap(),
+            Utc.timestamp_opt(61, 0).unwrap(),
+            GapReason::Paused);
+        
+        let pool = Arc::new(SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap());
+        let mut sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap();
+        sink.save_gap(&gap).await.unwrap();
+        let row 
+            = sqlx::query("SELECT * FROM scan_gaps;")
+                .fetch_one(&*pool.clone())
+                .await.unwrap();
+        let actual_start : DateTime<Utc> = row.get(0);
+        assert_eq!(actual_start, gap.start);
+        let actual_end : DateTime<Utc> = row.get(1);
+        assert_eq!(actual_end, gap.end);
+        let actual_reason : String = row.get(2);
+        assert_eq!(actual_reason, "paused");
+    }
+
+    #[tokio::test]
+    async fn signature_policy_must_match_history() {
+        let pool = Arc::new(SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap());
+        let sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap();
+        sink.check_signature_policy(&SignaturePolicy::Xxh3.id()).await.unwrap();
+        sink.check_signature_policy(&SignaturePolicy::Xxh3.id()).await.unwrap();
+        assert!(sink.check_signature_policy(&SignaturePolicy::Md5Legacy.id()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_events_in_chunks() {
+        use futures::TryStreamExt;
+
+        let events : Vec<DiscoveryEvent> = (0..10).map(|i| {
+            let signature = if i % 2 == 0 {
+                Signature::Named(format!("Device {i}"))
+            }
+            else {
+                Signature::Anonymous(format!("{:x}", md5::compute([i])))
+            };
+            DiscoveryEvent::new(Utc.timestamp_opt(i64::from(i), 0).unwrap(), signature, -20)
+        }).collect();
+        let pool = Arc::new(SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap());
+        let mut sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap();
+        sink.save(&events).await.unwrap();
+
+        let source = SQLLiteEventSource::create_from_pool(pool.clone());
+        let range = Utc.timestamp_opt(2, 0).unwrap()..Utc.timestamp_opt(9, 0).unwrap();
+        let chunks : Vec<Vec<DiscoveryEvent>> = source.stream_events(range, 3).try_collect().await.unwrap();
+        let chunk_sizes : Vec<usize> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(chunk_sizes, vec![3, 3, 1]);
+        let streamed : Vec<DiscoveryEvent> = chunks.into_iter().flatten().collect();
+        assert_eq!(streamed, events[2..9].to_vec());
+    }
+
+    #[tokio::test]
+    async fn stream_device_events_by_index() {
+        use futures::TryStreamExt;
+
+        let device = Signature::Named("Device 1".to_string());
+        // two sightings a second, and another device in between, so that a
+        // chunk ends part way through a second
+        let events : Vec<DiscoveryEvent> = (0..10u8).map(|i| {
+            let signature = if i % 3 == 2 { Signature::Named("Device 2".to_string()) } else { device.clone() };
+            DiscoveryEvent::new(Utc.timestamp_opt(i64::from(i / 2), 0).unwrap(), signature, -20 - i16::from(i))
+        }).collect();
+        let pool = Arc::new(SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap());
+        let mut sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap();
+        sink.save(&events).await.unwrap();
+
+        let source = SQLLiteEventSource::create_from_pool(pool.clone());
+        let range = Utc.timestamp_opt(1, 0).unwrap()..Utc.timestamp_opt(5, 0).unwrap();
+        let chunks : Vec<Vec<DiscoveryEvent>> = source.stream_device_events(&device, range.clone(), 3).try_collect().await.unwrap();
+        let streamed : Vec<DiscoveryEvent> = chunks.into_iter().flatten().collect();
+        let expected : Vec<DiscoveryEvent> = events.into_iter()
+            .filter(|e| e.signature == device && range.contains(&e.date_time))
+            .collect();
+        assert_eq!(streamed, expected);
+
+        let plan : Vec<(i64, i64, i64, String)> = sqlx::query_as("
+            EXPLAIN QUERY PLAN SELECT rowid FROM discovery_events
+            WHERE signature_kind = 'named' AND signature_value = 'Device 1' AND date_time > 0
+            ORDER BY date_time, rowid")
+            .fetch_all(&*pool).await.unwrap();
+        assert!(plan.iter().any(|(_, _, _, detail)| detail.contains("discovery_events_by_signature")));
+    }
+
+    #[tokio::test]
+    async fn read_back_gaps_and_time_range() {
+        let pool = Arc::new(SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap());
+        let mut sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap();
+        let source = SQLLiteEventSource::create_from_pool(pool.clone());
+        assert_eq!(source.time_range().await.unwrap(), None);
+
+        let gap = ScanGap::new(Utc.timestamp_opt(10, 0).unwrap(), Utc.timestamp_opt(20, 0).unwrap(), GapReason::Stalled);
+        sink.save_gap(&gap).await.unwrap();
+        sink.save(&[
+            DiscoveryEvent::new(Utc.timestamp_opt(5, 0).unwrap(), Signature::Named("Device 1".to_string()), -20),
+            DiscoveryEvent::new(Utc.timestamp_opt(25, 0).unwrap(), Signature::Named("Device 1".to_string()), -20),
+        ]).await.unwrap();
+
+        assert_eq!(source.time_range().await.unwrap(), 
+            Some(Utc.timestamp_opt(5, 0).unwrap()..Utc.timestamp_opt(26, 0).unwrap()));
+        assert_eq!(source.gaps(Utc.timestamp_opt(0, 0).unwrap()..Utc.timestamp_opt(15, 0).unwrap()).await.unwrap(), vec![gap]);
+        assert!(source.gaps(Utc.timestamp_opt(20, 0).unwrap()..Utc.timestamp_opt(30, 0).unwrap()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn structured_signature_columns() {
+        let pool = Arc::new(SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap());
+        let mut sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap()
+            .with_source(Some("hallway".to_string()));
+        sink.save(&[
+            DiscoveryEvent::new(Utc.timestamp_opt(1, 0).unwrap(), Signature::Named("Device 1".to_string()), -20),
+            DiscoveryEvent::new(Utc.timestamp_opt(2, 0).unwrap(), Signature::Anonymous("503eb25838435ebb288f3b657b9f9031".to_string()), -30),
+        ]).await.unwrap();
+        let rows : Vec<(String, String, Option<String>, Option<String>)> 
+            = sqlx::query_as("SELECT signature_kind, signature_value, source, session_id FROM discovery_events ORDER BY rowid;")
+                .fetch_all(&*pool.clone())
+                .await.unwrap();
+        assert_eq!(rows[0].0, "named");
+        assert_eq!(rows[0].1, "Device 1");
+        assert_eq!(rows[1].0, "anonymous");
+        assert_eq!(rows[1].1, "503eb25838435ebb288f3b657b9f9031");
+        assert!(rows.iter().all(|r| r.2 == Some("hallway".to_string())));
+        assert!(rows[0].3.is_some());
+        assert_eq!(rows[0].3, rows[1].3);
+    }
+
+    #[tokio::test]
+    async fn sink_many_events() {
+        let events : Vec<DiscoveryEvent> = (0..250).map(|i| {
+            DiscoveryEvent::new(Utc.timestamp_opt(i, 0).unwrap(), Signature::Named(format!("Device {i}")), -20)
+        }).collect();
+        let pool = Arc::new(SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap());
+        let mut sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap();
+        sink.save(&events).await.unwrap();
+        let rows 
+            = sqlx::query("SELECT * FROM discovery_events ORDER BY rowid;")
+                .fetch_all(&*pool.clone())
+                .await.unwrap();
+        assert_eq!(rows.len(), events.len());
+        for (row, event) in rows.iter().zip(events.iter()) {
+            assert_row_eq(row, event);
+        }
+    }
+
+    #[tokio::test]
+    async fn uses_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        // characters which would be taken as part of a URL
+        let path = dir.path().join("events #1?.sqlite");
+        let options = super::connect_options(&path);
+        let pool = Arc::new(SqlitePoolOptions::new().connect_with(options).await.unwrap());
+        let (mode,) : (String,) = sqlx::query_as("PRAGMA journal_mode;").fetch_one(&*pool).await.unwrap();
+        assert_eq!(mode, "wal");
+        assert!(path.exists());
+    }
+
+    #[tokio::test]
+    async fn prune_before() {
+        let at = |seconds: i64| Utc.timestamp_opt(seconds, 0).unwrap();
+        let pool = Arc::new(SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap());
+        let mut sink = SQLLiteEventSink::create_from_pool(pool.clone()).await.unwrap();
+        sink.save(&[
+            DiscoveryEvent::new(at(1), Signature::Named("Device 1".to_string()), -20),
+            DiscoveryEvent::new(at(100), Signature::Named("Device 1".to_string()), -20),
+        ]).await.unwrap();
+        sink.save_gap(&ScanGap::new(at(10), at(20), GapReason::Stalled)).await.unwrap();
+        sink.save_gap(&ScanGap::new(at(40), at(60), GapReason::Stalled)).await.unwrap();
+
+        assert_eq!(sink.prune(at(50)).await.unwrap(), (1, 1));
+        sink.vacuum().await.unwrap();
+        let source = SQLLiteEventSource::create_from_pool(pool);
+        assert_eq!(source.time_range().await.unwrap(), Some(at(100)..at(101)));
+        assert_eq!(source.gaps(at(0)..at(101)).await.unwrap(), vec![ScanGap::new(at(40), at(60), GapReason::Stalled)]);
+    }
+
     fn assert_row_eq(actual: &SqliteRow, expected: &DiscoveryEvent) {
         let actual_date_time : DateTime<Utc> = actual.get(0);
         assert_eq!(actual_date_time, expected.date_time);
@@ -0,0 +1,192 @@
+use std::{fmt, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
+
+use super::EventSink;
+
+/// What has been recorded so far, for telling whether a sink is keeping up
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct SinkStats {
+    pub events: u64,
+    pub gaps: u64,
+    /// `None` if the sink doesn't know how much it has written
+    pub bytes: Option<u64>,
+    pub saves: u64,
+    pub errors: u64,
+    pub last_save_time: Option<Duration>,
+    pub total_save_time: Duration
+}
+
+impl SinkStats {
+    #[must_use] pub fn mean_save_time(&self) -> Option<Duration> {
+        u32::try_from(self.saves).ok()
+            .filter(|saves| *saves > 0)
+            .map(|saves| self.total_save_time / saves)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    #[allow(clippy::cast_precision_loss)]
+    let (value, unit) = match bytes {
+        b if b >= 1 << 30 => (b as f64 / f64::from(1 << 30), "GB"),
+        b if b >= 1 << 20 => (b as f64 / f64::from(1 << 20), "MB"),
+        b if b >= 1 << 10 => (b as f64 / f64::from(1 << 10), "KB"),
+        b => return format!("{b}B")
+    };
+    format!("{value:.1}{unit}")
+}
+
+fn format_millis(duration: Duration) -> String {
+    format!("{}ms", duration.as_millis())
+}
+
+impl fmt::Display for SinkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} events", self.events)?;
+        if let Some(bytes) = self.bytes {
+            write!(f, ", {}", format_bytes(bytes))?;
+        }
+        if let (Some(last), Some(mean)) = (self.last_save_time, self.mean_save_time()) {
+            write!(f, ", last save {} (mean {})", format_millis(last), format_millis(mean))?;
+        }
+        write!(f, ", {} errors", self.errors)
+    }
+}
+
+/// A handle on the stats of an `InstrumentedEventSink`, which stays usable
+/// once the sink itself has been boxed up
+#[derive(Debug, Clone, Default)]
+pub struct SharedSinkStats(Arc<Mutex<SinkStats>>);
+
+impl SharedSinkStats {
+    #[must_use] pub fn get(&self) -> SinkStats {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SinkStats> {
+        // stats are only ever added to, so are still usable after a panic
+        self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Counts what is passed through to `inner`, and how long it takes
+pub struct InstrumentedEventSink {
+    inner: Box<dyn EventSink>,
+    stats: SharedSinkStats,
+    failed: u64
+}
+
+impl InstrumentedEventSink {
+    #[must_use] pub fn create_from_sink(inner: Box<dyn EventSink>) -> InstrumentedEventSink {
+        InstrumentedEventSink { inner, stats: SharedSinkStats::default(), failed: 0 }
+    }
+
+    #[must_use] pub fn stats(&self) -> SharedSinkStats {
+        self.stats.clone()
+    }
+
+    fn record<T>(&mut self, started: Instant, result: &Result<T, Error>, update: impl FnOnce(&mut SinkStats)) {
+        let elapsed = started.elapsed();
+        if result.is_err() {
+            self.failed += 1;
+        }
+        let mut stats = self.stats.lock();
+        stats.saves += 1;
+        stats.last_save_time = Some(elapsed);
+        stats.total_save_time += elapsed;
+        stats.bytes = self.inner.bytes_written();
+        stats.errors = self.failed + self.inner.errors_handled();
+        if result.is_ok() {
+            update(&mut stats);
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for InstrumentedEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        let started = Instant::now();
+        let result = self.inner.save(events).await;
+        self.record(started, &result, |stats| stats.events += events.len() as u64);
+        result
+    }
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error> {
+        let started = Instant::now();
+        let result = self.inner.save_gap(gap).await;
+        self.record(started, &result, |stats| stats.gaps += 1);
+        result
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        self.inner.close().await
+    }
+    fn bytes_written(&self) -> Option<u64> {
+        self.inner.bytes_written()
+    }
+    fn errors_handled(&self) -> u64 {
+        self.inner.errors_handled()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use chrono::{Utc, TimeZone};
+
+    use crate::{gap::{ScanGap, GapReason}, history::{EventSink, jsonl::JsonLinesEventSink, tee::TeeEventSink, test_support::{event, FailingEventSink}}};
+
+    use super::{InstrumentedEventSink, SinkStats};
+
+    #[tokio::test]
+    async fn count_what_was_written() {
+        let mut sink = InstrumentedEventSink::create_from_sink(
+            Box::new(JsonLinesEventSink::create_from_writer(Box::new(std::io::sink()))));
+        let stats = sink.stats();
+        sink.save(&[event(1), event(1)]).await.unwrap();
+        sink.save_gap(&ScanGap::new(Utc.timestamp_opt(1, 0).unwrap(), Utc.timestamp_opt(61, 0).unwrap(), GapReason::Stalled)).await.unwrap();
+
+        let stats = stats.get();
+        let line_length = serde_json::to_string(&event(1)).unwrap().len() as u64 + 1;
+        assert_eq!((stats.events, stats.gaps, stats.saves, stats.errors), (2, 1, 2, 0));
+        assert!(stats.bytes.unwrap() > 2 * line_length);
+        assert!(stats.last_save_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn count_errors() {
+        let mut sink = InstrumentedEventSink::create_from_sink(Box::new(FailingEventSink));
+        assert!(sink.save(&[event(1)]).await.is_err());
+
+        let stats = sink.stats().get();
+        assert_eq!((stats.events, stats.saves, stats.errors, stats.bytes), (0, 1, 1, None));
+    }
+
+    #[tokio::test]
+    async fn count_errors_handled_by_inner_sinks() {
+        let mut sink = InstrumentedEventSink::create_from_sink(Box::new(TeeEventSink::create_from_sinks(vec![
+            Box::new(FailingEventSink),
+            Box::new(JsonLinesEventSink::create_from_writer(Box::new(std::io::sink())))
+        ])));
+        sink.save(&[event(1)]).await.unwrap();
+
+        let stats = sink.stats().get();
+        assert_eq!((stats.events, stats.saves, stats.errors), (1, 1, 1));
+    }
+
+    #[test]
+    fn summary() {
+        let stats = SinkStats {
+            events: 1234,
+            gaps: 0,
+            bytes: Some(3 * 1024 * 1024 / 2),
+            saves: 2,
+            errors: 1,
+            last_save_time: Some(Duration::from_millis(3)),
+            total_save_time: Duration::from_millis(10)
+        };
+        assert_eq!(stats.to_string(), "1234 events, 1.5MB, last save 3ms (mean 5ms), 1 errors");
+        assert_eq!(SinkStats::default().to_string(), "0 events, 0 errors");
+    }
+}
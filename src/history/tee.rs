@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
+
+use super::EventSink;
+
+/// Writes to several sinks at once. A failing child doesn't stop the others
+/// being written to, and is logged and counted in `errors_handled`; an error
+/// is only returned once every child has failed.
+pub struct TeeEventSink {
+    children: Vec<Box<dyn EventSink>>,
+    errors_handled: u64
+}
+
+impl TeeEventSink {
+    #[must_use] pub fn create_from_sinks(children: Vec<Box<dyn EventSink>>) -> TeeEventSink {
+        TeeEventSink { children, errors_handled: 0 }
+    }
+
+    fn result(&mut self, results: Vec<Result<(), Error>>) -> Result<(), Error> {
+        let count = results.len();
+        let mut errors = vec![];
+        for (index, result) in results.into_iter().enumerate() {
+            if let Err(e) = result {
+                tracing::warn!("recording to sink {} of {count} failed: {e}", index + 1);
+                errors.push(e);
+            }
+        }
+        if count > 0 && errors.len() == count {
+            Err(Error::Sinks(errors))
+        }
+        else {
+            self.errors_handled += errors.len() as u64;
+            Ok(())
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for TeeEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        let mut results = vec![];
+        for child in &mut self.children {
+            results.push(child.save(events).await);
+        }
+        self.result(results)
+    }
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error> {
+        let mut results = vec![];
+        for child in &mut self.children {
+            results.push(child.save_gap(gap).await);
+        }
+        self.result(results)
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        let mut errors = vec![];
+        for child in self.children {
+            if let Err(e) = child.close().await {
+                errors.push(e);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(Error::Sinks(errors))
+        }
+    }
+    fn bytes_written(&self) -> Option<u64> {
+        self.children.iter()
+            .filter_map(|child| child.bytes_written())
+            .reduce(|total, bytes| total + bytes)
+    }
+    fn errors_handled(&self) -> u64 {
+        self.errors_handled + self.children.iter().map(|child| child.errors_handled()).sum::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{error::Error, history::{EventSink, test_support::{events, Saves, RecordingEventSink, FailingEventSink}}};
+
+    use super::TeeEventSink;
+
+    #[tokio::test]
+    async fn writes_to_all_children() {
+        let first = Saves::default();
+        let second = Saves::default();
+        let mut tee = TeeEventSink::create_from_sinks(vec![
+            Box::new(RecordingEventSink::new(&first)),
+            Box::new(RecordingEventSink::new(&second)),
+        ]);
+        tee.save(&events(1)).await.unwrap();
+        assert_eq!(first.events(), 1);
+        assert_eq!(second.events(), 1);
+    }
+
+    #[tokio::test]
+    async fn isolates_failing_children() {
+        let working = Saves::default();
+        let mut tee = TeeEventSink::create_from_sinks(vec![
+            Box::new(FailingEventSink),
+            Box::new(RecordingEventSink::new(&working)),
+        ]);
+        tee.save(&events(1)).await.unwrap();
+        assert_eq!(working.events(), 1);
+        assert_eq!(tee.errors_handled(), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_when_all_children_fail() {
+        let mut tee = TeeEventSink::create_from_sinks(vec![
+            Box::new(FailingEventSink),
+            Box::new(FailingEventSink),
+        ]);
+        assert!(matches!(tee.save(&events(1)).await, Err(Error::Sinks(errors)) if errors.len() == 2));
+    }
+}
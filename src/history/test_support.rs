@@ -0,0 +1,90 @@
+use std::{io, sync::{Arc, Mutex}};
+
+use async_trait::async_trait;
+use chrono::{Utc, TimeZone};
+use tokio::sync::Semaphore;
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap, signature::Signature};
+
+use super::EventSink;
+
+/// An event from "Device 1", `seconds` after the epoch
+pub fn event(seconds: i64) -> DiscoveryEvent {
+    DiscoveryEvent::new(Utc.timestamp_opt(seconds, 0).unwrap(), Signature::Named("Device 1".to_string()), -20)
+}
+
+/// `count` events from "Device 1", all at the same time
+pub fn events(count: usize) -> Vec<DiscoveryEvent> {
+    vec![event(1); count]
+}
+
+/// What a `RecordingEventSink` was given, one entry per save, which can be
+/// looked at while the sink is owned by whatever is being tested
+#[derive(Clone, Default)]
+pub struct Saves(Arc<Mutex<Vec<Vec<DiscoveryEvent>>>>);
+
+impl Saves {
+    /// How many events each save had
+    pub fn sizes(&self) -> Vec<usize> {
+        self.0.lock().unwrap().iter().map(Vec::len).collect()
+    }
+
+    pub fn events(&self) -> usize {
+        self.sizes().iter().sum()
+    }
+
+    /// When each event saved was, in seconds, in the order they were saved
+    pub fn timestamps(&self) -> Vec<i64> {
+        self.0.lock().unwrap().iter().flatten().map(|e| e.date_time.timestamp()).collect()
+    }
+}
+
+/// Keeps what is saved to it in `saves`. If gated, each save first waits for a
+/// permit from the gate, to stand in for a slow sink.
+pub struct RecordingEventSink {
+    saves: Saves,
+    gate: Option<Arc<Semaphore>>
+}
+
+impl RecordingEventSink {
+    pub fn new(saves: &Saves) -> RecordingEventSink {
+        RecordingEventSink { saves: saves.clone(), gate: None }
+    }
+
+    pub fn gated(saves: &Saves, gate: &Arc<Semaphore>) -> RecordingEventSink {
+        RecordingEventSink { saves: saves.clone(), gate: Some(gate.clone()) }
+    }
+}
+
+#[async_trait]
+impl EventSink for RecordingEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        if let Some(gate) = &self.gate {
+            gate.acquire().await.map_err(|e| Error::Other(e.into()))?.forget();
+        }
+        self.saves.0.lock().unwrap().push(events.to_vec());
+        Ok(())
+    }
+    async fn save_gap(&mut self, _: &ScanGap) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Fails every save with an "offline" `io::Error`
+pub struct FailingEventSink;
+
+#[async_trait]
+impl EventSink for FailingEventSink {
+    async fn save(&mut self, _: &[DiscoveryEvent]) -> Result<(), Error> {
+        Err(io::Error::other("offline").into())
+    }
+    async fn save_gap(&mut self, _: &ScanGap) -> Result<(), Error> {
+        Err(io::Error::other("offline").into())
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use object_store::{ObjectStore, path::Path as ObjectPath};
+use tokio::task::JoinHandle;
+use url::Url;
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
+
+use super::{EventSink, rotate::rotated_segments};
+
+/// How often to look for newly rotated segments
+const UPLOAD_INTERVAL : Duration = Duration::from_secs(60);
+
+/// Moves segments rotated out of a recording into an object store, under
+/// `prefix`, deleting each local copy once it has been uploaded
+pub struct SegmentUploader {
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+    recording: PathBuf
+}
+
+impl SegmentUploader {
+    /// Accepts `s3://bucket/prefix` or `gs://bucket/prefix`. Credentials, and
+    /// e.g. `AWS_ENDPOINT` for MinIO, are taken from the `AWS_*` and
+    /// `GOOGLE_*` environment variables.
+    pub fn create_from_url(url: &str, recording: PathBuf) -> Result<SegmentUploader, Error> {
+        let parsed = Url::parse(url)?;
+        let env = std::env::vars()
+            .filter(|(k, _)| k.starts_with("AWS_") || k.starts_with("GOOGLE_"))
+            .map(|(k, v)| (k.to_lowercase(), v));
+        let (store, prefix) = object_store::parse_url_opts(&parsed, env)?;
+        Ok(SegmentUploader::create_from_store(Arc::from(store), prefix, recording))
+    }
+
+    #[must_use] pub fn create_from_store(store: Arc<dyn ObjectStore>, prefix: ObjectPath, recording: PathBuf) -> SegmentUploader {
+        SegmentUploader { store, prefix, recording }
+    }
+
+    /// Returns how many segments were uploaded
+    pub async fn upload_pending(&self) -> Result<usize, Error> {
+        let segments = rotated_segments(&self.recording)?;
+        for segment in &segments {
+            let name = segment.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let location = self.prefix.child(name);
+            self.store.put(&location, fs::read(segment)?.into()).await?;
+            fs::remove_file(segment)?;
+        }
+        Ok(segments.len())
+    }
+}
+
+/// Periodically uploads segments rotated out by `inner` in the background,
+/// and anything left over when closed
+pub struct UploadingEventSink {
+    inner: Box<dyn EventSink>,
+    uploader: Arc<SegmentUploader>,
+    task: JoinHandle<()>
+}
+
+impl UploadingEventSink {
+    #[must_use] pub fn create_from_sink(inner: Box<dyn EventSink>, uploader: SegmentUploader) -> UploadingEventSink {
+        let uploader = Arc::new(uploader);
+        let background = uploader.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(UPLOAD_INTERVAL).await;
+                // anything which fails to upload is tried again next time
+                if let Err(e) = background.upload_pending().await {
+                    tracing::warn!("uploading failed: {e}");
+                }
+            }
+        });
+        UploadingEventSink { inner, uploader, task }
+    }
+}
+
+#[async_trait]
+impl EventSink for UploadingEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        self.inner.save(events).await
+    }
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error> {
+        self.inner.save_gap(gap).await
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        self.task.abort();
+        self.inner.close().await?;
+        self.uploader.upload_pending().await?;
+        Ok(())
+    }
+    fn bytes_written(&self) -> Option<u64> {
+        self.inner.bytes_written()
+    }
+    fn errors_handled(&self) -> u64 {
+        self.inner.errors_handled()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::{self, File}, sync::Arc};
+
+    use object_store::{memory::InMemory, path::Path as ObjectPath, ObjectStore};
+
+    use super::SegmentUploader;
+
+    #[tokio::test]
+    async fn upload_rotated_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("events.jsonl");
+        File::create(&recording).unwrap();
+        fs::write(dir.path().join("events-20230812T100000.jsonl.gz"), b"segment").unwrap();
+        let store = Arc::new(InMemory::new());
+        let uploader = SegmentUploader::create_from_store(store.clone(), ObjectPath::from("unit-1"), recording.clone());
+
+        assert_eq!(uploader.upload_pending().await.unwrap(), 1);
+        let uploaded = store.get(&ObjectPath::from("unit-1/events-20230812T100000.jsonl.gz")).await.unwrap()
+            .bytes().await.unwrap();
+        assert_eq!(&uploaded[..], b"segment");
+        assert!(!dir.path().join("events-20230812T100000.jsonl.gz").exists());
+        assert!(recording.exists());
+        assert_eq!(uploader.upload_pending().await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use serde::Serialize;
+use tokio::{net::TcpStream, task::JoinHandle};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::{discover::DiscoveryEvent, error::Error, gap::ScanGap};
+
+use super::EventSink;
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Batch<'a> {
+    Events(&'a [DiscoveryEvent]),
+    Gap(&'a ScanGap)
+}
+
+/// How long to wait for the collector to acknowledge closing the connection
+const CLOSE_TIMEOUT : Duration = Duration::from_secs(5);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A connection to the collector, whose incoming frames are read on a task of
+/// its own. Reading is what answers the collector's pings, and notices when it
+/// closes the connection.
+struct Connection {
+    sink: SplitSink<Socket, Message>,
+    reader: JoinHandle<()>
+}
+
+impl Connection {
+    async fn open(url: &str) -> Result<Connection, Error> {
+        let (socket, _) = connect_async(url).await?;
+        let (sink, mut stream) = socket.split();
+        let reader = tokio::spawn(async move {
+            while let Some(message) = stream.next().await {
+                if let Err(e) = message {
+                    tracing::debug!("reading from collector failed: {e}");
+                    break;
+                }
+            }
+        });
+        Ok(Connection { sink, reader })
+    }
+
+    async fn close(mut self) -> Result<(), Error> {
+        self.sink.close().await?;
+        // the reader finishes once the collector closes its side too
+        if tokio::time::timeout(CLOSE_TIMEOUT, &mut self.reader).await.is_err() {
+            tracing::debug!("collector didn't acknowledge closing the connection");
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Forwards events to a remote collector (`ws://` or `wss://`) as JSON text
+/// messages, one per save: `{"events":[...]}` or `{"gap":{...}}`
+pub struct WebSocketEventSink {
+    url: String,
+    connection: Option<Connection>,
+    bytes: u64
+}
+
+impl WebSocketEventSink {
+    pub async fn connect(url: &str) -> Result<WebSocketEventSink, Error> {
+        let connection = Connection::open(url).await?;
+        Ok(WebSocketEventSink { url: url.to_string(), connection: Some(connection), bytes: 0 })
+    }
+
+    async fn send(&mut self, batch: &Batch<'_>) -> Result<(), Error> {
+        let message = serde_json::to_string(batch)?;
+        let length = message.len() as u64;
+        // if the collector went away, reconnect once before giving up
+        for attempt in 0..2 {
+            let connection = match &mut self.connection {
+                Some(connection) => connection,
+                None => self.connection.insert(Connection::open(&self.url).await?)
+            };
+            match connection.sink.send(Message::Text(message.clone())).await {
+                Ok(()) => {
+                    self.bytes += length;
+                    return Ok(());
+                },
+                Err(e) if attempt == 0 => {
+                    tracing::warn!("sending to {} failed, reconnecting: {e}", self.url);
+                    self.connection = None;
+                },
+                Err(e) => return Err(e.into())
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSink for WebSocketEventSink {
+    async fn save(&mut self, events: &[DiscoveryEvent]) -> Result<(), Error> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.send(&Batch::Events(events)).await
+    }
+    async fn save_gap(&mut self, gap: &ScanGap) -> Result<(), Error> {
+        self.send(&Batch::Gap(gap)).await
+    }
+    async fn close(mut self: Box<Self>) -> Result<(), Error> {
+        if let Some(connection) = self.connection.take() {
+            connection.close().await?;
+        }
+        Ok(())
+    }
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Utc, TimeZone};
+    use futures::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+    use crate::{discover::DiscoveryEvent, signature::Signature, gap::{ScanGap, GapReason}, history::EventSink};
+
+    use super::WebSocketEventSink;
+
+    #[tokio::test]
+    async fn forward_to_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let collector = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+            let mut received = vec![];
+            while let Some(Ok(message)) = socket.next().await {
+                if message.is_text() {
+                    received.push(message.into_text().unwrap());
+                }
+            }
+            received
+        });
+
+        let mut sink = Box::new(WebSocketEventSink::connect(&url).await.unwrap());
+        sink.save(&[
+            DiscoveryEvent::new(Utc.timestamp_opt(1, 0).unwrap(), Signature::Named("Device 1".to_string()), -20)
+        ]).await.unwrap();
+        sink.save_gap(&ScanGap::new(Utc.timestamp_opt(1, 0).unwrap(), Utc.timestamp_opt(61, 0).unwrap(), GapReason::Stalled)).await.unwrap();
+        sink.close().await.unwrap();
+
+        assert_eq!(collector.await.unwrap(), vec![
+            "{\"events\":[{\"date_time\":\"1970-01-01T00:00:01Z\",\"signature\":{\"Named\":\"Device 1\"},\"rssi\":-20}]}",
+            "{\"gap\":{\"start\":\"1970-01-01T00:00:01Z\",\"end\":\"1970-01-01T00:01:01Z\",\"reason\":\"Stalled\"}}",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn answer_pings_between_saves() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let collector = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+            socket.send(Message::Ping(vec![1])).await.unwrap();
+            socket.next().await.unwrap().unwrap()
+        });
+
+        let _sink = WebSocketEventSink::connect(&url).await.unwrap();
+        assert_eq!(collector.await.unwrap(), Message::Pong(vec![1]));
+    }
+}
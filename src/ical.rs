@@ -0,0 +1,109 @@
+use chrono::{DateTime, Utc};
+
+use crate::presence::PresenceSession;
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace(['\r', '\n'], "\\n")
+}
+
+/// Splits a line longer than 75 octets onto continuation lines, each starting
+/// with a space, without splitting any character
+fn fold(line: &str) -> String {
+    const MAX_OCTETS : usize = 75;
+    let mut folded = String::with_capacity(line.len());
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_OCTETS {
+            folded.push_str("\r\n ");
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded
+}
+
+fn format_time(date_time: &DateTime<Utc>) -> String {
+    date_time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Renders sessions as an iCalendar (RFC 5545) file, one event per session.
+/// Calendars take their times from `DTSTART` and `DTEND`, but each event's
+/// description also says when it was, as formatted by `show_time`.
+#[must_use] pub fn to_ical(sessions: &[PresenceSession], generated_at: DateTime<Utc>, show_time: impl Fn(DateTime<Utc>) -> String) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//blescan//presence//EN".to_string(),
+    ];
+    for session in sessions {
+        let name = session.signature.value();
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}-{:x}@blescan", session.start.timestamp(), md5::compute(name.as_bytes())),
+            format!("DTSTAMP:{}", format_time(&generated_at)),
+            format!("DTSTART:{}", format_time(&session.start)),
+            format!("DTEND:{}", format_time(&session.end)),
+            format!("SUMMARY:{} present", escape_text(name)),
+            format!("DESCRIPTION:{} sightings\\, strongest RSSI {}\\, from {} to {}", session.sightings, session.strongest_rssi,
+                escape_text(&show_time(session.start)), escape_text(&show_time(session.end))),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.into_iter().map(|l| fold(&l) + "\r\n").collect()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Utc, TimeZone};
+
+    use crate::{presence::PresenceSession, signature::Signature};
+
+    use super::to_ical;
+
+    fn session(name: &str) -> PresenceSession {
+        PresenceSession {
+            signature: Signature::Named(name.to_string()),
+            start: Utc.timestamp_opt(0, 0).unwrap(),
+            end: Utc.timestamp_opt(3600, 0).unwrap(),
+            sightings: 10,
+            strongest_rssi: -40
+        }
+    }
+
+    #[test]
+    fn session_to_ical() {
+        let actual = to_ical(&[session("Keys; spare")], Utc.timestamp_opt(7200, 0).unwrap(), |t| t.to_rfc3339());
+        let expected = concat!(
+            "BEGIN:VCALENDAR\r\n",
+            "VERSION:2.0\r\n",
+            "PRODID:-//blescan//presence//EN\r\n",
+            "BEGIN:VEVENT\r\n",
+            "UID:0-1631613b2926079a192b5d263f0b3049@blescan\r\n",
+            "DTSTAMP:19700101T020000Z\r\n",
+            "DTSTART:19700101T000000Z\r\n",
+            "DTEND:19700101T010000Z\r\n",
+            "SUMMARY:Keys\\; spare present\r\n",
+            "DESCRIPTION:10 sightings\\, strongest RSSI -40\\, from 1970-01-01T00:00:00+00\r\n",
+            " :00 to 1970-01-01T01:00:00+00:00\r\n",
+            "END:VEVENT\r\n",
+            "END:VCALENDAR\r\n",
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn fold_long_lines() {
+        let name = format!("{}\r\n{}", "Küchenwaage ".repeat(8), "Ω".repeat(40));
+        let actual = to_ical(&[session(&name)], Utc.timestamp_opt(7200, 0).unwrap(), |t| t.to_rfc3339());
+        assert!(actual.split("\r\n").all(|line| line.len() <= 75));
+        let unfolded = actual.replace("\r\n ", "");
+        let summary = format!("SUMMARY:{}\\n{} present\r\n", "Küchenwaage ".repeat(8), "Ω".repeat(40));
+        assert!(unfolded.contains(&summary));
+    }
+}
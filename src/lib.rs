@@ -1,8 +1,17 @@
 pub mod history;
 pub mod chrono_extra;
 pub mod device_state;
+pub mod distance;
 pub mod snapshot;
 pub mod discover_btleplug;
 pub mod discover;
 pub mod state;
 pub mod signature;
+pub mod gap;
+pub mod presence;
+pub mod rssi_history;
+pub mod watchlist;
+pub mod ical;
+pub mod manufacturer;
+pub mod config;
+pub mod error;
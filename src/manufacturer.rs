@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+
+use crate::signature::Signature;
+
+/// The lowest Bluetooth SIG company identifier in each device's manufacturer
+/// data. Only known while scanning, as recordings don't keep it.
+pub type Manufacturers = HashMap<Signature, u16>;
+
+/// Names for some common Bluetooth SIG company identifiers, as found in
+/// manufacturer data
+const NAMES : [(u16, &str); 17] = [
+    (0x0001, "Nokia"),
+    (0x0002, "Intel"),
+    (0x0006, "Microsoft"),
+    (0x000D, "Texas Instruments"),
+    (0x000F, "Broadcom"),
+    (0x004C, "Apple"),
+    (0x0059, "Nordic Semiconductor"),
+    (0x0075, "Samsung"),
+    (0x0087, "Garmin"),
+    (0x009E, "Bose"),
+    (0x00E0, "Google"),
+    (0x012D, "Sony"),
+    (0x0157, "Huami"),
+    (0x0171, "Amazon"),
+    (0x02E5, "Espressif"),
+    (0x038F, "Xiaomi"),
+    (0x0499, "Ruuvi Innovations"),
+];
+
+/// The manufacturer's name if known, otherwise its identifier
+#[must_use] pub fn manufacturer_name(manufacturer_id: Option<u16>) -> String {
+    match manufacturer_id {
+        Some(id) => match NAMES.iter().find(|(known, _)| *known == id) {
+            Some((_, name)) => (*name).to_string(),
+            None => format!("Manufacturer {id:#06x}")
+        },
+        None => "Unknown manufacturer".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::manufacturer_name;
+
+    #[test]
+    fn names() {
+        assert_eq!(manufacturer_name(Some(0x004C)), "Apple");
+        assert_eq!(manufacturer_name(Some(0x1234)), "Manufacturer 0x1234");
+        assert_eq!(manufacturer_name(None), "Unknown manufacturer");
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{discover::DiscoveryEvent, gap::{ScanGap, observed_duration}, signature::Signature, snapshot::Snapshot};
+
+/// A continuous period during which a device was considered present
+#[derive(PartialEq, Debug, Clone)]
+pub struct PresenceSession {
+    pub signature: Signature,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub sightings: usize,
+    pub strongest_rssi: i16,
+}
+
+impl PresenceSession {
+    fn new(event: &DiscoveryEvent) -> PresenceSession {
+        PresenceSession {
+            signature: event.signature.clone(),
+            start: event.date_time,
+            end: event.date_time,
+            sightings: 1,
+            strongest_rssi: event.rssi
+        }
+    }
+
+    fn extend(&mut self, event: &DiscoveryEvent) {
+        self.end = event.date_time;
+        self.sightings += 1;
+        self.strongest_rssi = self.strongest_rssi.max(event.rssi);
+    }
+
+    #[must_use] pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+/// Groups sightings into sessions, splitting whenever a device went unseen for
+/// longer than `max_absence`. Time spent in gaps doesn't count as absence, as
+/// nothing was being scanned then.
+#[must_use] pub fn sessions(events: &[DiscoveryEvent], gaps: &[ScanGap], max_absence: Duration) -> Vec<PresenceSession> {
+    let mut by_signature : HashMap<&Signature, Vec<&DiscoveryEvent>> = HashMap::new();
+    for event in events {
+        by_signature.entry(&event.signature).or_default().push(event);
+    }
+    let mut sessions = vec![];
+    for (_, mut sightings) in by_signature {
+        sightings.sort_by_key(|e| e.date_time);
+        let mut current : Option<PresenceSession> = None;
+        for event in sightings {
+            current = match current {
+                Some(mut session) if observed_duration(session.end, event.date_time, gaps) <= max_absence => {
+                    session.extend(event);
+                    Some(session)
+                },
+                Some(session) => {
+                    sessions.push(session);
+                    Some(PresenceSession::new(event))
+                },
+                None => Some(PresenceSession::new(event))
+            };
+        }
+        sessions.extend(current);
+    }
+    sessions.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.signature.cmp(&b.signature)));
+    sessions
+}
+
+/// Builds the same sessions as `sessions`, from sightings added oldest first
+/// a batch at a time, so that they needn't all be held at once
+pub struct SessionBuilder {
+    gaps: Vec<ScanGap>,
+    max_absence: Duration,
+    open: HashMap<Signature, PresenceSession>,
+    closed: Vec<PresenceSession>
+}
+
+impl SessionBuilder {
+    #[must_use] pub fn new(gaps: Vec<ScanGap>, max_absence: Duration) -> SessionBuilder {
+        SessionBuilder { gaps, max_absence, open: HashMap::new(), closed: vec![] }
+    }
+
+    pub fn add(&mut self, events: &[DiscoveryEvent]) {
+        for event in events {
+            match self.open.get_mut(&event.signature) {
+                Some(session) if observed_duration(session.end, event.date_time, &self.gaps) <= self.max_absence => {
+                    session.extend(event);
+                },
+                _ => {
+                    if let Some(session) = self.open.insert(event.signature.clone(), PresenceSession::new(event)) {
+                        self.closed.push(session);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The sessions so far, in the order they started
+    #[must_use] pub fn sessions(&self) -> Vec<PresenceSession> {
+        let mut sessions : Vec<PresenceSession> = self.closed.iter().chain(self.open.values()).cloned().collect();
+        sessions.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.signature.cmp(&b.signature)));
+        sessions
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PresenceChange {
+    Arrived,
+    Departed
+}
+
+/// A device arriving or departing, as noticed while scanning
+#[derive(PartialEq, Debug, Clone)]
+pub struct PresenceEvent {
+    pub date_time: DateTime<Utc>,
+    pub signature: Signature,
+    pub change: PresenceChange,
+}
+
+/// Notices devices arriving and departing, from successive snapshots. A device
+/// has departed once it has gone unseen for longer than `max_absence`, and the
+/// departure is dated from when it was last seen.
+pub struct PresenceTracker {
+    max_absence: Duration,
+    present: HashSet<Signature>
+}
+
+impl PresenceTracker {
+    #[must_use] pub fn new(max_absence: Duration) -> PresenceTracker {
+        PresenceTracker { max_absence, present: HashSet::new() }
+    }
+
+    /// Changes since the last update, oldest first
+    pub fn update(&mut self, snapshot: &Snapshot, now: DateTime<Utc>) -> Vec<PresenceEvent> {
+        let mut changes = vec![];
+        for device in &snapshot.0 {
+            let here = now - device.date_time <= self.max_absence;
+            let change = if here && self.present.insert(device.signature.clone()) {
+                Some(PresenceChange::Arrived)
+            }
+            else if !here && self.present.remove(&device.signature) {
+                Some(PresenceChange::Departed)
+            }
+            else {
+                None
+            };
+            if let Some(change) = change {
+                changes.push(PresenceEvent { date_time: device.date_time, signature: device.signature.clone(), change });
+            }
+        }
+        changes.sort_by(|a, b| a.date_time.cmp(&b.date_time).then_with(|| a.signature.cmp(&b.signature)));
+        changes
+    }
+
+    #[must_use] pub fn is_present(&self, signature: &Signature) -> bool {
+        self.present.contains(signature)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Utc, TimeZone, Duration};
+
+    use crate::{discover::DiscoveryEvent, signature::Signature, gap::{ScanGap, GapReason}};
+
+    use crate::{snapshot::Snapshot, device_state::DeviceState};
+
+    use super::{sessions, SessionBuilder, PresenceTracker, PresenceEvent, PresenceChange};
+
+    fn sighting(seconds: i64, name: &str, rssi: i16) -> DiscoveryEvent {
+        DiscoveryEvent::new(Utc.timestamp_opt(seconds, 0).unwrap(), Signature::Named(name.to_string()), rssi)
+    }
+
+    #[test]
+    fn split_on_long_absence() {
+        let events = vec![
+            sighting(0, "1", -50), sighting(10, "1", -40), sighting(100, "1", -60), sighting(110, "1", -60),
+        ];
+        let actual = sessions(&events, &[], Duration::seconds(30));
+        let spans : Vec<(i64, i64, usize, i16)> = actual.iter()
+            .map(|s| (s.start.timestamp(), s.end.timestamp(), s.sightings, s.strongest_rssi))
+            .collect();
+        assert_eq!(spans, vec![(0, 10, 2, -40), (100, 110, 2, -60)]);
+    }
+
+    #[test]
+    fn gaps_do_not_count_as_absence() {
+        let events = vec![sighting(0, "1", -50), sighting(100, "1", -50)];
+        let gaps = vec![
+            ScanGap::new(Utc.timestamp_opt(5, 0).unwrap(), Utc.timestamp_opt(95, 0).unwrap(), GapReason::Stalled)
+        ];
+        assert_eq!(sessions(&events, &gaps, Duration::seconds(30)).len(), 1);
+        assert_eq!(sessions(&events, &[], Duration::seconds(30)).len(), 2);
+    }
+
+    #[test]
+    fn separate_sessions_per_device() {
+        let events = vec![sighting(0, "1", -50), sighting(1, "2", -50), sighting(2, "1", -50)];
+        let actual = sessions(&events, &[], Duration::seconds(30));
+        let names : Vec<String> = actual.iter().map(|s| s.signature.to_string().trim().to_string()).collect();
+        assert_eq!(names, vec!["1", "2"]);
+    }
+
+    #[test]
+    fn build_sessions_a_batch_at_a_time() {
+        let events = vec![
+            sighting(0, "1", -50), sighting(1, "2", -50), sighting(10, "1", -40),
+            sighting(50, "2", -50), sighting(100, "1", -60), sighting(110, "1", -60),
+        ];
+        let gaps = vec![
+            ScanGap::new(Utc.timestamp_opt(5, 0).unwrap(), Utc.timestamp_opt(45, 0).unwrap(), GapReason::Stalled)
+        ];
+        let mut builder = SessionBuilder::new(gaps.clone(), Duration::seconds(30));
+        for batch in events.chunks(4) {
+            builder.add(batch);
+        }
+        assert_eq!(builder.sessions(), sessions(&events, &gaps, Duration::seconds(30)));
+    }
+
+    #[test]
+    fn track_arrivals_and_departures() {
+        let seen_at = |seconds: i64, name: &str| DeviceState::new(Utc.timestamp_opt(seconds, 0).unwrap(), Signature::Named(name.to_string()), -10);
+        let event = |seconds: i64, name: &str, change: PresenceChange| PresenceEvent {
+            date_time: Utc.timestamp_opt(seconds, 0).unwrap(), signature: Signature::Named(name.to_string()), change
+        };
+        let mut tracker = PresenceTracker::new(Duration::seconds(60));
+
+        assert_eq!(tracker.update(&Snapshot(vec![seen_at(0, "1")]), Utc.timestamp_opt(0, 0).unwrap()),
+            vec![event(0, "1", PresenceChange::Arrived)]);
+        assert_eq!(tracker.update(&Snapshot(vec![seen_at(0, "1"), seen_at(30, "2")]), Utc.timestamp_opt(30, 0).unwrap()),
+            vec![event(30, "2", PresenceChange::Arrived)]);
+        assert!(tracker.is_present(&Signature::Named("1".to_string())));
+        assert_eq!(tracker.update(&Snapshot(vec![seen_at(0, "1"), seen_at(30, "2")]), Utc.timestamp_opt(61, 0).unwrap()),
+            vec![event(0, "1", PresenceChange::Departed)]);
+        assert_eq!(tracker.update(&Snapshot(vec![seen_at(70, "1"), seen_at(30, "2")]), Utc.timestamp_opt(70, 0).unwrap()),
+            vec![event(70, "1", PresenceChange::Arrived)]);
+    }
+}
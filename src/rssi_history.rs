@@ -0,0 +1,98 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{discover::DiscoveryEvent, signature::Signature};
+
+/// Recent RSSI readings for each device, going back `max_age` from the newest
+/// event seen, for showing how a device's signal has changed over time
+pub struct RssiHistory {
+    max_age: Duration,
+    readings: HashMap<Signature, VecDeque<(DateTime<Utc>, i16)>>
+}
+
+impl RssiHistory {
+    #[must_use] pub fn new(max_age: Duration) -> RssiHistory {
+        RssiHistory { max_age, readings: HashMap::new() }
+    }
+
+    pub fn record(&mut self, events: &[DiscoveryEvent]) {
+        for event in events {
+            self.readings.entry(event.signature.clone())
+                .or_default()
+                .push_back((event.date_time, event.rssi));
+        }
+        if let Some(newest) = events.iter().map(|e| e.date_time).max() {
+            let oldest = newest - self.max_age;
+            self.readings.retain(|_, readings| {
+                while readings.front().is_some_and(|(date_time, _)| *date_time < oldest) {
+                    readings.pop_front();
+                }
+                !readings.is_empty()
+            });
+        }
+    }
+
+    /// The RSSI for `signature` with readings weighted more the more recent
+    /// they are, to even out the jumps between individual readings
+    #[must_use] pub fn smoothed(&self, signature: &Signature) -> Option<f64> {
+        const WEIGHT : f64 = 0.3;
+        self.readings(signature)
+            .map(|(_, rssi)| f64::from(*rssi))
+            .reduce(|average, rssi| WEIGHT * rssi + (1.0 - WEIGHT) * average)
+    }
+
+    /// Readings for `signature`, oldest first
+    pub fn readings(&self, signature: &Signature) -> impl Iterator<Item = &(DateTime<Utc>, i16)> {
+        self.readings.get(signature).into_iter().flatten()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Utc, TimeZone, Duration};
+
+    use crate::{discover::DiscoveryEvent, signature::Signature};
+
+    use super::RssiHistory;
+
+    fn event(seconds: i64, name: &str, rssi: i16) -> DiscoveryEvent {
+        DiscoveryEvent::new(Utc.timestamp_opt(seconds, 0).unwrap(), Signature::Named(name.to_string()), rssi)
+    }
+
+    fn readings(history: &RssiHistory, name: &str) -> Vec<(i64, i16)> {
+        history.readings(&Signature::Named(name.to_string()))
+            .map(|(date_time, rssi)| (date_time.timestamp(), *rssi))
+            .collect()
+    }
+
+    #[test]
+    fn keep_readings_per_device() {
+        let mut history = RssiHistory::new(Duration::seconds(60));
+        history.record(&[event(0, "1", -50), event(0, "2", -70)]);
+        history.record(&[event(1, "1", -40)]);
+        assert_eq!(readings(&history, "1"), vec![(0, -50), (1, -40)]);
+        assert_eq!(readings(&history, "2"), vec![(0, -70)]);
+        assert_eq!(readings(&history, "3"), vec![]);
+    }
+
+    #[test]
+    fn smooth_readings() {
+        let mut history = RssiHistory::new(Duration::seconds(60));
+        assert_eq!(history.smoothed(&Signature::Named("1".to_string())), None);
+        history.record(&[event(0, "1", -50)]);
+        assert_eq!(history.smoothed(&Signature::Named("1".to_string())), Some(-50.0));
+        history.record(&[event(1, "1", -60)]);
+        assert!((history.smoothed(&Signature::Named("1".to_string())).unwrap() - -53.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn forget_old_readings() {
+        let mut history = RssiHistory::new(Duration::seconds(60));
+        history.record(&[event(0, "1", -50), event(0, "2", -70)]);
+        history.record(&[event(30, "1", -40)]);
+        history.record(&[event(90, "1", -30)]);
+        assert_eq!(readings(&history, "1"), vec![(30, -40), (90, -30)]);
+        assert_eq!(readings(&history, "2"), vec![]);
+    }
+}
@@ -1,3 +1,5 @@
+use std::{fs, path::Path};
+
 use btleplug::api::PeripheralProperties;
 use serde::{Serialize, Deserialize};
 
@@ -31,6 +33,37 @@ impl Signature {
     }
 }
 
+impl Signature {
+    #[must_use] pub fn kind(&self) -> &'static str {
+        use Signature::{Anonymous, Named};
+        match self {
+            Named(_) => "named",
+            Anonymous(_) => "anonymous"
+        }
+    }
+
+    #[must_use] pub fn value(&self) -> &str {
+        use Signature::{Anonymous, Named};
+        match self {
+            Named(n) | Anonymous(n) => n
+        }
+    }
+
+    /// The inverse of `kind()` and `value()`
+    #[must_use] pub fn from_kind_and_value(kind: &str, value: &str) -> Option<Signature> {
+        match kind {
+            "named" => Some(Signature::Named(value.to_string())),
+            "anonymous" => Some(Signature::Anonymous(value.to_string())),
+            _ => None
+        }
+    }
+
+    /// Whether this is the device with the given name, or anonymous digest
+    #[must_use] pub fn matches(&self, name_or_digest: &str) -> bool {
+        self.value() == name_or_digest
+    }
+}
+
 impl std::fmt::Display for Signature {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use Signature::{Anonymous, Named};
@@ -42,20 +75,119 @@ impl std::fmt::Display for Signature {
     }
 }
 
+/// What is MACed to identify an `HmacSha256` key
+const ID_LABEL : &[u8] = b"blescan signature policy id";
+
+/// Where `hmac` takes its key from, when not given a key file
+pub const SIGNATURE_KEY_VAR : &str = "BLESCAN_SIGNATURE_KEY";
+
+/// How anonymous signatures are derived from manufacturer data. Signatures
+/// from different policies (or different keys) never match each other.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub enum SignaturePolicy {
+    #[default]
+    Md5Legacy,
+    Xxh3,
+    /// HMAC-SHA256 truncated to 128 bits, so signatures can't be correlated
+    /// with those computed elsewhere without knowing the key
+    HmacSha256(Vec<u8>)
+}
+
+impl SignaturePolicy {
+    /// Reads the key for `HmacSha256` from a file, ignoring any trailing
+    /// newline, so that it doesn't have to be given on the command line
+    pub fn hmac_from_file<P: AsRef<Path>>(path: P) -> Result<SignaturePolicy, String> {
+        let path = path.as_ref();
+        let contents = fs::read(path).map_err(|e| format!("can't read signature key from {}: {e}", path.display()))?;
+        SignaturePolicy::hmac_from_key(contents.trim_ascii_end())
+            .ok_or(format!("signature key in {} is empty", path.display()))
+    }
+
+    fn hmac_from_key(key: &[u8]) -> Option<SignaturePolicy> {
+        (!key.is_empty()).then(|| SignaturePolicy::HmacSha256(key.to_vec()))
+    }
+
+    /// A description of the policy that is safe to record alongside history.
+    /// For `HmacSha256` this includes a MAC of a fixed label, rather than a
+    /// plain hash of the key, so that a weak key can't be recovered from it.
+    #[must_use] pub fn id(&self) -> String {
+        use SignaturePolicy::{Md5Legacy, Xxh3, HmacSha256};
+        match self {
+            Md5Legacy => "md5-legacy".to_string(),
+            Xxh3 => "xxh3".to_string(),
+            HmacSha256(_) => format!("hmac-sha256:{}", &self.digest(&[&ID_LABEL.to_vec()])[0..16])
+        }
+    }
+
+    fn digest(&self, chunks: &[&Vec<u8>]) -> String {
+        use SignaturePolicy::{Md5Legacy, Xxh3, HmacSha256};
+        match self {
+            Md5Legacy => {
+                let mut context = md5::Context::new();
+                for chunk in chunks {
+                    context.consume(chunk);
+                }
+                let digest = context.compute();
+                format!("{digest:x}")
+            },
+            Xxh3 => {
+                let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+                for chunk in chunks {
+                    hasher.update(chunk);
+                }
+                format!("{:016x}", hasher.digest())
+            },
+            HmacSha256(key) => {
+                use hmac::{Hmac, Mac};
+                let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key)
+                    .expect("HMAC can take a key of any size");
+                for chunk in chunks {
+                    mac.update(chunk);
+                }
+                let digest = mac.finalize().into_bytes();
+                to_hex(&digest[0..16])
+            }
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl std::str::FromStr for SignaturePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md5" | "md5-legacy" => Ok(SignaturePolicy::Md5Legacy),
+            "xxh3" => Ok(SignaturePolicy::Xxh3),
+            "hmac" => std::env::var(SIGNATURE_KEY_VAR).ok()
+                .and_then(|key| SignaturePolicy::hmac_from_key(key.as_bytes()))
+                .ok_or(format!("hmac needs a key in ${SIGNATURE_KEY_VAR}, or use hmac:<key-file>")),
+            _ => match s.strip_prefix("hmac:") {
+                Some(path) if !path.is_empty() => SignaturePolicy::hmac_from_file(path),
+                _ => Err(format!("unknown signature policy: {s} (expected md5, xxh3, hmac or hmac:<key-file>)"))
+            }
+        }
+    }
+}
+
 impl Signature {
     #[must_use] pub fn find(properties: &PeripheralProperties) -> Option<Signature> {
+        Signature::find_with_policy(properties, &SignaturePolicy::default())
+    }
+
+    #[must_use] pub fn find_with_policy(properties: &PeripheralProperties, policy: &SignaturePolicy) -> Option<Signature> {
         if let Some(local_name) = &properties.local_name {
             Some(Signature::Named(local_name.clone()))
         } else if !&properties.manufacturer_data.is_empty() {
-            let mut context = md5::Context::new();
             let mut manufacturer_ids: Vec<&u16> = properties.manufacturer_data.keys().collect();
             manufacturer_ids.sort();
-            for manufacturer_id in manufacturer_ids {
-                let arbitrary_data = properties.manufacturer_data[manufacturer_id].clone();
-                context.consume(arbitrary_data);
-            }
-            let digest = context.compute();
-            Some(Signature::Anonymous(format!("{digest:x}")))
+            let chunks : Vec<&Vec<u8>> = manufacturer_ids.into_iter()
+                .map(|manufacturer_id| &properties.manufacturer_data[manufacturer_id])
+                .collect();
+            Some(Signature::Anonymous(policy.digest(&chunks)))
         }
         else {
             None
@@ -63,3 +195,85 @@ impl Signature {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use btleplug::api::PeripheralProperties;
+
+    use super::{Signature, SignaturePolicy};
+
+    fn anonymous_properties() -> PeripheralProperties {
+        PeripheralProperties {
+            manufacturer_data: HashMap::from([(76, vec![1, 2, 3]), (6, vec![4, 5])]),
+            ..PeripheralProperties::default()
+        }
+    }
+
+    #[test]
+    fn named_regardless_of_policy() {
+        let properties = PeripheralProperties {
+            local_name: Some("Device 1".to_string()),
+            ..anonymous_properties()
+        };
+        for policy in [SignaturePolicy::Md5Legacy, SignaturePolicy::Xxh3, SignaturePolicy::HmacSha256(b"key".to_vec())] {
+            assert_eq!(
+                Signature::find_with_policy(&properties, &policy), 
+                Some(Signature::Named("Device 1".to_string())));
+        }
+    }
+
+    #[test]
+    fn md5_legacy_is_default() {
+        let properties = anonymous_properties();
+        let mut context = md5::Context::new();
+        context.consume([4, 5]);
+        context.consume([1, 2, 3]);
+        let expected = Signature::Anonymous(format!("{:x}", context.compute()));
+        assert_eq!(Signature::find(&properties), Some(expected));
+    }
+
+    #[test]
+    fn policies_produce_different_signatures() {
+        let properties = anonymous_properties();
+        let md5 = Signature::find_with_policy(&properties, &SignaturePolicy::Md5Legacy);
+        let xxh3 = Signature::find_with_policy(&properties, &SignaturePolicy::Xxh3);
+        let hmac_a = Signature::find_with_policy(&properties, &SignaturePolicy::HmacSha256(b"a".to_vec()));
+        let hmac_b = Signature::find_with_policy(&properties, &SignaturePolicy::HmacSha256(b"b".to_vec()));
+        assert_ne!(md5, xxh3);
+        assert_ne!(md5, hmac_a);
+        assert_ne!(hmac_a, hmac_b);
+        assert_eq!(hmac_a, Signature::find_with_policy(&properties, &SignaturePolicy::HmacSha256(b"a".to_vec())));
+    }
+
+    #[test]
+    fn policy_ids_do_not_reveal_keys() {
+        assert_eq!(SignaturePolicy::Md5Legacy.id(), "md5-legacy");
+        assert_eq!(SignaturePolicy::Xxh3.id(), "xxh3");
+        let id = SignaturePolicy::HmacSha256(b"secret".to_vec()).id();
+        assert!(id.starts_with("hmac-sha256:"));
+        assert!(!id.contains("secret"));
+        assert_eq!(id, SignaturePolicy::HmacSha256(b"secret".to_vec()).id());
+        assert_ne!(id, SignaturePolicy::HmacSha256(b"other".to_vec()).id());
+    }
+
+    #[test]
+    fn hmac_key_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("signature.key");
+        std::fs::write(&path, "abc\n").unwrap();
+        let policy = format!("hmac:{}", path.display()).parse::<SignaturePolicy>().unwrap();
+        assert_eq!(policy, SignaturePolicy::HmacSha256(b"abc".to_vec()));
+        std::fs::write(&path, "\n").unwrap();
+        assert!(SignaturePolicy::hmac_from_file(&path).is_err());
+    }
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!("md5".parse::<SignaturePolicy>().unwrap(), SignaturePolicy::Md5Legacy);
+        assert_eq!("xxh3".parse::<SignaturePolicy>().unwrap(), SignaturePolicy::Xxh3);
+        assert!("hmac:".parse::<SignaturePolicy>().is_err());
+        assert!("hmac:no-such-file".parse::<SignaturePolicy>().is_err());
+        assert!("sha1".parse::<SignaturePolicy>().is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, cmp::Ordering};
+use std::{collections::{HashMap, HashSet}, cmp::Ordering};
 
 use crate::{ signature::Signature, device_state::DeviceState};
 
@@ -66,6 +66,81 @@ impl Snapshot {
     }
 }
 
+/// How a device differs between two snapshots, taken at different times
+#[derive(PartialEq, Debug, Clone)]
+pub enum Difference {
+    /// not in the earlier snapshot
+    Added(DeviceState),
+    /// in the earlier snapshot, but not seen since
+    Removed(DeviceState),
+    /// seen since the earlier snapshot
+    Seen { before: DeviceState, after: DeviceState }
+}
+
+impl Difference {
+    fn order(&self) -> u8 {
+        match self {
+            Difference::Removed(_) => 0,
+            Difference::Added(_) => 1,
+            Difference::Seen { .. } => 2
+        }
+    }
+
+    #[must_use] pub fn signature(&self) -> &Signature {
+        match self {
+            Difference::Added(d) | Difference::Removed(d) | Difference::Seen { after: d, .. } => &d.signature
+        }
+    }
+}
+
+impl Snapshot {
+    /// How this differs from `before`, an earlier snapshot; removals first,
+    /// then additions, then the rest, each in signature order
+    #[must_use] pub fn diff(&self, before: &Snapshot) -> Vec<Difference> {
+        let before_by_signature: HashMap<&Signature, &DeviceState> = before.0.iter()
+            .map(|d| (&d.signature, d))
+            .collect();
+        let mut differences : Vec<Difference> = self.0.iter()
+            .map(|after| match before_by_signature.get(&after.signature) {
+                None => Difference::Added(after.clone()),
+                Some(before) if after.date_time <= before.date_time => Difference::Removed((*before).clone()),
+                Some(before) => Difference::Seen { before: (*before).clone(), after: after.clone() }
+            })
+            .collect();
+        let after_signatures : HashSet<&Signature> = self.0.iter().map(|d| &d.signature).collect();
+        differences.extend(before.0.iter()
+            .filter(|d| !after_signatures.contains(&d.signature))
+            .map(|d| Difference::Removed(d.clone())));
+        differences.sort_by(|a, b| a.order().cmp(&b.order()).then_with(|| a.signature().cmp(b.signature())));
+        differences
+    }
+}
+
+/// Which devices to keep; by default, all of them
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct DeviceFilter {
+    pub min_rssi: Option<i16>,
+    /// `Some(true)` for just named devices, `Some(false)` for just anonymous ones
+    pub named: Option<bool>,
+    /// part of the name, or anonymous digest, ignoring case
+    pub substring: Option<String>
+}
+
+impl DeviceFilter {
+    #[must_use] pub fn matches(&self, signature: &Signature, rssi: i16) -> bool {
+        self.min_rssi.is_none_or(|min_rssi| rssi >= min_rssi)
+            && self.named.is_none_or(|named| named == matches!(signature, Signature::Named(_)))
+            && self.substring.as_ref().is_none_or(|substring| 
+                signature.value().to_lowercase().contains(&substring.to_lowercase()))
+    }
+}
+
+impl Snapshot {
+    #[must_use] pub fn filter(&self, filter: &DeviceFilter) -> Snapshot {
+        Snapshot(self.0.iter().filter(|d| filter.matches(&d.signature, d.rssi)).cloned().collect())
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub struct Comparison {
     pub relative_age: chrono::Duration,
@@ -84,7 +159,7 @@ pub enum RssiComparison {
 mod test {
     use chrono::{Utc, TimeZone, Duration};
 
-    use crate::{device_state::DeviceState, signature::Signature, snapshot::{Comparison, RssiComparison}};
+    use crate::{device_state::DeviceState, signature::Signature, snapshot::{Comparison, RssiComparison, Difference, DeviceFilter}};
 
     use super::Snapshot;
 
@@ -202,4 +277,36 @@ mod test {
         assert_eq!(just_rssi(&actual_comparisons), just_rssi(&expected_comparisons));
         assert_eq!(actual_comparisons, expected_comparisons);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn differences() {
+        let device = |seconds: i64, name: &str, rssi: i16| DeviceState::new(Utc.timestamp_opt(seconds, 0).unwrap(), Signature::Named(name.to_string()), rssi);
+        let before = Snapshot(vec![device(1, "1", -10), device(1, "2", -10), device(1, "3", -10)]);
+        let after = Snapshot(vec![device(5, "1", -20), device(1, "2", -10), device(5, "4", -30)]);
+        assert_eq!(after.diff(&before), vec![
+            Difference::Removed(device(1, "2", -10)),
+            Difference::Removed(device(1, "3", -10)),
+            Difference::Added(device(5, "4", -30)),
+            Difference::Seen { before: device(1, "1", -10), after: device(5, "1", -20) },
+        ]);
+    }
+
+    #[test]
+    fn filtering() {
+        let device = |signature: Signature, rssi: i16| DeviceState::new(Utc.timestamp_opt(1, 0).unwrap(), signature, rssi);
+        let snapshot = Snapshot(vec![
+            device(Signature::Named("My Keys".to_string()), -40),
+            device(Signature::Named("Headphones".to_string()), -80),
+            device(Signature::Anonymous("abc123".to_string()), -50),
+        ]);
+        let names = |filter: DeviceFilter| snapshot.filter(&filter).0.iter()
+            .map(|d| d.signature.value().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names(DeviceFilter::default()), vec!["My Keys", "Headphones", "abc123"]);
+        assert_eq!(names(DeviceFilter { min_rssi: Some(-50), ..DeviceFilter::default() }), vec!["My Keys", "abc123"]);
+        assert_eq!(names(DeviceFilter { named: Some(true), ..DeviceFilter::default() }), vec!["My Keys", "Headphones"]);
+        assert_eq!(names(DeviceFilter { named: Some(false), ..DeviceFilter::default() }), vec!["abc123"]);
+        assert_eq!(names(DeviceFilter { substring: Some("keys".to_string()), min_rssi: Some(-30), ..DeviceFilter::default() }), Vec::<String>::new());
+        assert_eq!(names(DeviceFilter { substring: Some("keys".to_string()), ..DeviceFilter::default() }), vec!["My Keys"]);
+    }
+}
@@ -38,6 +38,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn initial_discovery() {
         let mut state = State::default();
         let start = Utc.timestamp_opt(0, 0).unwrap();
@@ -50,6 +51,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn updated_state() {
         let mut state = State::default();
         let start = Utc.timestamp_opt(0, 0).unwrap();
@@ -0,0 +1,85 @@
+use std::{fs, path::Path};
+
+use crate::{error::Error, presence::PresenceEvent, signature::Signature};
+
+/// Devices of particular interest, by name or anonymous digest
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Watchlist(Vec<String>);
+
+impl Watchlist {
+    /// Reads one name or digest per line, ignoring blank lines and `#` comments
+    pub fn from_file(path: &Path) -> Result<Watchlist, Error> {
+        let entries = fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        Ok(Watchlist(entries))
+    }
+
+    #[must_use] pub fn contains(&self, signature: &Signature) -> bool {
+        self.0.iter().any(|entry| signature.matches(entry))
+    }
+
+    /// Starts watching `signature`, or stops if it was already being watched
+    pub fn toggle(&mut self, signature: &Signature) {
+        if self.contains(signature) {
+            self.0.retain(|entry| !signature.matches(entry));
+        }
+        else {
+            self.0.push(signature.value().to_string());
+        }
+    }
+
+    /// Just the arrivals and departures of watched devices
+    pub fn filter<'a>(&'a self, events: &'a [PresenceEvent]) -> impl Iterator<Item = &'a PresenceEvent> {
+        events.iter().filter(|e| self.contains(&e.signature))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use chrono::{Utc, TimeZone};
+    use tempfile::NamedTempFile;
+
+    use crate::{presence::{PresenceEvent, PresenceChange}, signature::Signature};
+
+    use super::Watchlist;
+
+    #[test]
+    fn read_from_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "# my devices\nDevice 1\n\n  abcd  ").unwrap();
+        let watchlist = Watchlist::from_file(file.path()).unwrap();
+        assert!(watchlist.contains(&Signature::Named("Device 1".to_string())));
+        assert!(watchlist.contains(&Signature::Anonymous("abcd".to_string())));
+        assert!(!watchlist.contains(&Signature::Named("Device 2".to_string())));
+    }
+
+    #[test]
+    fn toggle() {
+        let device = Signature::Named("Device 1".to_string());
+        let mut watchlist = Watchlist::default();
+        watchlist.toggle(&device);
+        assert!(watchlist.contains(&device));
+        watchlist.toggle(&device);
+        assert!(!watchlist.contains(&device));
+    }
+
+    #[test]
+    fn filter_presence_events() {
+        let event = |name: &str| PresenceEvent {
+            date_time: Utc.timestamp_opt(0, 0).unwrap(),
+            signature: Signature::Named(name.to_string()),
+            change: PresenceChange::Arrived
+        };
+        let mut watchlist = Watchlist::default();
+        watchlist.toggle(&Signature::Named("Device 1".to_string()));
+        let events = vec![event("Device 1"), event("Device 2")];
+        let watched : Vec<&PresenceEvent> = watchlist.filter(&events).collect();
+        assert_eq!(watched, vec![&events[0]]);
+    }
+}